@@ -1,41 +1,636 @@
+use crate::bigint::BigInt;
+use crate::class::{Class, Instance, Method, Super};
+use crate::dict::Dict;
+use crate::error::{Frame, Span};
+use crate::generator::Generator;
+use crate::iter::Iter;
+use crate::scope::Scope;
+use crate::set::Set;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
 #[derive(Debug)]
 pub enum Expr {
     Number(i64),
+    BigNumber(Rc<BigInt>), // an integer literal too large for i64
     Float(f64),
     String(String), // new variant for string literals
-    Variable(String),
-    Assignment(String, Box<Expr>),
+    Bytes(Rc<Vec<u8>>),
+    // An f-string: literal text (as String parts) and FormattedValue parts.
+    JoinedString(Vec<Expr>),
+    // A replacement field of an f-string, `{value!conversion:spec}`; the spec
+    // is itself a JoinedString since it may hold fields.
+    FormattedValue {
+        value: Box<Expr>,
+        conversion: Option<char>,
+        spec: Option<Box<Expr>>,
+        span: Span,
+    },
+    Bool(bool),
+    None,
+    Variable(String, Span),
+    List(Vec<Expr>),
+    Tuple(Vec<Expr>),
+    // `*value` inside a tuple or list display, or a starred assignment target.
+    Starred(Box<Expr>, Span),
+    Dict(Vec<(Expr, Expr)>, Span),
+    Set(Vec<Expr>, Span),
+    // `target = value`, where the target is a name, subscript or attribute.
+    Assignment(Box<Expr>, Box<Expr>, Span),
+    // `target op= value`; the target is evaluated once and updated in place
+    // when its type allows (e.g. `items += more` extends the list).
+    AugmentedAssignment {
+        target: Box<Expr>,
+        operator: String, // including the '=', e.g. "+="
+        value: Box<Expr>,
+        span: Span,
+    },
+    Subscript {
+        value: Box<Expr>,
+        index: Box<Expr>,
+        span: Span,
+    },
+    // The `lower:upper:step` inside a subscript; omitted parts are None.
+    Slice {
+        lower: Option<Box<Expr>>,
+        upper: Option<Box<Expr>>,
+        step: Option<Box<Expr>>,
+    },
+    Attribute {
+        value: Box<Expr>,
+        name: String,
+        span: Span,
+    },
     Arithmetic {
         left: Box<Expr>,
         operator: String,
         right: Box<Expr>,
+        span: Span,
+    },
+    // `-x`, `+x` or `~x`.
+    UnaryOp {
+        operator: String,
+        operand: Box<Expr>,
+        span: Span,
+    },
+    // A comparison chain such as `a < b <= c`, evaluated pairwise.
+    Compare {
+        left: Box<Expr>,
+        operators: Vec<String>,
+        comparators: Vec<Expr>,
+        span: Span,
+    },
+    // `and` / `or`, which short-circuit and produce one of their operands.
+    Logical {
+        left: Box<Expr>,
+        operator: String,
+        right: Box<Expr>,
+        span: Span,
     },
+    Not(Box<Expr>, Span),
     FunctionDef {
         name: String,
+        qualname: String, // the dotted path through enclosing classes and functions
         params: Vec<String>,
+        body: Rc<Vec<Expr>>, // shared with the function values created from it
+        is_generator: bool,  // whether the body yields
+        scope: Rc<Scope>,
+    },
+    ClassDef {
+        name: String,
+        qualname: String,
+        bases: Vec<Expr>,
         body: Vec<Expr>,
+        scope: Rc<Scope>,
+        span: Span,
+    },
+    Call {
+        function: Box<Expr>,
+        args: Vec<Expr>,
+        keywords: Vec<(String, Expr)>, // `name=value` arguments, in source order
+        span: Span,
     },
     IfCondition {
         condition: Box<Expr>,
         body: Vec<Expr>,
+        orelse: Vec<Expr>, // the `else` block; an `elif` is a nested IfCondition here
+        span: Span,        // of the condition
     },
     ForLoop {
-        iterator: String,
-        range_start: Box<Expr>,
-        range_end: Box<Expr>,
+        target: Box<Expr>, // assigned each item, so it may unpack: `for i, x in ...`
+        iterable: Box<Expr>,
         body: Vec<Expr>,
+        orelse: Vec<Expr>, // the `else` block, run unless the loop ends with `break`
+        span: Span,        // of the iterable
     },
     WhileLoop {
         condition: Box<Expr>,
         body: Vec<Expr>,
+        orelse: Vec<Expr>,
+        span: Span,
+    },
+    Print(Vec<Expr>, Span), // now holds a list of expressions
+    Return(Option<Box<Expr>>, Span),
+    Break,
+    Continue,
+    Import(String, Span),
+    Delete(Vec<Expr>, Span), // `del` targets: names, subscripts or attributes
+    Try {
+        body: Vec<Expr>,
+        handlers: Vec<ExceptHandler>,
+        orelse: Vec<Expr>,    // run when the body finishes without an exception
+        finalbody: Vec<Expr>, // run however the rest finishes
     },
-    Print(Vec<Expr>), // now holds a list of expressions
-    Return(Box<Expr>),
+    // `raise`, `raise exc` or `raise exc from cause`; a bare `raise` re-raises
+    // the exception being handled.
+    Raise {
+        exception: Option<Box<Expr>>,
+        cause: Option<Box<Expr>>,
+        span: Span,
+    },
+    // `yield` or `yield value`, and `yield from iterable`. Once a generator's
+    // body is parsed, these only appear as a statement or as the value of an
+    // assignment, where a suspended generator can resume (see lower.rs).
+    Yield(Option<Box<Expr>>, Span),
+    YieldFrom(Box<Expr>, Span),
+    // `(element for target in iterable if condition ...)`: the body of an
+    // anonymous generator function, whose loops yield the element. The first
+    // iterable is evaluated where the expression is; the body iterates it as `.0`.
+    GeneratorExp {
+        qualname: String,
+        body: Rc<Vec<Expr>>,
+        iterable: Box<Expr>,
+        scope: Rc<Scope>,
+        span: Span,
+    },
+    // `lambda params: value`, a function whose body returns the value.
+    Lambda {
+        qualname: String,
+        params: Vec<String>,
+        body: Rc<Vec<Expr>>,
+        is_generator: bool, // whether the value yields
+        scope: Rc<Scope>,
+    },
+    // `global names` and `nonlocal names`, which only affect how the parser
+    // resolves the names.
+    Global(Vec<String>, Span),
+    Nonlocal(Vec<String>, Span),
+    Pass,
+    Bare(Box<Expr>, Span), // a bare expression on its own line
+}
+
+// An `except` clause: the exception types it handles (all of them when
+// absent) and the name the exception is bound to.
+#[derive(Debug)]
+pub struct ExceptHandler {
+    pub types: Option<Expr>,
+    pub name: Option<String>,
+    pub body: Vec<Expr>,
+    pub span: Span,
 }
 
 // A common Value type used during evaluation.
 #[derive(Debug, Clone)]
 pub enum Value {
-    Number(f64),
+    Int(i64),
+    // Ints that don't fit in i64; arithmetic normalizes results back to Int
+    // whenever they fit, so a BigInt is never equal to any Int.
+    BigInt(Rc<BigInt>),
+    Float(f64),
     Str(String),
+    Bytes(Rc<Vec<u8>>),
+    Bool(bool),
+    None,
+    // Returned by special methods that don't handle the other operand.
+    NotImplemented,
+    // Lists are shared, so every name bound to one sees its mutations.
+    List(Rc<RefCell<Vec<Value>>>),
+    Tuple(Rc<Vec<Value>>),
+    Dict(Rc<RefCell<Dict>>),
+    // A live view of a dict's keys, values or items, as returned by `d.keys()`.
+    DictView(Rc<RefCell<Dict>>, ViewKind),
+    Set(Rc<RefCell<Set>>),
+    FrozenSet(Rc<Set>),
+    Slice(Rc<Slice>),
+    Range(Rc<Range>),
+    Iterator(Rc<RefCell<Iter>>),
+    Function(Rc<Function>),
+    Builtin(Rc<Builtin>),
+    Module(Rc<Module>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    Method(Rc<Method>),
+    Super(Rc<Super>),
+    Generator(Rc<Generator>),
+    // The frames an exception passed through, outermost first.
+    Traceback(Rc<Vec<Frame>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewKind {
+    Keys,
+    Values,
+    Items,
+}
+
+// A user-defined function created by executing a `def` statement.
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub qualname: String,
+    pub params: Vec<String>,
+    pub body: Rc<Vec<Expr>>,
+    // The class whose body defined the function, set once that class exists.
+    pub class: RefCell<Weak<Class>>,
+    // Calling a generator function creates a generator rather than running the body.
+    pub is_generator: bool,
+    pub scope: Rc<Scope>,
+    // The variables of enclosing functions that the body uses, shared with them.
+    pub closure: HashMap<String, Cell>,
+}
+
+// A variable shared between a function and the functions nested in it; empty
+// while the variable is unbound.
+pub type Cell = Rc<RefCell<Option<Value>>>;
+
+// A function implemented by the interpreter. Methods carry the object they
+// were looked up on, e.g. `items.append`.
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: String,
+    pub receiver: Option<Value>,
+}
+
+// The slice object produced by `lower:upper:step`; missing bounds are None.
+#[derive(Debug)]
+pub struct Slice {
+    pub lower: Value,
+    pub upper: Value,
+    pub step: Value,
+}
+
+// A `range(start, stop, step)`; its items are computed, never stored.
+#[derive(Debug)]
+pub struct Range {
+    pub start: i64,
+    pub stop: i64,
+    pub step: i64,
+}
+
+impl Range {
+    pub fn len(&self) -> usize {
+        let (start, stop, step) = (self.start as i128, self.stop as i128, self.step as i128);
+        let len = if step > 0 && start < stop {
+            (stop - start - 1) / step + 1
+        } else if step < 0 && start > stop {
+            (start - stop - 1) / -step + 1
+        } else {
+            0
+        };
+        len as usize
+    }
+
+    // The item at a position known to be in range.
+    pub fn get(&self, position: usize) -> i64 {
+        self.start + position as i64 * self.step
+    }
+}
+
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    pub attributes: HashMap<String, Value>,
+}
+
+impl Value {
+    // The Python-facing type name, used in error messages.
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::Bytes(_) => "bytes",
+            Value::Bool(_) => "bool",
+            Value::None => "NoneType",
+            Value::NotImplemented => "NotImplementedType",
+            Value::List(_) => "list",
+            Value::Tuple(_) => "tuple",
+            Value::Dict(_) => "dict",
+            Value::DictView(_, ViewKind::Keys) => "dict_keys",
+            Value::DictView(_, ViewKind::Values) => "dict_values",
+            Value::DictView(_, ViewKind::Items) => "dict_items",
+            Value::Set(_) => "set",
+            Value::FrozenSet(_) => "frozenset",
+            Value::Slice(_) => "slice",
+            Value::Range(_) => "range",
+            Value::Iterator(iter) => iter.borrow().type_name(),
+            Value::Function(_) => "function",
+            Value::Builtin(_) => "builtin_function_or_method",
+            Value::Module(_) => "module",
+            Value::Class(_) => "type",
+            Value::Instance(instance) => &instance.class.name,
+            Value::Method(_) => "method",
+            Value::Super(_) => "super",
+            Value::Generator(_) => "generator",
+            Value::Traceback(_) => "traceback",
+        }
+    }
+
+    // Python truthiness: zero, empty strings and containers, False and None are false.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Int(n) => *n != 0,
+            Value::BigInt(_) => true,
+            Value::Float(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::Bytes(bytes) => !bytes.is_empty(),
+            Value::Bool(b) => *b,
+            Value::None => false,
+            Value::NotImplemented => true,
+            Value::List(items) => !items.borrow().is_empty(),
+            Value::Tuple(items) => !items.is_empty(),
+            Value::Dict(dict) | Value::DictView(dict, _) => !dict.borrow().is_empty(),
+            Value::Set(set) => !set.borrow().is_empty(),
+            Value::FrozenSet(set) => !set.is_empty(),
+            Value::Range(range) => range.len() > 0,
+            Value::Slice(_) | Value::Iterator(_) | Value::Function(_) | Value::Builtin(_) | Value::Module(_) => true,
+            Value::Class(_) | Value::Instance(_) | Value::Method(_) | Value::Super(_) => true,
+            Value::Generator(_) | Value::Traceback(_) => true,
+        }
+    }
+
+    // Wraps an arbitrary-precision result, using the inline form when it fits.
+    pub fn from_bigint(value: BigInt) -> Value {
+        match value.to_i64() {
+            Some(n) => Value::Int(n),
+            None => Value::BigInt(Rc::new(value)),
+        }
+    }
+
+    // The integer value of ints and bools (bool is an int subtype in Python);
+    // None for non-integers and for ints too large for i64.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            Value::Bool(b) => Some(*b as i64),
+            _ => None,
+        }
+    }
+
+    // Any integer (including bools) at arbitrary precision.
+    pub fn as_bigint(&self) -> Option<BigInt> {
+        match self {
+            Value::BigInt(n) => Some((**n).clone()),
+            _ => self.as_int().map(BigInt::from_i64),
+        }
+    }
+
+    // Any numeric value widened to a float, as in mixed int/float arithmetic.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(n) => Some(*n),
+            Value::BigInt(n) => Some(n.to_f64()),
+            _ => self.as_int().map(|n| n as f64),
+        }
+    }
+
+    pub fn is_int(&self) -> bool {
+        matches!(self, Value::Int(_) | Value::BigInt(_) | Value::Bool(_))
+    }
+
+    pub fn new_list(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn new_tuple(items: Vec<Value>) -> Value {
+        Value::Tuple(Rc::new(items))
+    }
+
+    pub fn new_dict(dict: Dict) -> Value {
+        Value::Dict(Rc::new(RefCell::new(dict)))
+    }
+
+    pub fn new_set(set: Set) -> Value {
+        Value::Set(Rc::new(RefCell::new(set)))
+    }
+
+    // The representation echoed by the REPL, e.g. strings keep their quotes.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Str(s) => quote_text(s.chars(), false),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl Value {
+    // The repr of a container, with `item_repr` giving the repr of each item
+    // (the evaluator supplies one that runs `__repr__` methods); any other
+    // value gives its plain repr. Items are copied out first, since a
+    // `__repr__` may change the container.
+    pub fn repr_with<E>(&self, item_repr: &mut dyn FnMut(&Value) -> Result<String, E>) -> Result<String, E> {
+        let mut reprs = |items: &[Value]| items.iter().map(&mut *item_repr).collect::<Result<Vec<_>, E>>();
+        Ok(match self {
+            Value::List(list) => {
+                let items = list.borrow().clone();
+                match guard_repr(Rc::as_ptr(list) as usize, || reprs(&items)) {
+                    Some(parts) => format!("[{}]", parts?.join(", ")),
+                    None => "[...]".to_string(),
+                }
+            }
+            Value::Tuple(items) => match reprs(items)?.as_slice() {
+                [only] => format!("({},)", only),
+                parts => format!("({})", parts.join(", ")),
+            },
+            Value::Dict(dict) => {
+                let entries: Vec<Value> =
+                    dict.borrow().iter().flat_map(|(key, value)| [key.clone(), value.clone()]).collect();
+                match guard_repr(Rc::as_ptr(dict) as usize, || reprs(&entries)) {
+                    Some(parts) => {
+                        let parts = parts?;
+                        let pairs: Vec<String> = parts.chunks(2).map(|pair| format!("{}: {}", pair[0], pair[1])).collect();
+                        format!("{{{}}}", pairs.join(", "))
+                    }
+                    None => "{...}".to_string(),
+                }
+            }
+            Value::DictView(dict, kind) => {
+                let items = match kind {
+                    ViewKind::Keys => dict.borrow().keys(),
+                    ViewKind::Values => dict.borrow().values(),
+                    ViewKind::Items => dict.borrow().items(),
+                };
+                format!("{}({})", self.type_name(), Value::new_list(items).repr_with(item_repr)?)
+            }
+            Value::Set(set) => match reprs(&set.borrow().items())?.as_slice() {
+                [] => "set()".to_string(),
+                parts => format!("{{{}}}", parts.join(", ")),
+            },
+            Value::FrozenSet(set) => match reprs(&set.items())?.as_slice() {
+                [] => "frozenset()".to_string(),
+                parts => format!("frozenset({{{}}})", parts.join(", ")),
+            },
+            other => other.repr(),
+        })
+    }
+}
+
+// Quotes the text of a str or bytes repr, preferring single quotes and
+// escaping what isn't printable; bytes beyond ASCII are always escaped.
+fn quote_text(text: impl Iterator<Item = char> + Clone, bytes: bool) -> String {
+    let quote = if text.clone().any(|c| c == '\'') && !text.clone().any(|c| c == '"') { '"' } else { '\'' };
+    let mut out = String::new();
+    out.push(quote);
+    for c in text {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\0'..='\x1f' | '\x7f'..='\u{a0}' | '\u{ad}' => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c if bytes && !c.is_ascii() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            '\u{2028}' | '\u{2029}' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push(quote);
+    out
+}
+
+// The str() form of a value, as written by print.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::BigInt(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", format_float(*n)),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bytes(bytes) => write!(f, "b{}", quote_text(bytes.iter().map(|&b| b as char), true)),
+            Value::Bool(true) => write!(f, "True"),
+            Value::Bool(false) => write!(f, "False"),
+            Value::None => write!(f, "None"),
+            Value::NotImplemented => write!(f, "NotImplemented"),
+            Value::List(_)
+            | Value::Tuple(_)
+            | Value::Dict(_)
+            | Value::DictView(..)
+            | Value::Set(_)
+            | Value::FrozenSet(_) => {
+                let text: Result<String, fmt::Error> = self.repr_with(&mut |item| Ok(item.repr()));
+                write!(f, "{}", text?)
+            }
+            Value::Slice(slice) => write!(f, "slice({}, {}, {})", slice.lower.repr(), slice.upper.repr(), slice.step.repr()),
+            Value::Range(range) if range.step == 1 => write!(f, "range({}, {})", range.start, range.stop),
+            Value::Range(range) => write!(f, "range({}, {}, {})", range.start, range.stop, range.step),
+            Value::Iterator(iter) => write!(f, "<{} object at {:p}>", iter.borrow().type_name(), Rc::as_ptr(iter)),
+            Value::Function(func) => write!(f, "<function {} at {:p}>", func.qualname, Rc::as_ptr(func)),
+            Value::Builtin(builtin) => match &builtin.receiver {
+                Some(receiver) if builtin.name == "__init__" => {
+                    write!(f, "<method-wrapper '__init__' of {} object at {:p}>", receiver.type_name(), Rc::as_ptr(builtin))
+                }
+                None if builtin.name == "__init__" => write!(f, "<slot wrapper '__init__' of 'object' objects>"),
+                Some(receiver) => write!(
+                    f,
+                    "<built-in method {} of {} object at {:p}>",
+                    builtin.name,
+                    receiver.type_name(),
+                    Rc::as_ptr(builtin)
+                ),
+                None => write!(f, "<built-in function {}>", builtin.name),
+            },
+            Value::Module(module) => write!(f, "<module '{}' (built-in)>", module.name),
+            Value::Class(class) => write!(f, "<class '{}'>", qualified_name(class)),
+            Value::Instance(instance) => {
+                write!(f, "<{} object at {:p}>", qualified_name(&instance.class), Rc::as_ptr(instance))
+            }
+            Value::Method(method) => {
+                write!(f, "<bound method {} of {}>", method.function.qualname, method.receiver.repr())
+            }
+            Value::Super(proxy) => {
+                write!(f, "<super: <class '{}'>, <{} object>>", proxy.class.name, proxy.object_class().name)
+            }
+            Value::Generator(generator) => {
+                write!(f, "<generator object {} at {:p}>", generator.function.qualname, Rc::as_ptr(generator))
+            }
+            Value::Traceback(frames) => write!(f, "<traceback object at {:p}>", Rc::as_ptr(frames)),
+        }
+    }
+}
+
+// A class's name as its repr shows it: built-in classes omit their module.
+fn qualified_name(class: &Class) -> String {
+    match class.module {
+        "builtins" => class.qualname.clone(),
+        module => format!("{}.{}", module, class.qualname),
+    }
+}
+
+thread_local! {
+    // The containers currently being formatted, innermost last.
+    static REPR_ACTIVE: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+// Formats the items of a container unless it is already being formatted
+// further out, i.e. it contains itself; None tells the caller to print `...`.
+fn guard_repr<T>(address: usize, format_items: impl FnOnce() -> T) -> Option<T> {
+    if REPR_ACTIVE.with(|active| active.borrow().contains(&address)) {
+        return None;
+    }
+    REPR_ACTIVE.with(|active| active.borrow_mut().push(address));
+    let parts = format_items();
+    REPR_ACTIVE.with(|active| active.borrow_mut().pop());
+    Some(parts)
+}
+
+// Formats a float the way Python's repr() does: the shortest digits that
+// round-trip, in positional notation for exponents from -4 up to 16 and in
+// scientific notation (with a signed, two-digit exponent) otherwise.
+pub fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    if value == 0.0 {
+        return if value.is_sign_negative() { "-0.0" } else { "0.0" }.to_string();
+    }
+    // `{:e}` yields the shortest round-trip digits, e.g. "-1.2345e-7". When
+    // several digit strings of that length round-trip, it may not pick the
+    // one closest to the value as CPython does; rounding to that many digits
+    // does, if the result still round-trips.
+    let mut scientific = format!("{:e}", value);
+    let precision = scientific.split_once('e').unwrap().0.chars().filter(char::is_ascii_digit).count() - 1;
+    let closest = format!("{:.*e}", precision, value);
+    if closest.parse() == Ok(value) {
+        scientific = closest;
+    }
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", mantissa),
+    };
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+
+    if (-4..16).contains(&exponent) {
+        let point = exponent + 1; // digits before the decimal point
+        let body = if point <= 0 {
+            format!("0.{}{}", "0".repeat((-point) as usize), digits)
+        } else if point as usize >= digits.len() {
+            format!("{}{}.0", digits, "0".repeat(point as usize - digits.len()))
+        } else {
+            format!("{}.{}", &digits[..point as usize], &digits[point as usize..])
+        };
+        format!("{}{}", sign, body)
+    } else {
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}{}e{}{:02}", sign, mantissa, exponent_sign, exponent.abs())
+    }
 }
@@ -0,0 +1,579 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// An arbitrary-precision signed integer: a sign plus a little-endian magnitude
+// in base 2^32. The magnitude never has trailing zero limbs, and zero is the
+// empty magnitude with a positive sign, so derived equality is value equality.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+const BASE: u64 = 1 << 32;
+// Largest power of ten that fits in a limb, used for decimal conversion.
+const DECIMAL_CHUNK: u32 = 1_000_000_000;
+const DECIMAL_CHUNK_DIGITS: usize = 9;
+
+impl BigInt {
+    fn from_parts(negative: bool, mut magnitude: Vec<u32>) -> Self {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        let negative = negative && !magnitude.is_empty();
+        Self { negative, magnitude }
+    }
+
+    pub fn from_i64(value: i64) -> Self {
+        let abs = value.unsigned_abs();
+        Self::from_parts(value < 0, vec![abs as u32, (abs >> 32) as u32])
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let abs = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| (acc << 32) | limb as u64);
+        if self.negative {
+            if abs <= i64::MAX as u64 + 1 {
+                Some((abs as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else {
+            i64::try_from(abs).ok()
+        }
+    }
+
+    // The integer part of a finite float, exactly.
+    pub fn from_f64(value: f64) -> Self {
+        let bits = value.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = bits & ((1 << 52) - 1);
+        // The value is mantissa * 2^(exponent - 1075); subnormals have no implicit bit.
+        let mantissa = if exponent == 0 { fraction << 1 } else { fraction | 1 << 52 };
+        let magnitude = Self::from_i64(mantissa as i64);
+        let magnitude = match exponent - 1075 {
+            shift if shift >= 0 => magnitude.shl(shift as u64),
+            shift => magnitude.shr(shift.unsigned_abs()),
+        };
+        if value < 0.0 {
+            magnitude.neg()
+        } else {
+            magnitude
+        }
+    }
+
+    // The nearest float; infinite when the value is beyond f64's range.
+    pub fn to_f64(&self) -> f64 {
+        // Going through the decimal form gives correct rounding for free.
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    // Parses an optionally signed string of decimal digits.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut magnitude = Vec::new();
+        let head = digits.len() % DECIMAL_CHUNK_DIGITS;
+        let mut chunks = vec![&digits[..head]];
+        chunks.extend(digits.as_bytes()[head..].chunks(DECIMAL_CHUNK_DIGITS).map(|c| std::str::from_utf8(c).unwrap()));
+        for chunk in chunks.into_iter().filter(|c| !c.is_empty()) {
+            let scale = 10u32.pow(chunk.len() as u32);
+            mul_small_add(&mut magnitude, scale, chunk.parse().unwrap());
+        }
+        Some(Self::from_parts(negative, magnitude))
+    }
+
+    // The absolute value modulo `modulus` (which must be nonzero).
+    pub fn magnitude_rem(&self, modulus: u64) -> u64 {
+        self.magnitude
+            .iter()
+            .rev()
+            .fold(0u128, |acc, &limb| ((acc << 32) | limb as u128) % modulus as u128) as u64
+    }
+
+    // The digits of the magnitude in the given radix (up to 36), lowercase
+    // and without a sign.
+    pub fn magnitude_digits(&self, radix: u32) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+        let mut digits = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            digits.push(std::char::from_digit(div_small(&mut magnitude, radix), radix).unwrap());
+        }
+        digits.iter().rev().collect()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::from_parts(!self.negative, self.magnitude.clone())
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        if self.negative == other.negative {
+            return Self::from_parts(self.negative, add_magnitudes(&self.magnitude, &other.magnitude));
+        }
+        // Opposite signs: subtract the smaller magnitude from the larger.
+        match compare_magnitudes(&self.magnitude, &other.magnitude) {
+            Ordering::Less => Self::from_parts(other.negative, sub_magnitudes(&other.magnitude, &self.magnitude)),
+            _ => Self::from_parts(self.negative, sub_magnitudes(&self.magnitude, &other.magnitude)),
+        }
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Self::from_parts(self.negative != other.negative, mul_magnitudes(&self.magnitude, &other.magnitude))
+    }
+
+    // Floor division and modulo together, with Python's sign rules: the
+    // quotient rounds toward negative infinity and the remainder takes the
+    // divisor's sign. None when dividing by zero.
+    pub fn div_mod_floor(&self, other: &Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem_magnitudes(&self.magnitude, &other.magnitude);
+        let mut quotient = Self::from_parts(self.negative != other.negative, quotient);
+        let mut remainder = Self::from_parts(self.negative, remainder);
+        if !remainder.is_zero() && remainder.negative != other.negative {
+            quotient = quotient.sub(&Self::from_i64(1));
+            remainder = remainder.add(other);
+        }
+        Some((quotient, remainder))
+    }
+
+    // True division, rounded once from the exact quotient as CPython does:
+    // the quotient is taken with 55 significant bits, any remainder folded
+    // into the lowest bit, and the result rounded half to even. None when the
+    // result is too large for a float. The divisor must be nonzero.
+    pub fn true_div(&self, other: &Self) -> Option<f64> {
+        let negative = self.negative != other.negative;
+        let signed = |value: f64| if negative { -value } else { value };
+        if self.is_zero() {
+            return Some(signed(0.0));
+        }
+        let diff = bit_length(&self.magnitude) as i64 - bit_length(&other.magnitude) as i64;
+        // The quotient is at least 2^(diff - 1) and below 2^(diff + 1).
+        if diff > f64::MAX_EXP as i64 {
+            return None;
+        }
+        if diff < (f64::MIN_EXP - f64::MANTISSA_DIGITS as i32 - 1) as i64 {
+            return Some(signed(0.0));
+        }
+        // Scale the dividend by 2^-shift so the quotient has 54 or 55 bits,
+        // or fewer when the result is subnormal.
+        let shift = diff.max(f64::MIN_EXP as i64) - f64::MANTISSA_DIGITS as i64 - 2;
+        let dividend = Self::from_parts(false, self.magnitude.clone());
+        let (dividend, mut inexact) = if shift <= 0 {
+            (dividend.shl(shift.unsigned_abs()), false)
+        } else {
+            let scaled = dividend.shr(shift as u64);
+            let lost = scaled.shl(shift as u64) != dividend;
+            (scaled, lost)
+        };
+        let (quotient, remainder) = div_rem_magnitudes(&dividend.magnitude, &other.magnitude);
+        inexact |= remainder.iter().any(|&limb| limb != 0);
+        let quotient = quotient.iter().rev().fold(0u64, |acc, &limb| (acc << 32) | limb as u64);
+        let quotient_bits = 64 - quotient.leading_zeros() as i64;
+        // Round away the bits below the float's precision, half to even.
+        let extra = quotient_bits.max(f64::MIN_EXP as i64 - shift) - f64::MANTISSA_DIGITS as i64;
+        let half = 1u64 << (extra - 1);
+        let mut low = quotient | inexact as u64;
+        if low & half != 0 && low & (3 * half - 1) != 0 {
+            low += half;
+        }
+        low &= !(2 * half - 1);
+        // Scale back in steps, each exact, since the result is representable.
+        let mut result = low as f64;
+        let mut exponent = shift;
+        while exponent != 0 {
+            let step = exponent.clamp(-1000, 1000);
+            result *= 2f64.powi(step as i32);
+            exponent -= step;
+        }
+        result.is_finite().then(|| signed(result))
+    }
+
+    // ~x, which is -x - 1 for Python ints.
+    pub fn not(&self) -> Self {
+        self.neg().sub(&Self::from_i64(1))
+    }
+
+    // Applies a bitwise operator limb by limb to the two's complement forms,
+    // which Python ints behave as if they had, with infinite sign extension.
+    pub fn bitwise(&self, other: &Self, operator: impl Fn(u32, u32) -> u32) -> Self {
+        let len = self.magnitude.len().max(other.magnitude.len()) + 1;
+        let (l, r) = (self.twos_complement(len), other.twos_complement(len));
+        let mut limbs: Vec<u32> = l.iter().zip(&r).map(|(&a, &b)| operator(a, b)).collect();
+        let negative = limbs.last().is_some_and(|&top| top >> 31 == 1);
+        if negative {
+            negate_limbs(&mut limbs);
+        }
+        Self::from_parts(negative, limbs)
+    }
+
+    // The lowest `len` limbs of the two's complement form.
+    fn twos_complement(&self, len: usize) -> Vec<u32> {
+        let mut limbs = self.magnitude.clone();
+        limbs.resize(len, 0);
+        if self.negative {
+            negate_limbs(&mut limbs);
+        }
+        limbs
+    }
+
+    pub fn shl(&self, bits: u64) -> Self {
+        let mut magnitude = vec![0; (bits / 32) as usize];
+        magnitude.extend(shift_left(&self.magnitude, (bits % 32) as u32));
+        Self::from_parts(self.negative, magnitude)
+    }
+
+    // Shifting right floors, so negative values stay negative: -1 >> n is -1.
+    pub fn shr(&self, bits: u64) -> Self {
+        if self.negative {
+            return self.not().shr(bits).not();
+        }
+        let limbs = bits / 32;
+        if limbs >= self.magnitude.len() as u64 {
+            return Self::from_i64(0);
+        }
+        Self::from_parts(false, shift_right(&self.magnitude[limbs as usize..], (bits % 32) as u32))
+    }
+
+    pub fn pow(&self, mut exponent: u64) -> Self {
+        let mut result = Self::from_i64(1);
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul(&base);
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = base.mul(&base);
+            }
+        }
+        result
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.magnitude, &other.magnitude),
+            (true, true) => compare_magnitudes(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Peel off nine decimal digits at a time, least significant first.
+        let mut chunks = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            chunks.push(div_small(&mut magnitude, DECIMAL_CHUNK));
+        }
+        let mut out = String::new();
+        if self.negative {
+            out.push('-');
+        }
+        out.push_str(&chunks.pop().unwrap().to_string());
+        for chunk in chunks.iter().rev() {
+            out.push_str(&format!("{:09}", chunk));
+        }
+        write!(f, "{}", out)
+    }
+}
+
+fn bit_length(magnitude: &[u32]) -> u64 {
+    magnitude.last().map_or(0, |&top| (magnitude.len() as u64 - 1) * 32 + 32 - top.leading_zeros() as u64)
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let sum = limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+    result
+}
+
+// a - b, where |a| >= |b|.
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut diff = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = if diff < 0 { 1 } else { 0 };
+        if diff < 0 {
+            diff += BASE as i64;
+        }
+        result.push(diff as u32);
+    }
+    result
+}
+
+fn mul_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    result
+}
+
+// magnitude = magnitude * factor + addend
+fn mul_small_add(magnitude: &mut Vec<u32>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+    for limb in magnitude.iter_mut() {
+        let product = *limb as u64 * factor as u64 + carry;
+        *limb = product as u32;
+        carry = product >> 32;
+    }
+    if carry > 0 {
+        magnitude.push(carry as u32);
+    }
+}
+
+// Divides in place by a single limb, returning the remainder.
+fn div_small(magnitude: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+    for limb in magnitude.iter_mut().rev() {
+        let current = (remainder << 32) | *limb as u64;
+        *limb = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+    remainder as u32
+}
+
+// Long division of magnitudes (Knuth, TAOCP vol. 2, algorithm D).
+fn div_rem_magnitudes(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if compare_magnitudes(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if b.len() == 1 {
+        let mut quotient = a.to_vec();
+        let remainder = div_small(&mut quotient, b[0]);
+        return (quotient, vec![remainder]);
+    }
+
+    // Normalize so the divisor's top limb has its high bit set.
+    let shift = b.last().unwrap().leading_zeros();
+    let divisor = shift_left(b, shift);
+    let mut dividend = shift_left(a, shift);
+    dividend.push(0);
+    let n = divisor.len();
+    let m = dividend.len() - n;
+    let mut quotient = vec![0u32; m];
+    let top = divisor[n - 1] as u64;
+    let second = divisor[n - 2] as u64;
+
+    for j in (0..m).rev() {
+        // Estimate the quotient digit from the top two limbs, then correct it.
+        let numerator = ((dividend[j + n] as u64) << 32) | dividend[j + n - 1] as u64;
+        let mut estimate = numerator / top;
+        let mut remainder = numerator % top;
+        while estimate >= BASE || estimate * second > ((remainder << 32) | dividend[j + n - 2] as u64) {
+            estimate -= 1;
+            remainder += top;
+            if remainder >= BASE {
+                break;
+            }
+        }
+
+        // Multiply and subtract estimate * divisor from the current window.
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = estimate * divisor[i] as u64 + carry;
+            carry = product >> 32;
+            let diff = dividend[i + j] as i64 - (product & 0xffff_ffff) as i64 - borrow;
+            dividend[i + j] = diff as u32;
+            borrow = if diff < 0 { 1 } else { 0 };
+        }
+        let diff = dividend[j + n] as i64 - carry as i64 - borrow;
+        dividend[j + n] = diff as u32;
+
+        // The estimate was one too large: add the divisor back.
+        if diff < 0 {
+            estimate -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = dividend[i + j] as u64 + divisor[i] as u64 + carry;
+                dividend[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            dividend[j + n] = dividend[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = estimate as u32;
+    }
+
+    dividend.truncate(n);
+    let remainder = shift_right(&dividend, shift);
+    (quotient, remainder)
+}
+
+// Two's complement negation in place: invert every limb and add one.
+fn negate_limbs(limbs: &mut [u32]) {
+    let mut carry = 1u64;
+    for limb in limbs {
+        let sum = (!*limb) as u64 + carry;
+        *limb = sum as u32;
+        carry = sum >> 32;
+    }
+}
+
+fn shift_left(magnitude: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return magnitude.to_vec();
+    }
+    let mut result = Vec::with_capacity(magnitude.len() + 1);
+    let mut carry = 0u32;
+    for &limb in magnitude {
+        result.push((limb << shift) | carry);
+        carry = limb >> (32 - shift);
+    }
+    if carry > 0 {
+        result.push(carry);
+    }
+    result
+}
+
+fn shift_right(magnitude: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return magnitude.to_vec();
+    }
+    let mut result = vec![0u32; magnitude.len()];
+    for i in 0..magnitude.len() {
+        let high = magnitude.get(i + 1).map_or(0, |&next| next << (32 - shift));
+        result[i] = (magnitude[i] >> shift) | high;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BigInt;
+
+    fn int(text: &str) -> BigInt {
+        BigInt::parse(text).unwrap()
+    }
+
+    fn power(base: i64, exponent: u64) -> BigInt {
+        BigInt::from_i64(base).pow(exponent)
+    }
+
+    fn div_mod(left: &BigInt, right: &BigInt) -> (String, String) {
+        let (quotient, remainder) = left.div_mod_floor(right).unwrap();
+        (quotient.to_string(), remainder.to_string())
+    }
+
+    #[test]
+    fn div_mod_floor_follows_python_signs() {
+        let cases = [(7, 2, 3, 1), (-7, 2, -4, 1), (7, -2, -4, -1), (-7, -2, 3, -1)];
+        for (left, right, quotient, remainder) in cases {
+            let (left, right) = (BigInt::from_i64(left), BigInt::from_i64(right));
+            assert_eq!(div_mod(&left, &right), (quotient.to_string(), remainder.to_string()));
+        }
+        let quotient = "-142857142857142857142857142858".to_string();
+        assert_eq!(div_mod(&power(10, 30), &BigInt::from_i64(-7)), (quotient.clone(), "-6".to_string()));
+        assert_eq!(div_mod(&power(10, 30).neg(), &BigInt::from_i64(7)), (quotient, "6".to_string()));
+        let divisor = power(2, 40).neg().add(&BigInt::from_i64(1));
+        assert_eq!(div_mod(&power(2, 70).neg(), &divisor), ("1073741824".to_string(), "-1073741824".to_string()));
+        assert_eq!(BigInt::from_i64(1).div_mod_floor(&BigInt::from_i64(0)), None);
+    }
+
+    #[test]
+    fn to_f64_rounds_half_to_even() {
+        assert_eq!(power(2, 53).add(&BigInt::from_i64(1)).to_f64(), 9007199254740992.0);
+        assert_eq!(power(2, 54).add(&BigInt::from_i64(2)).to_f64(), 1.8014398509481984e16);
+        assert_eq!(power(2, 54).add(&BigInt::from_i64(6)).to_f64(), 1.801439850948199e16);
+        assert_eq!(BigInt::from_i64(i64::MIN).to_f64(), -9.223372036854776e18);
+        assert_eq!(power(10, 308).to_f64(), 1e308);
+        assert_eq!(power(2, 1024).sub(&power(2, 971)).to_f64(), f64::MAX);
+        assert_eq!(power(2, 1024).to_f64(), f64::INFINITY);
+    }
+
+    #[test]
+    fn true_div_matches_cpython() {
+        let cases = [
+            (power(10, 400), power(10, 399), 10.0),
+            (power(2, 1100), power(2, 1099), 2.0),
+            (int("9007199254740993"), BigInt::from_i64(3), 3002399751580331.0),
+            (int("-932652146082171377906"), int("-2352542074769714"), 396444.4062805835),
+            (power(2, 2000), power(3, 1000), 8.68433580377441e124),
+            (power(10, 50).neg(), BigInt::from_i64(7), -1.4285714285714286e49),
+            (power(2, 1024).sub(&power(2, 971)), BigInt::from_i64(1), f64::MAX),
+            // Subnormal results, and one that rounds to zero.
+            (BigInt::from_i64(1), power(2, 1074), 5e-324),
+            (BigInt::from_i64(3), power(2, 1076), 5e-324),
+            (BigInt::from_i64(1), power(2, 1075), 0.0),
+        ];
+        for (left, right, expected) in cases {
+            assert_eq!(left.true_div(&right), Some(expected), "{} / {}", left, right);
+        }
+        let zero = BigInt::from_i64(0).true_div(&BigInt::from_i64(-5)).unwrap();
+        assert!(zero == 0.0 && zero.is_sign_negative());
+    }
+
+    #[test]
+    fn true_div_overflows() {
+        assert_eq!(power(2, 1024).true_div(&BigInt::from_i64(1)), None);
+        // Only rounding takes this one out of range.
+        assert_eq!(power(2, 1025).sub(&power(2, 971)).true_div(&BigInt::from_i64(2)), None);
+    }
+}
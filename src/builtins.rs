@@ -0,0 +1,1331 @@
+use crate::ast::{Builtin, Module, Range, Slice, Value, ViewKind};
+use crate::bigint::BigInt;
+use crate::class::{object_class, Class, Method, Super};
+use crate::dict::Dict;
+use crate::error::{ErrorKind, PyError, Span};
+use crate::evaluator::{call_function, Environment};
+use crate::exception;
+use crate::format::format_string;
+use crate::generator::{self, Generator, Resume};
+use crate::operators::{key_error, sequence_items, slice_bound, slice_range};
+use crate::set::Set;
+use crate::special;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+// Names that resolve to built-in functions unless a variable shadows them.
+const FUNCTIONS: &[&str] = &[
+    "len", "range", "iter", "next", "int", "float", "str", "bool", "list", "tuple", "dict", "set", "frozenset",
+    "format", "isinstance", "issubclass", "super", "hash",
+];
+
+// The built-in functions that are also the types of the values they create.
+const TYPES: &[&str] = &["int", "float", "str", "bool", "range", "list", "tuple", "dict", "set", "frozenset"];
+
+const STR_METHODS: &[&str] = &[
+    "split", "join", "strip", "lstrip", "rstrip", "replace", "startswith", "endswith", "find", "upper", "lower",
+    "format", "isdigit", "zfill", "splitlines", "partition", "count",
+];
+
+const LIST_METHODS: &[&str] = &["append", "extend", "pop", "insert", "remove", "index", "count", "sort", "reverse"];
+
+const TUPLE_METHODS: &[&str] = &["index", "count"];
+
+const DICT_METHODS: &[&str] = &["keys", "values", "items", "get", "setdefault", "pop", "update"];
+
+const SET_METHODS: &[&str] = &["add", "discard", "remove", "pop"];
+
+const GENERATOR_METHODS: &[&str] = &["send", "throw", "close", "__next__", "__iter__"];
+
+fn builtin(name: &str, receiver: Option<Value>) -> Value {
+    Value::Builtin(Rc::new(Builtin { name: name.to_string(), receiver }))
+}
+
+pub fn lookup(name: &str) -> Option<Value> {
+    match name {
+        "object" => return Some(Value::Class(object_class())),
+        "NotImplemented" => return Some(Value::NotImplemented),
+        _ => {}
+    }
+    if FUNCTIONS.contains(&name) {
+        return Some(builtin(name, None));
+    }
+    exception::lookup(name)
+}
+
+// The modules `import` can load; sys.argv holds the script's command line.
+pub fn import_module(name: &str, argv: &[String]) -> Option<Value> {
+    let attributes = match name {
+        "sys" => {
+            let argv = argv.iter().map(|arg| Value::Str(arg.clone())).collect();
+            HashMap::from([("argv".to_string(), Value::new_list(argv))])
+        }
+        _ => return None,
+    };
+    Some(Value::Module(Rc::new(Module { name: name.to_string(), attributes })))
+}
+
+// `value.name`; methods come back bound to the value they were looked up on.
+pub fn get_attribute(value: &Value, name: &str) -> Result<Value, PyError> {
+    match value {
+        Value::Str(_) if STR_METHODS.contains(&name) => Ok(builtin(name, Some(value.clone()))),
+        Value::List(_) if LIST_METHODS.contains(&name) => Ok(builtin(name, Some(value.clone()))),
+        Value::Tuple(_) if TUPLE_METHODS.contains(&name) => Ok(builtin(name, Some(value.clone()))),
+        Value::Dict(_) if DICT_METHODS.contains(&name) => Ok(builtin(name, Some(value.clone()))),
+        Value::Set(_) if SET_METHODS.contains(&name) => Ok(builtin(name, Some(value.clone()))),
+        Value::Generator(_) if GENERATOR_METHODS.contains(&name) => Ok(builtin(name, Some(value.clone()))),
+        Value::Generator(generator) if name == "__name__" => Ok(Value::Str(generator.function.name.clone())),
+        Value::Generator(generator) if name == "__qualname__" => Ok(Value::Str(generator.function.qualname.clone())),
+        Value::Module(module) => module.attributes.get(name).cloned().ok_or_else(|| {
+            PyError::new(
+                ErrorKind::AttributeError,
+                format!("module '{}' has no attribute '{}'", module.name, name),
+            )
+        }),
+        Value::Function(function) if name == "__name__" => Ok(Value::Str(function.name.clone())),
+        Value::Function(function) if name == "__qualname__" => Ok(Value::Str(function.qualname.clone())),
+        Value::Method(method) if name == "__self__" => Ok(method.receiver.clone()),
+        Value::Method(method) if name == "__func__" => Ok(Value::Function(Rc::clone(&method.function))),
+        Value::Method(method) if name == "__name__" || name == "__qualname__" => {
+            get_attribute(&Value::Function(Rc::clone(&method.function)), name)
+        }
+        // Instance attributes shadow those of the class and its ancestors.
+        Value::Instance(instance) => {
+            if name == "__class__" {
+                return Ok(Value::Class(Rc::clone(&instance.class)));
+            }
+            if let Some(attribute) = instance.attributes.borrow().get(name) {
+                return Ok(attribute.clone());
+            }
+            match instance.class.lookup(name) {
+                Some(attribute) => Ok(bind(attribute, value)),
+                None => Err(PyError::new(
+                    ErrorKind::AttributeError,
+                    format!("'{}' object has no attribute '{}'", instance.class.name, name),
+                )),
+            }
+        }
+        Value::Class(class) => class_attribute(class, name).ok_or_else(|| {
+            PyError::new(
+                ErrorKind::AttributeError,
+                format!("type object '{}' has no attribute '{}'", class.name, name),
+            )
+        }),
+        Value::Super(proxy) => match proxy.object_class().lookup_after(&proxy.class, name) {
+            Some(attribute) if matches!(proxy.object, Value::Instance(_)) => Ok(bind(attribute, &proxy.object)),
+            Some(attribute) => Ok(attribute),
+            None => Err(PyError::new(
+                ErrorKind::AttributeError,
+                format!("'super' object has no attribute '{}'", name),
+            )),
+        },
+        _ => Err(PyError::new(
+            ErrorKind::AttributeError,
+            format!("'{}' object has no attribute '{}'", value.type_name(), name),
+        )),
+    }
+}
+
+// Functions found on an instance's class become methods bound to it, as does
+// object's `__init__`.
+fn bind(attribute: Value, receiver: &Value) -> Value {
+    match attribute {
+        Value::Function(function) => Value::Method(Rc::new(Method { function, receiver: receiver.clone() })),
+        Value::Builtin(init) if init.name == "__init__" && init.receiver.is_none() => {
+            builtin("__init__", Some(receiver.clone()))
+        }
+        other => other,
+    }
+}
+
+// A class's own special attributes, then whatever its MRO holds.
+fn class_attribute(class: &Rc<Class>, name: &str) -> Option<Value> {
+    let classes = |classes: &[Rc<Class>]| Value::new_tuple(classes.iter().cloned().map(Value::Class).collect());
+    match name {
+        "__name__" => Some(Value::Str(class.name.clone())),
+        "__qualname__" => Some(Value::Str(class.qualname.clone())),
+        "__module__" => Some(Value::Str(class.module.to_string())),
+        "__bases__" => Some(classes(&class.bases)),
+        "__mro__" => Some(classes(&class.mro())),
+        _ => class.lookup(name),
+    }
+}
+
+pub fn call_builtin<W: Write>(
+    builtin: &Builtin,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let name = builtin.name.as_str();
+    match &builtin.receiver {
+        Some(Value::Str(s)) => {
+            // str.join takes any iterable, so objects are walked up front.
+            let args = match args.as_slice() {
+                [object @ (Value::Instance(_) | Value::Generator(_))] if name == "join" => {
+                    vec![Value::new_list(special::items(object, span, env, writer)?)]
+                }
+                _ => args,
+            };
+            if name == "format" {
+                return Ok(Value::Str(format_string(s, &args, &kwargs, span, env, writer)?));
+            }
+            return call_str_method(name, s, args, kwargs);
+        }
+        Some(Value::List(list)) => return call_list_method(name, list, args, kwargs, span, env, writer),
+        Some(Value::Tuple(items)) => {
+            no_keywords(name, &kwargs)?;
+            return match name {
+                "index" => find_index(items, &args, "tuple", span, env, writer),
+                _ => count_matches(items, &args, span, env, writer),
+            };
+        }
+        Some(Value::Dict(dict)) => return call_dict_method(name, dict, args, kwargs, span, env, writer),
+        Some(Value::Set(set)) => {
+            no_keywords(name, &kwargs)?;
+            return call_set_method(name, set, args, span, env, writer);
+        }
+        Some(Value::Generator(generator)) => {
+            no_keywords(name, &kwargs)?;
+            return call_generator_method(name, generator, args, span, env, writer);
+        }
+        _ => {}
+    }
+    // dict() takes its keyword arguments as entries, as dict.update does.
+    if name == "dict" {
+        expect_args(name, &args, 0, 1)?;
+        let dict = Rc::new(RefCell::new(Dict::new()));
+        update_dict(&dict, args, kwargs, span, env, writer)?;
+        return Ok(Value::Dict(dict));
+    }
+    no_keywords(name, &kwargs)?;
+    match name {
+        "len" => {
+            expect_args(name, &args, 1, 1)?;
+            Ok(Value::Int(special::length(&args[0], span, env, writer)? as i64))
+        }
+        "range" => {
+            expect_args(name, &args, 1, 3)?;
+            let bounds = args.iter().map(int_arg).collect::<Result<Vec<_>, _>>()?;
+            let range = match bounds[..] {
+                [stop] => Range { start: 0, stop, step: 1 },
+                [start, stop] => Range { start, stop, step: 1 },
+                [_, _, 0] => return Err(PyError::new(ErrorKind::ValueError, "range() arg 3 must not be zero")),
+                [start, stop, step] => Range { start, stop, step },
+                _ => unreachable!(),
+            };
+            Ok(Value::Range(Rc::new(range)))
+        }
+        "iter" => {
+            expect_args(name, &args, 1, 1)?;
+            special::iter(&args[0], span, env, writer)
+        }
+        "next" => {
+            expect_args(name, &args, 1, 2)?;
+            match (special::next(&args[0], span, env, writer), args.get(1)) {
+                (Err(error), Some(default)) if error.kind == ErrorKind::StopIteration => Ok(default.clone()),
+                (result, _) => result,
+            }
+        }
+        "int" => {
+            expect_args(name, &args, 0, 2)?;
+            match args.first() {
+                Some(value) => to_int(value, args.get(1)),
+                None => Ok(Value::Int(0)),
+            }
+        }
+        "float" => {
+            expect_args(name, &args, 0, 1)?;
+            match args.first() {
+                Some(value) => Ok(Value::Float(to_float(value)?)),
+                None => Ok(Value::Float(0.0)),
+            }
+        }
+        "str" => {
+            expect_args(name, &args, 0, 1)?;
+            match args.first() {
+                Some(value) => Ok(Value::Str(special::to_str(value, span, env, writer)?)),
+                None => Ok(Value::Str(String::new())),
+            }
+        }
+        "bool" => {
+            expect_args(name, &args, 0, 1)?;
+            match args.first() {
+                Some(value) => Ok(Value::Bool(special::truthy(value, span, env, writer)?)),
+                None => Ok(Value::Bool(false)),
+            }
+        }
+        "list" => {
+            expect_args(name, &args, 0, 1)?;
+            match args.first() {
+                Some(iterable) => Ok(Value::new_list(special::items(iterable, span, env, writer)?)),
+                None => Ok(Value::new_list(Vec::new())),
+            }
+        }
+        "tuple" => {
+            expect_args(name, &args, 0, 1)?;
+            match args.first() {
+                // Tuples are immutable, so one can stand in for its copy.
+                Some(tuple @ Value::Tuple(_)) => Ok(tuple.clone()),
+                Some(iterable) => Ok(Value::new_tuple(special::items(iterable, span, env, writer)?)),
+                None => Ok(Value::new_tuple(Vec::new())),
+            }
+        }
+        "set" => {
+            expect_args(name, &args, 0, 1)?;
+            match args.first() {
+                Some(iterable) => Ok(Value::new_set(new_set(iterable, span, env, writer)?)),
+                None => Ok(Value::new_set(Set::new())),
+            }
+        }
+        "frozenset" => {
+            expect_args(name, &args, 0, 1)?;
+            match args.first() {
+                // Frozensets are immutable, so one can stand in for its copy.
+                Some(set @ Value::FrozenSet(_)) => Ok(set.clone()),
+                Some(iterable) => Ok(Value::FrozenSet(Rc::new(new_set(iterable, span, env, writer)?))),
+                None => Ok(Value::FrozenSet(Rc::new(Set::new()))),
+            }
+        }
+        "format" => {
+            expect_args(name, &args, 1, 2)?;
+            let spec = match args.get(1) {
+                Some(Value::Str(spec)) => spec.as_str(),
+                Some(other) => {
+                    return Err(PyError::new(
+                        ErrorKind::TypeError,
+                        format!("format() argument 2 must be str, not {}", other.type_name()),
+                    ))
+                }
+                None => "",
+            };
+            Ok(Value::Str(special::format(&args[0], None, spec, span, env, writer)?))
+        }
+        "hash" => {
+            expect_args(name, &args, 1, 1)?;
+            Ok(Value::Int(special::hash(&args[0], span, env, writer)?))
+        }
+        "isinstance" => {
+            expect_args(name, &args, 2, 2)?;
+            let matches = match &args[0] {
+                Value::Instance(instance) => is_subtype(Some(&instance.class), "", &args[1], name)?,
+                other => is_subtype(None, other.type_name(), &args[1], name)?,
+            };
+            Ok(Value::Bool(matches))
+        }
+        "issubclass" => {
+            expect_args(name, &args, 2, 2)?;
+            let matches = match &args[0] {
+                Value::Class(class) => is_subtype(Some(class), "", &args[1], name)?,
+                Value::Builtin(function) if function.receiver.is_none() && TYPES.contains(&function.name.as_str()) => {
+                    is_subtype(None, &function.name, &args[1], name)?
+                }
+                _ => return Err(PyError::new(ErrorKind::TypeError, "issubclass() arg 1 must be a class")),
+            };
+            Ok(Value::Bool(matches))
+        }
+        "super" => {
+            let (class, object) = match &args[..] {
+                [] => env.super_arguments()?,
+                [Value::Class(class), object] => (Rc::clone(class), object.clone()),
+                [other, _] => {
+                    return Err(PyError::new(
+                        ErrorKind::TypeError,
+                        format!("super() argument 1 must be a type, not {}", other.type_name()),
+                    ))
+                }
+                [_] => return Err(PyError::new(ErrorKind::TypeError, "super() takes 0 or 2 arguments (1 given)")),
+                _ => {
+                    return Err(PyError::new(
+                        ErrorKind::TypeError,
+                        format!("super() expected at most 2 arguments, got {}", args.len()),
+                    ))
+                }
+            };
+            let derived = match &object {
+                Value::Instance(instance) => instance.class.is_subclass(&class),
+                Value::Class(subclass) => subclass.is_subclass(&class),
+                _ => false,
+            };
+            if !derived {
+                return Err(PyError::new(
+                    ErrorKind::TypeError,
+                    "super(type, obj): obj must be an instance or subtype of type",
+                ));
+            }
+            Ok(Value::Super(Rc::new(Super { class, object })))
+        }
+        // object's `__init__`, bound to an instance or given it as the first argument.
+        "__init__" => {
+            let mut args = args;
+            if let Some(receiver) = &builtin.receiver {
+                args.insert(0, receiver.clone());
+            }
+            let Some(object) = args.first() else {
+                return Err(PyError::new(
+                    ErrorKind::TypeError,
+                    "descriptor '__init__' of 'object' object needs an argument",
+                ));
+            };
+            // BaseException's `__init__` keeps its arguments as `args`.
+            if exception::is_exception(object) {
+                if let Value::Instance(instance) = object {
+                    instance.attributes.borrow_mut().insert("args".to_string(), Value::new_tuple(args[1..].to_vec()));
+                }
+                return Ok(Value::None);
+            }
+            if args.len() > 1 {
+                // CPython names the class unless it overrides `__init__`.
+                let owner = match object {
+                    Value::Instance(instance) if !matches!(instance.class.lookup(name), Some(Value::Function(_))) => {
+                        instance.class.name.as_str()
+                    }
+                    _ => "object",
+                };
+                return Err(PyError::new(
+                    ErrorKind::TypeError,
+                    format!("{}.__init__() takes exactly one argument (the instance to initialize)", owner),
+                ));
+            }
+            Ok(Value::None)
+        }
+        _ => unreachable!("unknown builtin {}", name),
+    }
+}
+
+// Whether a type (a class, or else the built-in type named `type_name`) is
+// `classinfo` or derives from it; classinfo may be a tuple of types, too.
+fn is_subtype(class: Option<&Rc<Class>>, type_name: &str, classinfo: &Value, function: &str) -> Result<bool, PyError> {
+    match classinfo {
+        Value::Class(info) => Ok(match class {
+            Some(class) => class.is_subclass(info),
+            None => Rc::ptr_eq(info, &object_class()),
+        }),
+        // bool is the one built-in type deriving from another.
+        Value::Builtin(info) if info.receiver.is_none() && TYPES.contains(&info.name.as_str()) => {
+            Ok(class.is_none() && (info.name == type_name || info.name == "int" && type_name == "bool"))
+        }
+        Value::Tuple(items) => {
+            for item in items.iter() {
+                if is_subtype(class, type_name, item, function)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        _ if function == "isinstance" => Err(PyError::new(
+            ErrorKind::TypeError,
+            "isinstance() arg 2 must be a type, a tuple of types, or a union",
+        )),
+        _ => Err(PyError::new(
+            ErrorKind::TypeError,
+            "issubclass() arg 2 must be a class, a tuple of classes, or a union",
+        )),
+    }
+}
+
+// len() of a built-in value.
+pub fn len(value: &Value) -> Result<usize, PyError> {
+    let len = match value {
+        Value::Str(s) => s.chars().count(),
+        Value::Bytes(bytes) => bytes.len(),
+        Value::List(items) => items.borrow().len(),
+        Value::Tuple(items) => items.len(),
+        Value::Dict(dict) | Value::DictView(dict, _) => dict.borrow().len(),
+        Value::Set(set) => set.borrow().len(),
+        Value::FrozenSet(set) => set.len(),
+        Value::Range(range) => range.len(),
+        other => {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("object of type '{}' has no len()", other.type_name()),
+            ))
+        }
+    };
+    Ok(len)
+}
+
+fn call_list_method<W: Write>(
+    name: &str,
+    list: &Rc<RefCell<Vec<Value>>>,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if name == "sort" {
+        return sort_list(list, args, kwargs, span, env, writer);
+    }
+    no_keywords(name, &kwargs)?;
+    match name {
+        "append" => {
+            expect_args(name, &args, 1, 1)?;
+            list.borrow_mut().push(args[0].clone());
+        }
+        "extend" => {
+            expect_args(name, &args, 1, 1)?;
+            // Collected before borrowing, since a list may be extended by itself.
+            let items = special::items(&args[0], span, env, writer)?;
+            list.borrow_mut().extend(items);
+        }
+        "pop" => {
+            expect_args(name, &args, 0, 1)?;
+            let mut items = list.borrow_mut();
+            if items.is_empty() {
+                return Err(PyError::new(ErrorKind::IndexError, "pop from empty list"));
+            }
+            let len = items.len() as i64;
+            let index = match args.first() {
+                Some(index) => int_arg(index)?,
+                None => -1,
+            };
+            let position = if index < 0 { index + len } else { index };
+            if !(0..len).contains(&position) {
+                return Err(PyError::new(ErrorKind::IndexError, "pop index out of range"));
+            }
+            return Ok(items.remove(position as usize));
+        }
+        "insert" => {
+            expect_args(name, &args, 2, 2)?;
+            let mut items = list.borrow_mut();
+            let len = items.len() as i64;
+            let index = int_arg(&args[0])?;
+            let position = if index < 0 { (index + len).max(0) } else { index.min(len) };
+            items.insert(position as usize, args[1].clone());
+        }
+        "remove" => {
+            expect_args(name, &args, 1, 1)?;
+            let items = list.borrow().clone();
+            let Some(position) = position(&items, &args[0], 0..items.len(), span, env, writer)? else {
+                return Err(PyError::new(ErrorKind::ValueError, "list.remove(x): x not in list"));
+            };
+            list.borrow_mut().remove(position);
+        }
+        // The items are copied out, since `__eq__` may change the list.
+        "index" => return find_index(&list.borrow().clone(), &args, "list", span, env, writer),
+        "count" => return count_matches(&list.borrow().clone(), &args, span, env, writer),
+        "reverse" => {
+            expect_args(name, &args, 0, 0)?;
+            list.borrow_mut().reverse();
+        }
+        _ => unreachable!("unknown list method {}", name),
+    }
+    Ok(Value::None)
+}
+
+// list.index / tuple.index: the first position of a value, optionally
+// searching only between start and end (clamped like slice bounds).
+fn find_index<W: Write>(
+    items: &[Value],
+    args: &[Value],
+    type_name: &str,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    expect_args("index", args, 1, 3)?;
+    let bounds = Slice {
+        lower: args.get(1).cloned().unwrap_or(Value::None),
+        upper: args.get(2).cloned().unwrap_or(Value::None),
+        step: Value::None,
+    };
+    let (start, stop, _) = slice_range(&bounds, items.len())?;
+    match position(items, &args[0], start as usize..stop.max(start) as usize, span, env, writer)? {
+        Some(position) => Ok(Value::Int(position as i64)),
+        None if type_name == "list" => {
+            let repr = special::repr(&args[0], span, env, writer)?;
+            Err(PyError::new(ErrorKind::ValueError, format!("{} is not in list", repr)))
+        }
+        None => Err(PyError::new(ErrorKind::ValueError, format!("{}.index(x): x not in {}", type_name, type_name))),
+    }
+}
+
+fn count_matches<W: Write>(
+    items: &[Value],
+    args: &[Value],
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    expect_args("count", args, 1, 1)?;
+    let mut count = 0;
+    for item in items {
+        if special::equal(item, &args[0], span, env, writer)? {
+            count += 1;
+        }
+    }
+    Ok(Value::Int(count))
+}
+
+// The first position in `range` holding an item equal to `value`.
+fn position<W: Write>(
+    items: &[Value],
+    value: &Value,
+    range: std::ops::Range<usize>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<usize>, PyError> {
+    for i in range {
+        if special::equal(&items[i], value, span, env, writer)? {
+            return Ok(Some(i));
+        }
+    }
+    Ok(None)
+}
+
+// set(iterable) and frozenset(iterable). Another set or a dict already holds
+// distinct items, and is copied the way CPython copies it.
+fn new_set<W: Write>(iterable: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<Set, PyError> {
+    match iterable {
+        Value::Set(_) | Value::FrozenSet(_) | Value::Dict(_) => Set::from_value(iterable),
+        _ => special::new_set(special::items(iterable, span, env, writer)?, span, env, writer),
+    }
+}
+
+fn call_set_method<W: Write>(
+    name: &str,
+    set: &Rc<RefCell<Set>>,
+    args: Vec<Value>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if name == "pop" {
+        expect_args(name, &args, 0, 0)?;
+        return set
+            .borrow_mut()
+            .pop()
+            .ok_or_else(|| key_error(&Value::Str("pop from an empty set".to_string())));
+    }
+    expect_args(name, &args, 1, 1)?;
+    let item = args.into_iter().next().unwrap();
+    match name {
+        "add" => special::set_add(set, item, span, env, writer)?,
+        "discard" => {
+            special::set_discard(set, &item, span, env, writer)?;
+        }
+        _ => {
+            if !special::set_discard(set, &item, span, env, writer)? {
+                return Err(key_error(&item));
+            }
+        }
+    }
+    Ok(Value::None)
+}
+
+fn call_generator_method<W: Write>(
+    name: &str,
+    generator: &Rc<Generator>,
+    args: Vec<Value>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    match name {
+        "send" => {
+            expect_args(name, &args, 1, 1)?;
+            generator::resume(generator, Resume::Send(args[0].clone()), span, env, writer)
+        }
+        "throw" => {
+            expect_args(name, &args, 1, 3)?;
+            let error = generator::thrown(&args, span, env, writer)?;
+            generator::resume(generator, Resume::Throw(error), span, env, writer)
+        }
+        "close" => {
+            expect_args(name, &args, 0, 0)?;
+            generator::close(generator, span, env, writer)
+        }
+        "__next__" => {
+            expect_args(name, &args, 0, 0)?;
+            generator::resume(generator, Resume::Send(Value::None), span, env, writer)
+        }
+        _ => {
+            expect_args(name, &args, 0, 0)?;
+            Ok(Value::Generator(Rc::clone(generator)))
+        }
+    }
+}
+
+fn call_str_method(name: &str, s: &str, args: Vec<Value>, kwargs: Vec<(String, Value)>) -> Result<Value, PyError> {
+    let text = |s: &str| Value::Str(s.to_string());
+    let list = |parts: Vec<&str>| Value::new_list(parts.into_iter().map(text).collect());
+    match name {
+        "split" => {
+            let [sep, maxsplit] = keyword_args(name, args, kwargs, ["sep", "maxsplit"])?;
+            let maxsplit = maxsplit.as_ref().map_or(Ok(-1), int_arg)?;
+            let parts = match sep {
+                None | Some(Value::None) => split_whitespace(s, maxsplit),
+                Some(Value::Str(sep)) if sep.is_empty() => {
+                    return Err(PyError::new(ErrorKind::ValueError, "empty separator"))
+                }
+                Some(Value::Str(sep)) if maxsplit < 0 => s.split(sep.as_str()).collect(),
+                Some(Value::Str(sep)) => s.splitn(maxsplit as usize + 1, sep.as_str()).collect(),
+                Some(other) => {
+                    return Err(PyError::new(
+                        ErrorKind::TypeError,
+                        format!("must be str or None, not {}", other.type_name()),
+                    ))
+                }
+            };
+            return Ok(list(parts));
+        }
+        "splitlines" => {
+            let [keepends] = keyword_args(name, args, kwargs, ["keepends"])?;
+            let keepends = keepends.is_some_and(|keepends| keepends.is_truthy());
+            return Ok(list(split_lines(s, keepends)));
+        }
+        _ => {}
+    }
+    no_keywords(name, &kwargs)?;
+    match name {
+        "join" => {
+            expect_args(name, &args, 1, 1)?;
+            let items = sequence_items(&args[0]).map_err(|error| match error.kind {
+                ErrorKind::TypeError => PyError::new(ErrorKind::TypeError, "can only join an iterable"),
+                _ => error,
+            })?;
+            let mut parts = Vec::with_capacity(items.len());
+            for (i, item) in items.iter().enumerate() {
+                match item {
+                    Value::Str(part) => parts.push(part.as_str()),
+                    other => {
+                        return Err(PyError::new(
+                            ErrorKind::TypeError,
+                            format!("sequence item {}: expected str instance, {} found", i, other.type_name()),
+                        ))
+                    }
+                }
+            }
+            Ok(Value::Str(parts.join(s)))
+        }
+        "strip" | "lstrip" | "rstrip" => {
+            expect_args(name, &args, 0, 1)?;
+            let strip_chars = match args.first() {
+                None | Some(Value::None) => None,
+                Some(Value::Str(chars)) => Some(chars.as_str()),
+                Some(_) => {
+                    return Err(PyError::new(ErrorKind::TypeError, format!("{} arg must be None or str", name)))
+                }
+            };
+            // Whitespace by default, otherwise any of the given characters.
+            let strip = |c: char| strip_chars.map_or(is_space(c), |chars| chars.contains(c));
+            Ok(text(match name {
+                "strip" => s.trim_matches(strip),
+                "lstrip" => s.trim_start_matches(strip),
+                _ => s.trim_end_matches(strip),
+            }))
+        }
+        "replace" => {
+            expect_args(name, &args, 2, 3)?;
+            let old = str_arg(&args[0], "replace() argument 1 ")?;
+            let new = str_arg(&args[1], "replace() argument 2 ")?;
+            match args.get(2).map(int_arg).transpose()? {
+                Some(count) if count >= 0 => Ok(Value::Str(s.replacen(old, new, count as usize))),
+                _ => Ok(Value::Str(s.replace(old, new))),
+            }
+        }
+        "startswith" | "endswith" => {
+            expect_args(name, &args, 1, 3)?;
+            let window = str_window(s, args.get(1), args.get(2))?.map(|(window, _)| window);
+            let matches = |affix: &str| match window {
+                Some(window) if name == "startswith" => window.starts_with(affix),
+                Some(window) => window.ends_with(affix),
+                None => false,
+            };
+            match &args[0] {
+                Value::Str(affix) => Ok(Value::Bool(matches(affix))),
+                // A tuple matches when any of its strings does.
+                Value::Tuple(affixes) => {
+                    for affix in affixes.iter() {
+                        let Value::Str(affix) = affix else {
+                            return Err(PyError::new(
+                                ErrorKind::TypeError,
+                                format!("tuple for {} must only contain str, not {}", name, affix.type_name()),
+                            ));
+                        };
+                        if matches(affix) {
+                            return Ok(Value::Bool(true));
+                        }
+                    }
+                    Ok(Value::Bool(false))
+                }
+                other => Err(PyError::new(
+                    ErrorKind::TypeError,
+                    format!("{} first arg must be str or a tuple of str, not {}", name, other.type_name()),
+                )),
+            }
+        }
+        "find" | "count" => {
+            expect_args(name, &args, 1, 3)?;
+            let sub = str_arg(&args[0], "")?;
+            let Some((window, offset)) = str_window(s, args.get(1), args.get(2))? else {
+                return Ok(Value::Int(if name == "find" { -1 } else { 0 }));
+            };
+            let result = match name {
+                // Positions are in code points, not bytes.
+                "find" => window.find(sub).map_or(-1, |byte| (offset + window[..byte].chars().count()) as i64),
+                // An empty string occurs between every pair of characters.
+                _ if sub.is_empty() => window.chars().count() as i64 + 1,
+                _ => window.matches(sub).count() as i64,
+            };
+            Ok(Value::Int(result))
+        }
+        "upper" | "lower" | "isdigit" => {
+            expect_args(&format!("str.{}", name), &args, 0, 0)?;
+            Ok(match name {
+                "upper" => Value::Str(s.to_uppercase()),
+                "lower" => Value::Str(s.to_lowercase()),
+                _ => Value::Bool(!s.is_empty() && s.chars().all(is_digit)),
+            })
+        }
+        "zfill" => {
+            expect_args(name, &args, 1, 1)?;
+            let width = int_arg(&args[0])?;
+            let len = s.chars().count();
+            if width <= len as i64 {
+                return Ok(text(s));
+            }
+            // The zeros go after a leading sign.
+            let (sign, digits) = match s.chars().next() {
+                Some(c @ ('+' | '-')) => (c.to_string(), &s[1..]),
+                _ => (String::new(), s),
+            };
+            Ok(Value::Str(format!("{}{}{}", sign, "0".repeat(width as usize - len), digits)))
+        }
+        "partition" => {
+            expect_args(name, &args, 1, 1)?;
+            let sep = str_arg(&args[0], "")?;
+            if sep.is_empty() {
+                return Err(PyError::new(ErrorKind::ValueError, "empty separator"));
+            }
+            let (head, sep, tail) = match s.split_once(sep) {
+                Some((head, tail)) => (head, sep, tail),
+                None => (s, "", ""),
+            };
+            Ok(Value::new_tuple(vec![text(head), text(sep), text(tail)]))
+        }
+        _ => unreachable!("unknown str method {}", name),
+    }
+}
+
+// A str argument, or a TypeError such as "replace() argument 1 must be str,
+// not int"; `context` is the start of that message.
+fn str_arg<'a>(value: &'a Value, context: &str) -> Result<&'a str, PyError> {
+    match value {
+        Value::Str(s) => Ok(s),
+        other => Err(PyError::new(ErrorKind::TypeError, format!("{}must be str, not {}", context, other.type_name()))),
+    }
+}
+
+// The part of a string between the optional start and end arguments of
+// find() and friends, clamped like slice bounds, and its code-point offset.
+// None when start is past the end of the string or past `end`, where nothing
+// is found, not even an empty string.
+fn str_window<'a>(s: &'a str, start: Option<&Value>, end: Option<&Value>) -> Result<Option<(&'a str, usize)>, PyError> {
+    let bounds = Slice {
+        lower: start.cloned().unwrap_or(Value::None),
+        upper: end.cloned().unwrap_or(Value::None),
+        step: Value::None,
+    };
+    let len = s.chars().count();
+    let (start, stop, _) = slice_range(&bounds, len)?;
+    if start > stop || slice_bound(&bounds.lower)?.is_some_and(|start| start > len as i64) {
+        return Ok(None);
+    }
+    let byte = |i: usize| s.char_indices().nth(i).map_or(s.len(), |(byte, _)| byte);
+    Ok(Some((&s[byte(start as usize)..byte(stop as usize)], start as usize)))
+}
+
+// str.split() with no separator: runs of whitespace separate the parts, and
+// whitespace at either end is dropped.
+fn split_whitespace(s: &str, maxsplit: i64) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = s.trim_start_matches(is_space);
+    while !rest.is_empty() {
+        if maxsplit >= 0 && parts.len() as i64 == maxsplit {
+            parts.push(rest);
+            break;
+        }
+        let end = rest.find(is_space).unwrap_or(rest.len());
+        parts.push(&rest[..end]);
+        rest = rest[end..].trim_start_matches(is_space);
+    }
+    parts
+}
+
+// Python's whitespace: Unicode's, plus the separators '\x1c' to '\x1f'.
+fn is_space(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '\x1c'..='\x1f')
+}
+
+// The characters str.isdigit() accepts, those whose Unicode Numeric_Type is
+// Decimal or Digit (as of Unicode 14, which Python 3.11 follows). Other
+// numerals, such as '½' or 'Ⅻ', are numeric but not digits.
+const DIGITS: &[(char, char)] = &[
+    ('0', '9'), ('\u{b2}', '\u{b3}'), ('\u{b9}', '\u{b9}'), ('\u{660}', '\u{669}'), ('\u{6f0}', '\u{6f9}'),
+    ('\u{7c0}', '\u{7c9}'), ('\u{966}', '\u{96f}'), ('\u{9e6}', '\u{9ef}'), ('\u{a66}', '\u{a6f}'),
+    ('\u{ae6}', '\u{aef}'), ('\u{b66}', '\u{b6f}'), ('\u{be6}', '\u{bef}'), ('\u{c66}', '\u{c6f}'),
+    ('\u{ce6}', '\u{cef}'), ('\u{d66}', '\u{d6f}'), ('\u{de6}', '\u{def}'), ('\u{e50}', '\u{e59}'),
+    ('\u{ed0}', '\u{ed9}'), ('\u{f20}', '\u{f29}'), ('\u{1040}', '\u{1049}'), ('\u{1090}', '\u{1099}'),
+    ('\u{1369}', '\u{1371}'), ('\u{17e0}', '\u{17e9}'), ('\u{1810}', '\u{1819}'), ('\u{1946}', '\u{194f}'),
+    ('\u{19d0}', '\u{19da}'), ('\u{1a80}', '\u{1a89}'), ('\u{1a90}', '\u{1a99}'), ('\u{1b50}', '\u{1b59}'),
+    ('\u{1bb0}', '\u{1bb9}'), ('\u{1c40}', '\u{1c49}'), ('\u{1c50}', '\u{1c59}'), ('\u{2070}', '\u{2070}'),
+    ('\u{2074}', '\u{2079}'), ('\u{2080}', '\u{2089}'), ('\u{2460}', '\u{2468}'), ('\u{2474}', '\u{247c}'),
+    ('\u{2488}', '\u{2490}'), ('\u{24ea}', '\u{24ea}'), ('\u{24f5}', '\u{24fd}'), ('\u{24ff}', '\u{24ff}'),
+    ('\u{2776}', '\u{277e}'), ('\u{2780}', '\u{2788}'), ('\u{278a}', '\u{2792}'), ('\u{a620}', '\u{a629}'),
+    ('\u{a8d0}', '\u{a8d9}'), ('\u{a900}', '\u{a909}'), ('\u{a9d0}', '\u{a9d9}'), ('\u{a9f0}', '\u{a9f9}'),
+    ('\u{aa50}', '\u{aa59}'), ('\u{abf0}', '\u{abf9}'), ('\u{ff10}', '\u{ff19}'), ('\u{104a0}', '\u{104a9}'),
+    ('\u{10a40}', '\u{10a43}'), ('\u{10d30}', '\u{10d39}'), ('\u{10e60}', '\u{10e68}'), ('\u{11052}', '\u{1105a}'),
+    ('\u{11066}', '\u{1106f}'), ('\u{110f0}', '\u{110f9}'), ('\u{11136}', '\u{1113f}'), ('\u{111d0}', '\u{111d9}'),
+    ('\u{112f0}', '\u{112f9}'), ('\u{11450}', '\u{11459}'), ('\u{114d0}', '\u{114d9}'), ('\u{11650}', '\u{11659}'),
+    ('\u{116c0}', '\u{116c9}'), ('\u{11730}', '\u{11739}'), ('\u{118e0}', '\u{118e9}'), ('\u{11950}', '\u{11959}'),
+    ('\u{11c50}', '\u{11c59}'), ('\u{11d50}', '\u{11d59}'), ('\u{11da0}', '\u{11da9}'), ('\u{16a60}', '\u{16a69}'),
+    ('\u{16ac0}', '\u{16ac9}'), ('\u{16b50}', '\u{16b59}'), ('\u{1d7ce}', '\u{1d7ff}'), ('\u{1e140}', '\u{1e149}'),
+    ('\u{1e2f0}', '\u{1e2f9}'), ('\u{1e950}', '\u{1e959}'), ('\u{1f100}', '\u{1f10a}'), ('\u{1fbf0}', '\u{1fbf9}'),
+];
+
+fn is_digit(c: char) -> bool {
+    DIGITS.iter().any(|&(first, last)| (first..=last).contains(&c))
+}
+
+// str.splitlines(): splits at any of Python's line boundaries, "\r\n" counting
+// as one.
+fn split_lines(s: &str, keepends: bool) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if !matches!(c, '\n' | '\r' | '\x0b' | '\x0c' | '\x1c' | '\x1d' | '\x1e' | '\u{85}' | '\u{2028}' | '\u{2029}') {
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        if c == '\r' && chars.next_if(|&(_, next)| next == '\n').is_some() {
+            end += 1;
+        }
+        lines.push(if keepends { &s[start..end] } else { &s[start..i] });
+        start = end;
+    }
+    if start < s.len() {
+        lines.push(&s[start..]);
+    }
+    lines
+}
+
+// Matches positional and keyword arguments to a method's parameter names.
+fn keyword_args<const N: usize>(
+    name: &str,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    params: [&str; N],
+) -> Result<[Option<Value>; N], PyError> {
+    expect_args(name, &args, 0, N)?;
+    let mut values: [Option<Value>; N] = std::array::from_fn(|_| None);
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = Some(arg);
+    }
+    for (key, value) in kwargs {
+        let Some(position) = params.iter().position(|param| *param == key) else {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("'{}' is an invalid keyword argument for {}()", key, name),
+            ));
+        };
+        if values[position].is_some() {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("argument for {}() given by name ('{}') and position ({})", name, key, position + 1),
+            ));
+        }
+        values[position] = Some(value);
+    }
+    Ok(values)
+}
+
+fn call_dict_method<W: Write>(
+    name: &str,
+    dict: &Rc<RefCell<Dict>>,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if name == "update" {
+        return update_dict(dict, args, kwargs, span, env, writer);
+    }
+    no_keywords(name, &kwargs)?;
+    let view = |kind| Value::DictView(Rc::clone(dict), kind);
+    match name {
+        "keys" | "values" | "items" => {
+            expect_args(name, &args, 0, 0)?;
+            Ok(view(match name {
+                "keys" => ViewKind::Keys,
+                "values" => ViewKind::Values,
+                _ => ViewKind::Items,
+            }))
+        }
+        "get" => {
+            expect_args(name, &args, 1, 2)?;
+            let found = special::dict_get(dict, &args[0], span, env, writer)?;
+            Ok(found.or_else(|| args.get(1).cloned()).unwrap_or(Value::None))
+        }
+        "setdefault" => {
+            expect_args(name, &args, 1, 2)?;
+            let found = special::dict_get(dict, &args[0], span, env, writer)?;
+            if let Some(value) = found {
+                return Ok(value);
+            }
+            let default = args.get(1).cloned().unwrap_or(Value::None);
+            special::dict_insert(dict, args[0].clone(), default.clone(), span, env, writer)?;
+            Ok(default)
+        }
+        "pop" => {
+            expect_args(name, &args, 1, 2)?;
+            let removed = special::dict_remove(dict, &args[0], span, env, writer)?;
+            match (removed, args.get(1)) {
+                (Some(value), _) => Ok(value),
+                (None, Some(default)) => Ok(default.clone()),
+                (None, None) => Err(key_error(&args[0])),
+            }
+        }
+        _ => unreachable!("unknown dict method {}", name),
+    }
+}
+
+// dict.update([other], **kwargs): `other` is a dict or an iterable of
+// key/value pairs; keyword arguments are added afterwards.
+fn update_dict<W: Write>(
+    dict: &Rc<RefCell<Dict>>,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    expect_args("update", &args, 0, 1)?;
+    let mut entries = Vec::new();
+    match args.first() {
+        Some(Value::Dict(other)) => {
+            entries.extend(other.borrow().iter().map(|(key, value)| (key.clone(), value.clone())));
+        }
+        Some(other) => {
+            for (i, pair) in special::items(other, span, env, writer)?.iter().enumerate() {
+                let pair = special::items(pair, span, env, writer).map_err(|error| match error.kind {
+                    ErrorKind::TypeError => PyError::new(
+                        ErrorKind::TypeError,
+                        format!("cannot convert dictionary update sequence element #{} to a sequence", i),
+                    ),
+                    _ => error,
+                })?;
+                let [key, value] = <[Value; 2]>::try_from(pair).map_err(|pair| {
+                    PyError::new(
+                        ErrorKind::ValueError,
+                        format!("dictionary update sequence element #{} has length {}; 2 is required", i, pair.len()),
+                    )
+                })?;
+                entries.push((key, value));
+            }
+        }
+        None => {}
+    }
+    entries.extend(kwargs.into_iter().map(|(name, value)| (Value::Str(name), value)));
+    for (key, value) in entries {
+        special::dict_insert(dict, key, value, span, env, writer)?;
+    }
+    Ok(Value::None)
+}
+
+// list.sort(key=None, reverse=False): a stable sort using only `<`, so equal
+// items keep their original order (even when reversed).
+fn sort_list<W: Write>(
+    list: &Rc<RefCell<Vec<Value>>>,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if !args.is_empty() {
+        return Err(PyError::new(ErrorKind::TypeError, "sort() takes no positional arguments"));
+    }
+    let mut key = Value::None;
+    let mut reverse = false;
+    for (name, value) in kwargs {
+        match name.as_str() {
+            "key" => key = value,
+            "reverse" => reverse = value.is_truthy(),
+            _ => {
+                return Err(PyError::new(
+                    ErrorKind::TypeError,
+                    format!("'{}' is an invalid keyword argument for sort()", name),
+                ))
+            }
+        }
+    }
+
+    // Work on a copy so the key function can't observe a half-sorted list.
+    let items = list.borrow().clone();
+    let keys = match key {
+        Value::None => items.clone(),
+        key => {
+            let mut keys = Vec::with_capacity(items.len());
+            for item in &items {
+                keys.push(call_function(&key, vec![item.clone()], Vec::new(), span, env, writer)?);
+            }
+            keys
+        }
+    };
+    let order = merge_sort((0..items.len()).collect(), &mut |a, b| {
+        let (left, right) = if reverse { (&keys[b], &keys[a]) } else { (&keys[a], &keys[b]) };
+        let less = special::compare("<", left, right, span, env, writer)?;
+        special::truthy(&less, span, env, writer)
+    })?;
+    *list.borrow_mut() = order.into_iter().map(|i| items[i].clone()).collect();
+    Ok(Value::None)
+}
+
+// A stable merge sort over positions, driven by a fallible `less`, so that a
+// failing comparison surfaces as a Python exception.
+fn merge_sort(
+    mut order: Vec<usize>,
+    less: &mut impl FnMut(usize, usize) -> Result<bool, PyError>,
+) -> Result<Vec<usize>, PyError> {
+    if order.len() <= 1 {
+        return Ok(order);
+    }
+    let right = order.split_off(order.len() / 2);
+    let left = merge_sort(order, less)?;
+    let right = merge_sort(right, less)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        // Take from the right half only when strictly smaller, preserving stability.
+        if less(right[j], left[i])? {
+            merged.push(right[j]);
+            j += 1;
+        } else {
+            merged.push(left[i]);
+            i += 1;
+        }
+    }
+    merged.extend_from_slice(&left[i..]);
+    merged.extend_from_slice(&right[j..]);
+    Ok(merged)
+}
+
+// Converts an argument that must be an integer, such as an index.
+pub fn int_arg(value: &Value) -> Result<i64, PyError> {
+    match value.as_int() {
+        Some(n) => Ok(n),
+        None if value.is_int() => Err(PyError::new(
+            ErrorKind::OverflowError,
+            "Python int too large to convert to C ssize_t",
+        )),
+        None => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("'{}' object cannot be interpreted as an integer", value.type_name()),
+        )),
+    }
+}
+
+// int() of a number or a string; floats are truncated toward zero, and only
+// strings may come with a base.
+fn to_int(value: &Value, base: Option<&Value>) -> Result<Value, PyError> {
+    let text = match (value, base) {
+        (Value::Str(text), _) => text,
+        (_, Some(_)) => {
+            return Err(PyError::new(ErrorKind::TypeError, "int() can't convert non-string with explicit base"))
+        }
+        (Value::Bool(b), None) => return Ok(Value::Int(*b as i64)),
+        (Value::Int(_) | Value::BigInt(_), None) => return Ok(value.clone()),
+        (Value::Float(f), None) if f.is_nan() => {
+            return Err(PyError::new(ErrorKind::ValueError, "cannot convert float NaN to integer"))
+        }
+        (Value::Float(f), None) if f.is_infinite() => {
+            return Err(PyError::new(ErrorKind::OverflowError, "cannot convert float infinity to integer"))
+        }
+        (Value::Float(f), None) => return Ok(Value::from_bigint(BigInt::from_f64(*f))),
+        _ => {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!(
+                    "int() argument must be a string, a bytes-like object or a real number, not '{}'",
+                    value.type_name()
+                ),
+            ))
+        }
+    };
+    let base = match base {
+        Some(base) => int_arg(base)?,
+        None => 10,
+    };
+    if base != 0 && !(2..=36).contains(&base) {
+        return Err(PyError::new(ErrorKind::ValueError, "int() base must be >= 2 and <= 36, or 0"));
+    }
+    parse_int(text, base as u32).map(Value::from_bigint).ok_or_else(|| {
+        PyError::new(
+            ErrorKind::ValueError,
+            format!("invalid literal for int() with base {}: {}", base, value.repr()),
+        )
+    })
+}
+
+// A string as int() reads it: surrounding whitespace, a sign, a prefix
+// matching the base (base 0 takes it from the prefix), and underscores
+// between digits.
+fn parse_int(text: &str, base: u32) -> Option<BigInt> {
+    let text = text.trim();
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let prefix = text.get(..2).map(str::to_ascii_lowercase);
+    let radix = match prefix.as_deref() {
+        Some("0x") => 16,
+        Some("0o") => 8,
+        Some("0b") => 2,
+        _ => 0,
+    };
+    let (base, digits) = if radix != 0 && (base == 0 || base == radix) {
+        // An underscore may follow the prefix.
+        (radix, text[2..].strip_prefix('_').unwrap_or(&text[2..]))
+    } else if base == 0 {
+        // Without a prefix, base 0 is decimal with no leading zeros.
+        if text.starts_with('0') && text.chars().any(|c| c != '0' && c != '_') {
+            return None;
+        }
+        (10, text)
+    } else {
+        (base, text)
+    };
+    if digits.split('_').any(str::is_empty) {
+        return None;
+    }
+    let mut value = BigInt::from_i64(0);
+    for c in digits.chars().filter(|&c| c != '_') {
+        let digit = c.to_digit(base)?;
+        value = value.mul(&BigInt::from_i64(base as i64)).add(&BigInt::from_i64(digit as i64));
+    }
+    Some(if negative { value.neg() } else { value })
+}
+
+// float() of a number or a string.
+fn to_float(value: &Value) -> Result<f64, PyError> {
+    match value {
+        Value::Float(f) => Ok(*f),
+        Value::Str(text) => parse_float(text).ok_or_else(|| {
+            PyError::new(
+                ErrorKind::ValueError,
+                format!("could not convert string to float: {}", value.repr()),
+            )
+        }),
+        _ if value.is_int() => match value.as_float() {
+            Some(f) if f.is_finite() => Ok(f),
+            _ => Err(PyError::new(ErrorKind::OverflowError, "int too large to convert to float")),
+        },
+        _ => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("float() argument must be a string or a real number, not '{}'", value.type_name()),
+        )),
+    }
+}
+
+// A string as float() reads it. Rust's parser takes the same forms, "inf"
+// and "nan" in any case included, except for underscores, which Python
+// allows between digits.
+fn parse_float(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let bytes = text.as_bytes();
+    for (i, &byte) in bytes.iter().enumerate() {
+        let between_digits = i > 0 && bytes[i - 1].is_ascii_digit() && bytes.get(i + 1).is_some_and(u8::is_ascii_digit);
+        if byte == b'_' && !between_digits {
+            return None;
+        }
+    }
+    text.replace('_', "").parse().ok()
+}
+
+fn no_keywords(name: &str, kwargs: &[(String, Value)]) -> Result<(), PyError> {
+    if kwargs.is_empty() {
+        Ok(())
+    } else {
+        Err(PyError::new(ErrorKind::TypeError, format!("{}() takes no keyword arguments", name)))
+    }
+}
+
+// Checks the number of positional arguments, with CPython's wording.
+fn expect_args(name: &str, args: &[Value], min: usize, max: usize) -> Result<(), PyError> {
+    let count = args.len();
+    if (min..=max).contains(&count) {
+        return Ok(());
+    }
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let message = match (min == max, min) {
+        (true, 0) => format!("{}() takes no arguments ({} given)", name, count),
+        (true, 1) => format!("{}() takes exactly one argument ({} given)", name, count),
+        (true, _) => format!("{} expected {} arguments, got {}", name, min, count),
+        _ if count < min => format!("{} expected at least {} argument{}, got {}", name, min, plural(min), count),
+        _ => format!("{} expected at most {} argument{}, got {}", name, max, plural(max), count),
+    };
+    Err(PyError::new(ErrorKind::TypeError, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{call_builtin, lookup};
+    use crate::ast::Value;
+    use crate::error::Span;
+    use crate::evaluator::Environment;
+
+    fn call(name: &str, args: Vec<Value>) -> Result<String, String> {
+        let Some(Value::Builtin(builtin)) = lookup(name) else { panic!("{} is not a builtin", name) };
+        let span = Span::new(1, 0, 0);
+        let result = call_builtin(&builtin, args, Vec::new(), span, &mut Environment::new(), &mut Vec::new());
+        result.map(|value| value.repr()).map_err(|error| error.message)
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    #[test]
+    fn types_work_with_isinstance() {
+        let isinstance = |value: Value, name: &str| call("isinstance", vec![value, lookup(name).unwrap()]);
+        assert_eq!(isinstance(Value::Int(3), "int"), Ok("True".to_string()));
+        assert_eq!(isinstance(Value::Bool(true), "int"), Ok("True".to_string()));
+        assert_eq!(isinstance(Value::Int(3), "bool"), Ok("False".to_string()));
+        assert_eq!(isinstance(Value::Float(1.5), "float"), Ok("True".to_string()));
+        assert_eq!(isinstance(text("s"), "str"), Ok("True".to_string()));
+        assert_eq!(isinstance(Value::new_list(Vec::new()), "dict"), Ok("False".to_string()));
+        let types = vec![lookup("bool").unwrap(), lookup("int").unwrap()];
+        assert_eq!(call("issubclass", types), Ok("True".to_string()));
+    }
+
+    // Each expected result is what CPython gives.
+    #[test]
+    fn conversions_match_cpython() {
+        assert_eq!(call("int", vec![Value::Float(-2.7)]), Ok("-2".to_string()));
+        assert_eq!(call("int", vec![Value::Float(1e20)]), Ok("100000000000000000000".to_string()));
+        assert_eq!(call("int", vec![text(" -1_000\n")]), Ok("-1000".to_string()));
+        assert_eq!(call("int", vec![text("0x_1f"), Value::Int(0)]), Ok("31".to_string()));
+        assert_eq!(call("int", vec![text("z"), Value::Int(36)]), Ok("35".to_string()));
+        let error = |message: &str| Err(message.to_string());
+        assert_eq!(call("int", vec![text("010"), Value::Int(0)]), error("invalid literal for int() with base 0: '010'"));
+        assert_eq!(call("int", vec![text("1__0")]), error("invalid literal for int() with base 10: '1__0'"));
+        assert_eq!(call("int", vec![Value::Float(f64::NAN)]), error("cannot convert float NaN to integer"));
+        let explicit = error("int() can't convert non-string with explicit base");
+        assert_eq!(call("int", vec![Value::Float(1.5), Value::Int(10)]), explicit);
+        assert_eq!(call("float", vec![text(" 1_0.5 ")]), Ok("10.5".to_string()));
+        assert_eq!(call("float", vec![text("-Infinity")]), Ok("-inf".to_string()));
+        assert_eq!(call("float", vec![text("_1")]), error("could not convert string to float: '_1'"));
+        assert_eq!(call("str", vec![Value::Float(1.5)]), Ok("'1.5'".to_string()));
+        assert_eq!(call("bool", vec![text("")]), Ok("False".to_string()));
+        assert_eq!(call("dict", Vec::new()), Ok("{}".to_string()));
+    }
+}
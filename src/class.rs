@@ -0,0 +1,200 @@
+use crate::ast::{Builtin, Function, Value};
+use crate::error::{ErrorKind, PyError};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// A class created by a `class` statement, or the built-in `object` that every
+// class ultimately derives from.
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub qualname: String,
+    pub module: &'static str,
+    pub bases: Vec<Rc<Class>>,
+    // The method resolution order after the class itself.
+    ancestors: Vec<Rc<Class>>,
+    pub attributes: RefCell<HashMap<String, Value>>,
+}
+
+thread_local! {
+    static OBJECT: Rc<Class> = Rc::new(Class {
+        name: "object".to_string(),
+        qualname: "object".to_string(),
+        module: "builtins",
+        bases: Vec::new(),
+        ancestors: Vec::new(),
+        attributes: RefCell::new(HashMap::from([(
+            "__init__".to_string(),
+            Value::Builtin(Rc::new(Builtin { name: "__init__".to_string(), receiver: None })),
+        )])),
+    });
+}
+
+pub fn object_class() -> Rc<Class> {
+    OBJECT.with(Rc::clone)
+}
+
+impl Class {
+    // A class deriving from `bases` (from object when there are none), with
+    // the names bound by its body as attributes.
+    pub fn new(
+        name: &str,
+        qualname: &str,
+        mut bases: Vec<Rc<Class>>,
+        mut attributes: HashMap<String, Value>,
+    ) -> Result<Rc<Class>, PyError> {
+        // Equal objects must hash alike, so overriding `__eq__` alone makes
+        // instances unhashable.
+        if attributes.contains_key("__eq__") && !attributes.contains_key("__hash__") {
+            attributes.insert("__hash__".to_string(), Value::None);
+        }
+        if bases.is_empty() {
+            bases.push(object_class());
+        }
+        for (i, base) in bases.iter().enumerate() {
+            if bases[..i].iter().any(|other| Rc::ptr_eq(other, base)) {
+                return Err(PyError::new(ErrorKind::TypeError, format!("duplicate base class {}", base.name)));
+            }
+        }
+        let ancestors = linearize(&bases)?;
+        Ok(Rc::new(Class {
+            name: name.to_string(),
+            qualname: qualname.to_string(),
+            module: "__main__",
+            bases,
+            ancestors,
+            attributes: RefCell::new(attributes),
+        }))
+    }
+
+    // A built-in class with a single base, such as the exception types.
+    pub fn builtin(name: &str, base: Rc<Class>) -> Rc<Class> {
+        Rc::new(Class {
+            name: name.to_string(),
+            qualname: name.to_string(),
+            module: "builtins",
+            ancestors: base.mro(),
+            bases: vec![base],
+            attributes: RefCell::new(HashMap::new()),
+        })
+    }
+
+    // The class followed by its ancestors, in the order attributes are looked up.
+    pub fn mro(self: &Rc<Self>) -> Vec<Rc<Class>> {
+        let mut mro = vec![Rc::clone(self)];
+        mro.extend(self.ancestors.iter().cloned());
+        mro
+    }
+
+    // Looks an attribute up on the class, then along its MRO.
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        if let Some(value) = self.attributes.borrow().get(name) {
+            return Some(value.clone());
+        }
+        self.ancestors.iter().find_map(|class| class.attributes.borrow().get(name).cloned())
+    }
+
+    // Looks an attribute up along the MRO, skipping everything up to and
+    // including `after`, as super() does.
+    pub fn lookup_after(self: &Rc<Self>, after: &Rc<Class>, name: &str) -> Option<Value> {
+        self.mro()
+            .iter()
+            .skip_while(|class| !Rc::ptr_eq(class, after))
+            .skip(1)
+            .find_map(|class| class.attributes.borrow().get(name).cloned())
+    }
+
+    pub fn is_subclass(self: &Rc<Self>, other: &Rc<Class>) -> bool {
+        Rc::ptr_eq(self, other) || self.ancestors.iter().any(|class| Rc::ptr_eq(class, other))
+    }
+
+    // Once the class exists, the functions defined in its body learn which
+    // class they belong to, for zero-argument super().
+    pub fn adopt_methods(self: &Rc<Self>) {
+        for value in self.attributes.borrow().values() {
+            if let Value::Function(function) = value {
+                if function.class.borrow().upgrade().is_none() {
+                    *function.class.borrow_mut() = Rc::downgrade(self);
+                }
+            }
+        }
+    }
+}
+
+// C3 linearization: merges the MROs of the bases and the list of bases
+// itself, always taking the first head that doesn't appear in the tail of
+// another list, so every class precedes its bases and the bases keep their order.
+fn linearize(bases: &[Rc<Class>]) -> Result<Vec<Rc<Class>>, PyError> {
+    let mut sequences: Vec<Vec<Rc<Class>>> = bases.iter().map(Class::mro).collect();
+    sequences.push(bases.to_vec());
+    let mut order = Vec::new();
+    loop {
+        sequences.retain(|sequence| !sequence.is_empty());
+        if sequences.is_empty() {
+            return Ok(order);
+        }
+        let in_tail = |class: &Rc<Class>| {
+            sequences.iter().any(|sequence| sequence[1..].iter().any(|other| Rc::ptr_eq(other, class)))
+        };
+        let Some(next) = sequences.iter().map(|sequence| &sequence[0]).find(|head| !in_tail(head)).cloned() else {
+            let mut heads: Vec<&Rc<Class>> = Vec::new();
+            for sequence in &sequences {
+                if !heads.iter().any(|head| Rc::ptr_eq(head, &sequence[0])) {
+                    heads.push(&sequence[0]);
+                }
+            }
+            let names: Vec<&str> = heads.iter().map(|class| class.name.as_str()).collect();
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("Cannot create a consistent method resolution\norder (MRO) for bases {}", names.join(", ")),
+            ));
+        };
+        for sequence in &mut sequences {
+            if Rc::ptr_eq(&sequence[0], &next) {
+                sequence.remove(0);
+            }
+        }
+        order.push(next);
+    }
+}
+
+// An object created by calling a class.
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub attributes: RefCell<HashMap<String, Value>>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self { class, attributes: RefCell::new(HashMap::new()) }
+    }
+}
+
+// A function looked up on an instance, which is passed as its first argument.
+#[derive(Debug)]
+pub struct Method {
+    pub function: Rc<Function>,
+    pub receiver: Value,
+}
+
+// What super() returns: looks attributes up along the MRO of the object's
+// class, starting after `class`. The object is an instance, or a class for
+// `super(cls, subclass)`.
+#[derive(Debug)]
+pub struct Super {
+    pub class: Rc<Class>,
+    pub object: Value,
+}
+
+impl Super {
+    // The class whose MRO is searched.
+    pub fn object_class(&self) -> Rc<Class> {
+        match &self.object {
+            Value::Instance(instance) => Rc::clone(&instance.class),
+            Value::Class(class) => Rc::clone(class),
+            _ => unreachable!("super() checks its object"),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::ast::Value;
+use crate::error::PyError;
+use crate::operators::{hash_value, values_equal};
+use std::collections::HashMap;
+
+// An insertion-ordered map keyed by Python values. Entries are kept in a
+// vector in insertion order (a removed entry leaves a hole until the next
+// compaction), and `index` maps each key hash to the positions holding it, so
+// keys that are equal in Python (1, 1.0 and True) find the same entry. Each
+// entry keeps its key's hash, which may have come from `__hash__`.
+#[derive(Debug, Default, Clone)]
+pub struct Dict {
+    entries: Vec<Option<(i64, Value, Value)>>,
+    index: HashMap<i64, Vec<usize>>,
+    len: usize,
+}
+
+impl Dict {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn find(&self, key: &Value, hash: i64) -> Option<usize> {
+        self.index.get(&hash)?.iter().copied().find(|&position| {
+            matches!(&self.entries[position], Some((_, existing, _)) if values_equal(existing, key))
+        })
+    }
+
+    // The keys stored under a hash, with their positions, for callers that
+    // compare keys through `__eq__` (which may change the dict meanwhile).
+    pub fn candidates(&self, hash: i64) -> Vec<(usize, Value)> {
+        let Some(positions) = self.index.get(&hash) else { return Vec::new() };
+        positions
+            .iter()
+            .filter_map(|&position| self.entries[position].as_ref().map(|(_, key, _)| (position, key.clone())))
+            .collect()
+    }
+
+    pub fn value_at(&self, position: usize) -> Value {
+        self.entries[position].as_ref().map(|(_, _, value)| value.clone()).unwrap()
+    }
+
+    pub fn replace_at(&mut self, position: usize, value: Value) {
+        if let Some((_, _, existing)) = &mut self.entries[position] {
+            *existing = value;
+        }
+    }
+
+    // Adds an entry for a key known to be absent.
+    pub fn push(&mut self, hash: i64, key: Value, value: Value) {
+        self.index.entry(hash).or_default().push(self.entries.len());
+        self.entries.push(Some((hash, key, value)));
+        self.len += 1;
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<Value>, PyError> {
+        let hash = hash_value(key)?;
+        Ok(self.find(key, hash).map(|position| self.value_at(position)))
+    }
+
+    pub fn contains_key(&self, key: &Value) -> Result<bool, PyError> {
+        let hash = hash_value(key)?;
+        Ok(self.find(key, hash).is_some())
+    }
+
+    // Replacing the value of an existing key keeps the original key object
+    // and its position, as Python does.
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), PyError> {
+        let hash = hash_value(&key)?;
+        match self.find(&key, hash) {
+            Some(position) => self.replace_at(position, value),
+            None => self.push(hash, key, value),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Result<Option<Value>, PyError> {
+        let hash = hash_value(key)?;
+        Ok(self.find(key, hash).map(|position| self.remove_at(position)))
+    }
+
+    pub fn remove_at(&mut self, position: usize) -> Value {
+        let (hash, _, value) = self.entries[position].take().unwrap();
+        self.len -= 1;
+        if self.entries.len() > 2 * self.len + 8 {
+            self.compact();
+        } else if let Some(positions) = self.index.get_mut(&hash) {
+            positions.retain(|&p| p != position);
+        }
+        value
+    }
+
+    // Drops the holes left by removals and rebuilds the index to match.
+    fn compact(&mut self) {
+        let entries: Vec<_> = self.entries.drain(..).flatten().collect();
+        self.index.clear();
+        for (position, (hash, _, _)) in entries.iter().enumerate() {
+            self.index.entry(*hash).or_default().push(position);
+        }
+        self.entries = entries.into_iter().map(Some).collect();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.entries.iter().flatten().map(|(_, key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> Vec<Value> {
+        self.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        self.iter().map(|(_, value)| value.clone()).collect()
+    }
+
+    // The (key, value) pairs as tuples.
+    pub fn items(&self) -> Vec<Value> {
+        self.iter().map(|(key, value)| Value::new_tuple(vec![key.clone(), value.clone()])).collect()
+    }
+}
@@ -0,0 +1,327 @@
+use crate::ast::Value;
+use crate::lexer::lex;
+use crate::token::Token;
+use std::fmt;
+
+// A location in the source: a 1-based line and a byte range within that line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, start: usize, end: usize) -> Self {
+        Self { line, start, end }
+    }
+
+    // All of a line, for errors located only by the statement that raised them.
+    pub fn whole_line(line: usize) -> Span {
+        Span::new(line, 0, usize::MAX)
+    }
+
+    // The smallest span covering both; spans on different lines keep the first line.
+    pub fn to(self, other: Span) -> Span {
+        if other.line == self.line {
+            Span::new(self.line, self.start.min(other.start), self.end.max(other.end))
+        } else {
+            self
+        }
+    }
+}
+
+// The built-in exception types, which user-defined exceptions derive from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    BaseException,
+    Exception,
+    ArithmeticError,
+    LookupError,
+    AssertionError,
+    ImportError,
+    NotImplementedError,
+    SyntaxError,
+    IndentationError,
+    NameError,
+    UnboundLocalError,
+    TypeError,
+    ValueError,
+    IndexError,
+    KeyError,
+    MemoryError,
+    AttributeError,
+    ModuleNotFoundError,
+    ZeroDivisionError,
+    OverflowError,
+    RecursionError,
+    RuntimeError,
+    StopIteration,
+    GeneratorExit,
+    OSError,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+// A traceback entry: where a function, or the module, was running when an
+// exception passed through it.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct PyError {
+    pub kind: ErrorKind, // for a user-defined exception, the built-in type it derives from
+    pub message: String,
+    pub span: Option<Span>, // where the error is in the frame it has reached
+    pub traceback: Vec<Frame>, // the frames it has left, outermost first
+    // The exception object, once there is one: the value raised, or the one
+    // made for the error when it is caught.
+    pub exception: Option<Value>,
+}
+
+impl PyError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into(), span: None, traceback: Vec::new(), exception: None }
+    }
+
+    // Attaches a location unless a more precise one was already recorded. A
+    // re-raised exception keeps the traceback it was caught with instead.
+    pub fn at(mut self, span: Span) -> Self {
+        if self.traceback.is_empty() {
+            self.span.get_or_insert(span);
+        }
+        self
+    }
+
+    // Moves the error's location in the frame it has reached, the one running
+    // `function`, to the front of its traceback.
+    pub fn unwind(&mut self, function: &str) {
+        if let Some(span) = self.span.take() {
+            self.traceback.insert(0, Frame { function: function.to_string(), span });
+        }
+    }
+
+    // The error passing out of a call to `function`, back to the caller's frame.
+    pub fn leave_frame(mut self, function: &str, call: Span) -> Self {
+        self.unwind(function);
+        self.span = Some(call);
+        self
+    }
+
+    // Formats the error CPython-style, quoting the offending line with a caret
+    // underline when the location is known.
+    pub fn render(&self, filename: &str, source: &str) -> String {
+        let mut out = String::new();
+        if let Some(span) = self.span {
+            out.push_str(&format!("  File \"{}\", line {}\n", filename, span.line));
+            if let Some(line) = source.lines().nth(span.line - 1) {
+                let indent = line.len() - line.trim_start().len();
+                let start = span.start.clamp(indent, line.len());
+                let end = span.end.clamp(start, line.len());
+                let offset = line[indent..start].chars().count();
+                let width = line[start..end].chars().count().max(1);
+                out.push_str(&format!("    {}\n", line.trim()));
+                out.push_str(&format!("    {}{}\n", " ".repeat(offset), "^".repeat(width)));
+            }
+        }
+        out.push_str(&self.to_string());
+        out
+    }
+}
+
+// Repeats of the same traceback line beyond this many are summarized.
+const RECURSIVE_CUTOFF: usize = 3;
+
+// Formats a traceback as CPython prints it, most recent call last. Source
+// lines are quoted only when the file can be read back, as with a script.
+pub fn render_traceback(frames: &[Frame], filename: &str, source: &str) -> String {
+    let mut out = String::from("Traceback (most recent call last):\n");
+    let mut last: Option<(usize, &str)> = None;
+    let mut repeats = 0;
+    for frame in frames {
+        if last != Some((frame.span.line, frame.function.as_str())) {
+            summarize_repeats(&mut out, repeats);
+            last = Some((frame.span.line, frame.function.as_str()));
+            repeats = 0;
+        }
+        repeats += 1;
+        if repeats > RECURSIVE_CUTOFF {
+            continue;
+        }
+        out.push_str(&format!("  File \"{}\", line {}, in {}\n", filename, frame.span.line, frame.function));
+        if !filename.starts_with('<') {
+            quote_line(&mut out, frame.span, source);
+        }
+    }
+    summarize_repeats(&mut out, repeats);
+    out
+}
+
+fn summarize_repeats(out: &mut String, repeats: usize) {
+    if repeats > RECURSIVE_CUTOFF {
+        let more = repeats - RECURSIVE_CUTOFF;
+        out.push_str(&format!("  [Previous line repeated {} more time{}]\n", more, if more == 1 { "" } else { "s" }));
+    }
+}
+
+// A traceback entry's source line, marked under the failing expression unless
+// that is the whole line. Binary operations and subscripts are marked with `~`,
+// and `^` under the operator or the index.
+fn quote_line(out: &mut String, span: Span, source: &str) {
+    let Some(line) = source.lines().nth(span.line - 1) else { return };
+    let line = line.trim_end();
+    let indent = line.len() - line.trim_start().len();
+    let start = span.start.clamp(indent, line.len());
+    let end = span.end.clamp(start, line.len());
+    out.push_str(&format!("    {}\n", line.trim_start()));
+    let segment = &line[start..end];
+    let anchors = caret_anchors(segment);
+    if start == indent && end == line.len() && anchors.is_none() {
+        return;
+    }
+    let marks: String = segment
+        .char_indices()
+        .map(|(i, _)| match anchors {
+            Some((left, right)) if !(left..right).contains(&i) => '~',
+            _ => '^',
+        })
+        .collect();
+    out.push_str(&format!("    {}{}\n", " ".repeat(line[indent..start].chars().count()), marks));
+}
+
+// Binding strength of the binary operators, loosest first.
+fn binary_precedence(token: &Token) -> Option<u8> {
+    let precedence = match token {
+        Token::Pipe => 1,
+        Token::Caret => 2,
+        Token::Ampersand => 3,
+        Token::LeftShift | Token::RightShift => 4,
+        Token::Plus | Token::Minus => 5,
+        Token::Star | Token::Slash | Token::DoubleSlash | Token::Percent => 6,
+        Token::DoubleStar => POWER_PRECEDENCE,
+        _ => return None,
+    };
+    Some(precedence)
+}
+
+// How tightly a leading `-`, `+` or `~` binds, between `*` and `**`.
+const UNARY_PRECEDENCE: u8 = 7;
+const POWER_PRECEDENCE: u8 = 8;
+
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Identifier(_)
+            | Token::Number(_)
+            | Token::BigNumber(_)
+            | Token::Float(_)
+            | Token::StringLiteral(_)
+            | Token::Bytes(_)
+            | Token::FString(_)
+            | Token::True
+            | Token::False
+            | Token::None
+            | Token::CloseParen
+            | Token::CloseBracket
+            | Token::CloseBrace
+    )
+}
+
+// The byte range of a segment that CPython marks with `^` when the segment
+// is a binary operation (the operator) or a subscript (from the end of the
+// value through the end of the index). Like CPython 3.11, operands end before
+// any parentheses around them.
+fn caret_anchors(segment: &str) -> Option<(usize, usize)> {
+    let tokens: Vec<_> = lex(segment)
+        .ok()?
+        .into_iter()
+        .filter(|token| !matches!(token.token, Token::Newline | Token::Indent | Token::Dedent))
+        .collect();
+    // The depth of each token within brackets, and where each bracket closes.
+    let mut depths = Vec::with_capacity(tokens.len());
+    let mut closing = vec![None; tokens.len()];
+    let mut open = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.token {
+            Token::OpenParen | Token::OpenBracket | Token::OpenBrace => {
+                depths.push(open.len());
+                open.push(i);
+            }
+            Token::CloseParen | Token::CloseBracket | Token::CloseBrace => {
+                closing[open.pop()?] = Some(i);
+                depths.push(open.len());
+            }
+            _ => depths.push(open.len()),
+        }
+    }
+    // The binary operators outside brackets; anything else there but operands,
+    // attribute access and unary operators means neither form applies.
+    let mut operators = Vec::new();
+    for (i, token) in tokens.iter().enumerate().filter(|(i, _)| depths[*i] == 0) {
+        let follows_operand = i > 0 && ends_operand(&tokens[i - 1].token);
+        match binary_precedence(&token.token) {
+            Some(precedence) if follows_operand => operators.push((precedence, i)),
+            _ if matches!(token.token, Token::Minus | Token::Plus | Token::Tilde) && !follows_operand => {}
+            _ if ends_operand(&token.token) => {}
+            _ if matches!(token.token, Token::Dot | Token::OpenParen | Token::OpenBracket | Token::OpenBrace) => {}
+            _ => return None,
+        }
+    }
+    let unary = matches!(tokens.first()?.token, Token::Minus | Token::Plus | Token::Tilde);
+    // Where an operand starts and ends, inside any parentheses wrapping all of it.
+    let operand = |mut first: usize, mut last: usize| {
+        while tokens[first].token == Token::OpenParen && closing[first] == Some(last) && first + 1 < last {
+            first += 1;
+            last -= 1;
+        }
+        (tokens[first].span.start, tokens[last].span.end)
+    };
+    let after_whitespace = |mut i: usize| {
+        while segment[i..].starts_with(char::is_whitespace) {
+            i += 1;
+        }
+        i
+    };
+    if let Some(&(loosest, _)) = operators.iter().min_by_key(|(precedence, _)| *precedence) {
+        if unary && loosest > UNARY_PRECEDENCE {
+            return None;
+        }
+        // Operators of a level group to the left, except `**`.
+        let mut level = operators.iter().filter(|(precedence, _)| *precedence == loosest);
+        let (_, root) = if loosest == POWER_PRECEDENCE { level.next()? } else { level.next_back()? };
+        if root + 1 == tokens.len() {
+            return None;
+        }
+        let left = after_whitespace(operand(0, root - 1).1);
+        let (right, _) = operand(root + 1, tokens.len() - 1);
+        let two_characters = left + 1 < right && !segment[left + 1..].starts_with(char::is_whitespace);
+        return Some((left, left + if two_characters { 2 } else { 1 }));
+    }
+    let last = tokens.len() - 1;
+    if unary || tokens[last].token != Token::CloseBracket {
+        return None;
+    }
+    let bracket = closing.iter().position(|close| *close == Some(last))?;
+    if bracket == 0 || bracket + 1 == last {
+        return None;
+    }
+    Some((operand(0, bracket - 1).1, operand(bracket + 1, last - 1).1 + 1))
+}
+
+impl fmt::Display for PyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Exceptions raised without a message print just their type.
+        if self.message.is_empty() {
+            write!(f, "{}", self.kind)
+        } else {
+            write!(f, "{}: {}", self.kind, self.message)
+        }
+    }
+}
@@ -1,120 +1,1142 @@
-use crate::ast::{Expr, Value};
+use crate::ast::{Cell, ExceptHandler, Expr, Function, Slice, Value};
+use crate::builtins::{self, call_builtin, get_attribute};
+use crate::class::{Class, Instance};
+use crate::dict::Dict;
+use crate::error::{ErrorKind, PyError, Span};
+use crate::exception;
+use crate::generator::{self, Generator};
+use crate::scope::Scope;
+use crate::special;
+use crate::set::Set;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io::Write;
+use std::mem;
+use std::rc::{Rc, Weak};
 
-pub fn evaluate<W: Write>(
-    expr: &Expr,
-    variables: &mut HashMap<String, Value>,
-    writer: &mut W,
-) -> Option<Value> {
+// Whether CPython's compiler would fold an expression to a constant.
+fn is_foldable_constant(expr: &Expr) -> bool {
     match expr {
-        Expr::Number(val) => Some(Value::Number(*val as f64)),
-        Expr::Float(val) => Some(Value::Number(*val)),
-        Expr::String(s) => Some(Value::Str(s.clone())),
-        Expr::Variable(name) => variables.get(name).cloned(),
-        Expr::Assignment(var, value) => {
-            if let Some(result) = evaluate(value, variables, writer) {
-                variables.insert(var.clone(), result.clone());
-                Some(result)
-            } else {
-                None
+        Expr::Number(_) | Expr::BigNumber(_) | Expr::Float(_) | Expr::String(_) | Expr::Bytes(_) => true,
+        Expr::Bool(_) | Expr::None => true,
+        Expr::Arithmetic { left, right, .. } => is_foldable_constant(left) && is_foldable_constant(right),
+        Expr::UnaryOp { operand, .. } => is_foldable_constant(operand),
+        Expr::Tuple(elements) => elements.iter().all(is_foldable_constant),
+        _ => false,
+    }
+}
+
+// Nested calls allowed before raising RecursionError, mirroring CPython's default.
+const MAX_CALL_DEPTH: usize = 1000;
+
+// The variables visible to running code: the module globals, plus the local
+// scope of the function or class body currently executing (if any).
+pub struct Environment {
+    pub globals: HashMap<String, Value>,
+    locals: Option<Locals>,
+    function: Option<Rc<Function>>, // the function whose body is running, if any
+    depth: usize,
+    modules: HashMap<String, Value>, // imported modules, loaded once each
+    argv: Vec<String>,
+    handling: Vec<Value>,       // the exceptions whose handlers are running, innermost last
+    class_body: Option<String>, // the class whose body is running, if any
+}
+
+// The local variables of a running function or class body. Those shared with
+// nested functions are kept in cells, as are the enclosing functions'
+// variables that it uses.
+#[derive(Default)]
+pub struct Locals {
+    values: HashMap<String, Value>,
+    cells: HashMap<String, Cell>,
+    scope: Rc<Scope>,
+}
+
+impl Locals {
+    // The local scope of a call to `function`, starting with `values` bound.
+    pub fn new(function: &Function, mut values: HashMap<String, Value>) -> Self {
+        let mut cells = function.closure.clone();
+        for name in &function.scope.cells {
+            cells.insert(name.clone(), Rc::new(RefCell::new(values.remove(name))));
+        }
+        Self { values, cells, scope: Rc::clone(&function.scope) }
+    }
+
+    // The cell a name is kept in, if any. A class body's own names are never
+    // in cells, even those shared by functions nested in it.
+    fn cell(&self, name: &str) -> Option<&Cell> {
+        let cell = self.cells.get(name)?;
+        (self.scope.cells.contains(name) || !self.scope.locals.contains(name)).then_some(cell)
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.cell(name)?.borrow().clone(),
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::with_argv(vec![String::new()])
+    }
+
+    pub fn with_argv(argv: Vec<String>) -> Self {
+        Self {
+            globals: HashMap::new(),
+            locals: None,
+            function: None,
+            depth: 0,
+            modules: HashMap::new(),
+            argv,
+            handling: Vec::new(),
+            class_body: None,
+        }
+    }
+
+    // The local scope a name belongs to: none at module level or for a name
+    // declared global.
+    fn scope_of(&self, name: &str) -> Option<&Locals> {
+        self.locals.as_ref().filter(|locals| !locals.scope.globals.contains(name))
+    }
+
+    // The value of a name: a local variable, one of an enclosing function, a
+    // global or a builtin. A function's local names are never looked up further.
+    fn get(&self, name: &str) -> Option<Value> {
+        if let Some(locals) = self.scope_of(name) {
+            if let Some(value) = locals.values.get(name) {
+                return Some(value.clone());
+            }
+            if let Some(cell) = locals.cell(name) {
+                return cell.borrow().clone();
+            }
+            if self.function.is_some() && locals.scope.locals.contains(name) {
+                return None;
             }
         }
-        Expr::Arithmetic { left, operator, right } => {
-            let left_value = match evaluate(left, variables, writer)? {
-                Value::Number(n) => n,
-                _ => return None,
-            };
-            let right_value = match evaluate(right, variables, writer)? {
-                Value::Number(n) => n,
-                _ => return None,
+        self.globals.get(name).cloned().or_else(|| builtins::lookup(name))
+    }
+
+    // The error for using a name that `get` finds no value for.
+    fn unbound(&self, name: &str) -> PyError {
+        if let Some(locals) = self.scope_of(name) {
+            let is_local = locals.scope.locals.contains(name);
+            if locals.cell(name).is_some() && !is_local {
+                return PyError::new(
+                    ErrorKind::NameError,
+                    format!(
+                        "cannot access free variable '{}' where it is not associated with a value in enclosing scope",
+                        name
+                    ),
+                );
+            }
+            if self.function.is_some() && is_local {
+                return PyError::new(
+                    ErrorKind::UnboundLocalError,
+                    format!("cannot access local variable '{}' where it is not associated with a value", name),
+                );
+            }
+        }
+        PyError::new(ErrorKind::NameError, format!("name '{}' is not defined", name))
+    }
+
+    // Names bound inside a function are local to it unless declared global;
+    // at module level they are global.
+    fn set(&mut self, name: &str, value: Value) {
+        let locals = self.locals.as_mut().filter(|locals| !locals.scope.globals.contains(name));
+        match locals {
+            Some(locals) => match locals.cell(name) {
+                Some(cell) => *cell.borrow_mut() = Some(value),
+                None => {
+                    locals.values.insert(name.to_string(), value);
+                }
+            },
+            None => {
+                self.globals.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    // Unbinds a name from where `set` would bind it; false if it wasn't bound.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let locals = self.locals.as_mut().filter(|locals| !locals.scope.globals.contains(name));
+        match locals {
+            Some(locals) => match locals.cell(name) {
+                Some(cell) => cell.borrow_mut().take().is_some(),
+                None => locals.values.remove(name).is_some(),
+            },
+            None => self.globals.remove(name).is_some(),
+        }
+    }
+
+    // The cells of the running code's variables that a function or class
+    // defined in it uses.
+    fn capture(&self, scope: &Scope) -> HashMap<String, Cell> {
+        let Some(locals) = &self.locals else { return HashMap::new() };
+        scope.free.iter().filter_map(|name| Some((name.clone(), Rc::clone(locals.cells.get(name)?)))).collect()
+    }
+
+    // The name tracebacks show for the running frame.
+    pub fn frame_name(&self) -> String {
+        match (&self.function, &self.class_body) {
+            (Some(function), _) => function.name.clone(),
+            (None, Some(class)) => class.clone(),
+            (None, None) => "<module>".to_string(),
+        }
+    }
+
+    // Runs code in the frame of a call to `function`, with `locals` as its
+    // local scope; they are left as the code leaves them. Errors come out of
+    // the frame located at `call`.
+    pub fn in_frame<T>(
+        &mut self,
+        function: &Rc<Function>,
+        locals: &mut Locals,
+        call: Span,
+        run: impl FnOnce(&mut Self) -> Result<T, PyError>,
+    ) -> Result<T, PyError> {
+        // The module's own frame counts towards the limit too.
+        if self.depth + 1 >= MAX_CALL_DEPTH {
+            return Err(PyError::new(ErrorKind::RecursionError, "maximum recursion depth exceeded").at(call));
+        }
+        let caller_locals = self.locals.replace(mem::take(locals));
+        let caller_function = self.function.replace(Rc::clone(function));
+        self.depth += 1;
+        let result = run(self);
+        self.depth -= 1;
+        *locals = mem::replace(&mut self.locals, caller_locals).unwrap_or_default();
+        self.function = caller_function;
+        result.map_err(|e| e.leave_frame(&function.name, call))
+    }
+
+    // Runs code while an exception is being handled, as the context of any
+    // error it raises.
+    pub fn while_handling<T>(
+        &mut self,
+        exception: &Value,
+        run: impl FnOnce(&mut Self) -> Result<T, PyError>,
+    ) -> Result<T, PyError> {
+        self.handling.push(exception.clone());
+        let result = run(self);
+        self.handling.pop();
+        result.map_err(|mut error| {
+            exception::chain(&mut error, exception);
+            error
+        })
+    }
+
+    // What zero-argument super() stands for: the class defining the running
+    // method, and the method's first argument.
+    pub fn super_arguments(&self) -> Result<(Rc<Class>, Value), PyError> {
+        let runtime_error = |message: &str| Err(PyError::new(ErrorKind::RuntimeError, message));
+        let Some(function) = &self.function else {
+            return runtime_error("super(): no arguments");
+        };
+        let Some(first) = function.params.first() else {
+            return runtime_error("super(): no arguments");
+        };
+        let Some(class) = function.class.borrow().upgrade() else {
+            return runtime_error("super(): __class__ cell not found");
+        };
+        match self.locals.as_ref().and_then(|locals| locals.get(first)) {
+            Some(object) => Ok((class, object)),
+            None => runtime_error("super(): arg[0] deleted"),
+        }
+    }
+}
+
+// How a statement finished: normally, by a `return` that unwinds through
+// enclosing blocks and loops to the function call, or by a `break` or
+// `continue` that unwinds to the innermost loop.
+pub enum Flow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue,
+}
+
+pub fn execute<W: Write>(
+    stmt: &Expr,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Flow, PyError> {
+    // An error not located more precisely is reported on the statement's line.
+    let line = statement_line(stmt);
+    let result = execute_statement(stmt, env, writer).map_err(|error| match line {
+        Some(line) => error.at(Span::whole_line(line)),
+        None => error,
+    });
+    // Generators the statement let go of, like that of a loop it broke out of.
+    generator::close_dropped(Span::whole_line(line.unwrap_or(0)), env, writer);
+    result
+}
+
+pub fn statement_line(stmt: &Expr) -> Option<usize> {
+    match stmt {
+        Expr::Assignment(_, _, span)
+        | Expr::AugmentedAssignment { span, .. }
+        | Expr::Print(_, span)
+        | Expr::ForLoop { span, .. }
+        | Expr::WhileLoop { span, .. }
+        | Expr::ClassDef { span, .. }
+        | Expr::IfCondition { span, .. }
+        | Expr::Return(_, span)
+        | Expr::Import(_, span)
+        | Expr::Delete(_, span)
+        | Expr::Raise { span, .. }
+        | Expr::Bare(_, span) => Some(span.line),
+        _ => None,
+    }
+}
+
+fn execute_statement<W: Write>(
+    stmt: &Expr,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Flow, PyError> {
+    match stmt {
+        Expr::Assignment(target, value, span) => {
+            let result = evaluate(value, env, writer)?;
+            assign(target, result, *span, env, writer)?;
+        }
+        Expr::AugmentedAssignment { target, operator, value, span } => {
+            augmented_assign(target, operator, value, *span, env, writer)?;
+        }
+        Expr::Print(args, span) => {
+            // All the arguments are evaluated before any is converted to a string.
+            let values = args.iter().map(|arg| evaluate(arg, env, writer)).collect::<Result<Vec<_>, _>>()?;
+            let mut parts = Vec::new();
+            for value in &values {
+                parts.push(special::to_str(value, *span, env, writer)?);
+            }
+            let output = parts.join(" ");
+            writeln!(writer, "{}", output).map_err(|e| PyError::new(ErrorKind::OSError, e.to_string()))?;
+        }
+        Expr::ForLoop { target, iterable, body, orelse, span } => {
+            let iterable = evaluate(iterable, env, writer)?;
+            return evaluate_for_loop(target, &iterable, body, orelse, *span, env, writer);
+        }
+        Expr::WhileLoop { condition, body, orelse, span } => {
+            return evaluate_while_loop(condition, body, orelse, *span, env, writer);
+        }
+        Expr::FunctionDef { name, qualname, params, body, is_generator, scope } => {
+            let function = Function {
+                name: name.clone(),
+                qualname: qualname.clone(),
+                params: params.clone(),
+                body: Rc::clone(body),
+                class: RefCell::new(Weak::new()),
+                is_generator: *is_generator,
+                scope: Rc::clone(scope),
+                closure: env.capture(scope),
             };
-            let result = match operator.as_str() {
-                "-" => left_value - right_value,
-                "+" => left_value + right_value,
-                "*" => left_value * right_value,
-                "/" => left_value / right_value, // returns decimals
-                "//" => (left_value / right_value).floor(), // floor division
-                "**" => left_value.powf(right_value),
-                ">"  => if left_value > right_value { 1.0 } else { 0.0 },
-                "<"  => if left_value < right_value { 1.0 } else { 0.0 },
-                _ => 0.0,
+            env.set(name, Value::Function(Rc::new(function)));
+        }
+        Expr::ClassDef { name, qualname, bases, body, scope, span } => {
+            let mut base_classes = Vec::with_capacity(bases.len());
+            for base in bases {
+                match evaluate(base, env, writer)? {
+                    Value::Class(class) => base_classes.push(class),
+                    other => {
+                        return Err(PyError::new(
+                            ErrorKind::TypeError,
+                            format!("cannot create '{}' instances", other.type_name()),
+                        ))
+                    }
+                }
+            }
+            // The body runs in a scope of its own, which becomes the class's attributes.
+            let frame = Locals { values: HashMap::new(), cells: env.capture(scope), scope: Rc::clone(scope) };
+            let outer_locals = env.locals.replace(frame);
+            let outer_function = env.function.take();
+            let outer_class = env.class_body.replace(name.clone());
+            let result = execute_block(body, env, writer);
+            let namespace = mem::replace(&mut env.locals, outer_locals).unwrap_or_default().values;
+            env.function = outer_function;
+            env.class_body = outer_class;
+            result.map_err(|e| e.leave_frame(name, Span::whole_line(span.line)))?;
+            let class = Class::new(name, qualname, base_classes, namespace)?;
+            class.adopt_methods();
+            env.set(name, Value::Class(class));
+        }
+        Expr::IfCondition { condition, body, orelse, span } => {
+            let condition = evaluate(condition, env, writer)?;
+            // CPython reports a failing truth test on the statement's line.
+            if special::truthy(&condition, Span::whole_line(span.line), env, writer)? {
+                return execute_block(body, env, writer);
+            }
+            return execute_block(orelse, env, writer);
+        }
+        Expr::Return(value, _) => {
+            let value = match value {
+                Some(expr) => evaluate(expr, env, writer)?,
+                None => Value::None,
             };
-            Some(Value::Number(result))
+            return Ok(Flow::Return(value));
         }
-        Expr::Print(args) => {
-            let mut parts = Vec::new();
-            for arg in args {
-                if let Some(val) = evaluate(arg, variables, writer) {
-                    let s = match val {
-                        Value::Number(n) => n.to_string(),
-                        Value::Str(s) => s,
+        Expr::Pass | Expr::Global(..) | Expr::Nonlocal(..) => {}
+        Expr::Break => return Ok(Flow::Break),
+        Expr::Continue => return Ok(Flow::Continue),
+        Expr::Import(name, span) => {
+            let module = match env.modules.get(name) {
+                Some(module) => module.clone(),
+                None => {
+                    let Some(module) = builtins::import_module(name, &env.argv) else {
+                        return Err(PyError::new(
+                            ErrorKind::ModuleNotFoundError,
+                            format!("No module named '{}'", name),
+                        )
+                        .at(*span));
                     };
-                    parts.push(s);
+                    env.modules.insert(name.clone(), module.clone());
+                    module
                 }
+            };
+            env.set(name, module);
+        }
+        Expr::Delete(targets, _) => {
+            for target in targets {
+                delete(target, env, writer)?;
             }
-            let output = parts.join(" ");
-            writeln!(writer, "{}", output).unwrap();
-            None
         }
-        Expr::ForLoop { iterator, range_start, range_end, body } => {
-            if let Some(Value::Number(start)) = evaluate(range_start, variables, writer) {
-                if let Some(Value::Number(end)) = evaluate(range_end, variables, writer) {
-                    evaluate_for_loop(iterator, start, end, body, variables, writer);
+        Expr::Try { body, handlers, orelse, finalbody } => {
+            let result = match execute_block(body, env, writer) {
+                Ok(Flow::Normal) => execute_block(orelse, env, writer),
+                Err(error) => handle(error, handlers, env, writer),
+                flow => flow,
+            };
+            if finalbody.is_empty() {
+                return result;
+            }
+            return finish(result, finalbody, env, writer);
+        }
+        Expr::Raise { exception: raised, cause, span } => {
+            let Some(raised) = raised else {
+                // A bare `raise` re-raises the exception being handled, as it was caught.
+                return match env.handling.last() {
+                    Some(object) => Err(exception::raise(object.clone(), None)),
+                    None => Err(PyError::new(ErrorKind::RuntimeError, "No active exception to reraise").at(*span)),
+                };
+            };
+            let value = evaluate(raised, env, writer)?;
+            let object = exception_instance(value, "exceptions must derive from BaseException", *span, env, writer)?;
+            if let Some(cause) = cause {
+                let cause = match evaluate(cause, env, writer)? {
+                    Value::None => Value::None,
+                    value => {
+                        exception_instance(value, "exception causes must derive from BaseException", *span, env, writer)?
+                    }
+                };
+                exception::set_cause(&object, cause);
+            }
+            return Err(exception::raise(object, Some(*span)));
+        }
+        expr => {
+            evaluate(expr, env, writer)?;
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+// Runs the first handler of a `try` statement that matches the error raised in
+// its body, with the exception bound to the handler's name while it runs. The
+// error propagates when no handler matches.
+fn handle<W: Write>(
+    mut error: PyError,
+    handlers: &[ExceptHandler],
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Flow, PyError> {
+    let Some((index, object)) = find_handler(&mut error, handlers, env, writer)? else {
+        return Err(error);
+    };
+    let handler = &handlers[index];
+    let result = env.while_handling(&object, |env| execute_block(&handler.body, env, writer));
+    // The name is unbound once the handler is done.
+    if let Some(name) = &handler.name {
+        env.remove(name);
+    }
+    result
+}
+
+// Catches an error raised in the body of a `try` statement: the index of the
+// first handler that matches it, and the exception object, now bound to the
+// handler's name.
+pub fn find_handler<W: Write>(
+    error: &mut PyError,
+    handlers: &[ExceptHandler],
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<(usize, Value)>, PyError> {
+    // An error raised within another exception's handler has it as context,
+    // even when caught there.
+    if let Some(context) = env.handling.last() {
+        exception::chain(error, context);
+    }
+    let object = exception::catch(error, &env.frame_name());
+    // An error raised while matching the exception is chained to it.
+    let chained = |mut new: PyError| {
+        exception::chain(&mut new, &object);
+        new
+    };
+    for (index, handler) in handlers.iter().enumerate() {
+        if let Some(types) = &handler.types {
+            let types = evaluate(types, env, writer).map_err(chained)?;
+            let handles = exception::handles(&types, error).map_err(|e| chained(e.at(Span::whole_line(handler.span.line))))?;
+            if !handles {
+                continue;
+            }
+        }
+        if let Some(name) = &handler.name {
+            env.set(name, object.clone());
+        }
+        return Ok(Some((index, object)));
+    }
+    Ok(None)
+}
+
+// Runs the `finally` block of a `try` statement once the rest has finished
+// with `result`. A return, break or continue in the block replaces that
+// outcome, even a propagating error.
+fn finish<W: Write>(
+    result: Result<Flow, PyError>,
+    finalbody: &[Expr],
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Flow, PyError> {
+    let mut error = match result {
+        Ok(flow) => {
+            return match execute_block(finalbody, env, writer)? {
+                Flow::Normal => Ok(flow),
+                replaced => Ok(replaced),
+            }
+        }
+        Err(error) => error,
+    };
+    let object = exception::catch(&mut error, &env.frame_name());
+    match env.while_handling(&object, |env| execute_block(finalbody, env, writer))? {
+        Flow::Normal => Err(error),
+        flow => Ok(flow),
+    }
+}
+
+// The exception `raise value` raises: an exception instance, or a new
+// instance of an exception class.
+fn exception_instance<W: Write>(
+    value: Value,
+    message: &str,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    match value {
+        Value::Class(class) if exception::is_exception_class(&class) => {
+            instantiate(&class, Vec::new(), Vec::new(), span, env, writer)
+        }
+        value if exception::is_exception(&value) => Ok(value),
+        _ => Err(PyError::new(ErrorKind::TypeError, message).at(span)),
+    }
+}
+
+// Binds a value to the target of an assignment.
+pub fn assign<W: Write>(
+    target: &Expr,
+    value: Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<(), PyError> {
+    match target {
+        Expr::Variable(name, _) => {
+            env.set(name, value);
+            Ok(())
+        }
+        Expr::Subscript { value: container, index, span } => {
+            let container = evaluate(container, env, writer)?;
+            let index = evaluate(index, env, writer)?;
+            special::set_item(&container, &index, value, *span, env, writer)
+        }
+        Expr::Tuple(targets) | Expr::List(targets) => {
+            let items = special::items(&value, span, env, writer).map_err(|error| match error.kind {
+                ErrorKind::TypeError if error.span.is_none() => PyError::new(
+                    ErrorKind::TypeError,
+                    format!("cannot unpack non-iterable {} object", value.type_name()),
+                ),
+                _ => error,
+            })?;
+            for (target, item) in targets.iter().zip(unpack(targets, items)?) {
+                match target {
+                    Expr::Starred(target, _) => assign(target, item, span, env, writer)?,
+                    target => assign(target, item, span, env, writer)?,
                 }
             }
-            None
+            Ok(())
         }
-        Expr::WhileLoop { condition, body } => {
-            evaluate_while_loop(condition, body, variables, writer);
-            None
+        Expr::Attribute { value: object, name, span } => {
+            let object = evaluate(object, env, writer)?;
+            set_attribute(&object, name, value).map_err(|e| e.at(*span))
         }
-        Expr::FunctionDef { .. } => None,
-        Expr::IfCondition { condition, body } => {
-            if let Some(Value::Number(cond_val)) = evaluate(condition, variables, writer) {
-                if cond_val != 0.0 {
-                    for expr in body {
-                        evaluate(expr, variables, writer);
-                    }
+        _ => Err(PyError::new(ErrorKind::SyntaxError, "cannot assign to expression")),
+    }
+}
+
+// `object.name = value`: instances and classes take new attributes; the
+// attributes of built-in objects are read-only.
+fn set_attribute(object: &Value, name: &str, value: Value) -> Result<(), PyError> {
+    match object {
+        Value::Instance(instance) if instance.class.module != "builtins" || exception::is_exception(object) => {
+            instance.attributes.borrow_mut().insert(name.to_string(), value);
+            Ok(())
+        }
+        Value::Class(class) if class.module != "builtins" => {
+            class.attributes.borrow_mut().insert(name.to_string(), value);
+            Ok(())
+        }
+        Value::Instance(_) | Value::Class(_) => Err(PyError::new(
+            ErrorKind::AttributeError,
+            format!("'{}' object has no attribute '{}'", object.type_name(), name),
+        )),
+        _ => {
+            get_attribute(object, name)?;
+            Err(PyError::new(
+                ErrorKind::AttributeError,
+                format!("'{}' object attribute '{}' is read-only", object.type_name(), name),
+            ))
+        }
+    }
+}
+
+// `target op= value`: the container and index (or object) of the target are
+// evaluated once, and its current value before the right-hand side.
+fn augmented_assign<W: Write>(
+    target: &Expr,
+    operator: &str,
+    value: &Expr,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<(), PyError> {
+    match target {
+        Expr::Variable(name, _) => {
+            let current = evaluate(target, env, writer)?;
+            let value = evaluate(value, env, writer)?;
+            let result = special::inplace_op(operator, &current, &value, span, env, writer)?;
+            env.set(name, result);
+        }
+        Expr::Subscript { value: container, index, span: item_span } => {
+            let container = evaluate(container, env, writer)?;
+            let index = evaluate(index, env, writer)?;
+            let current = special::get_item(&container, &index, *item_span, env, writer)?;
+            let value = evaluate(value, env, writer)?;
+            let result = special::inplace_op(operator, &current, &value, span, env, writer)?;
+            special::set_item(&container, &index, result, span, env, writer)?;
+        }
+        Expr::Attribute { value: object, name, span: attribute_span } => {
+            let object = evaluate(object, env, writer)?;
+            let current = get_attribute(&object, name).map_err(|e| e.at(*attribute_span))?;
+            let value = evaluate(value, env, writer)?;
+            let result = special::inplace_op(operator, &current, &value, span, env, writer)?;
+            set_attribute(&object, name, result).map_err(|e| e.at(span))?;
+        }
+        _ => return Err(PyError::new(ErrorKind::SyntaxError, "illegal expression for augmented assignment")),
+    }
+    Ok(())
+}
+
+// Splits the values being unpacked into one per target; a starred target
+// collects whatever the others leave over into a list.
+fn unpack(targets: &[Expr], mut items: Vec<Value>) -> Result<Vec<Value>, PyError> {
+    let starred = targets.iter().position(|target| matches!(target, Expr::Starred(..)));
+    let Some(starred) = starred else {
+        if items.len() > targets.len() {
+            return Err(PyError::new(
+                ErrorKind::ValueError,
+                format!("too many values to unpack (expected {})", targets.len()),
+            ));
+        }
+        if items.len() < targets.len() {
+            return Err(PyError::new(
+                ErrorKind::ValueError,
+                format!("not enough values to unpack (expected {}, got {})", targets.len(), items.len()),
+            ));
+        }
+        return Ok(items);
+    };
+    let required = targets.len() - 1;
+    if items.len() < required {
+        return Err(PyError::new(
+            ErrorKind::ValueError,
+            format!("not enough values to unpack (expected at least {}, got {})", required, items.len()),
+        ));
+    }
+    let after = targets.len() - starred - 1;
+    let rest: Vec<Value> = items.drain(starred..items.len() - after).collect();
+    items.insert(starred, Value::new_list(rest));
+    Ok(items)
+}
+
+fn delete<W: Write>(target: &Expr, env: &mut Environment, writer: &mut W) -> Result<(), PyError> {
+    match target {
+        Expr::Variable(name, span) => {
+            if env.remove(name) {
+                Ok(())
+            } else {
+                Err(env.unbound(name).at(*span))
+            }
+        }
+        Expr::Subscript { value: container, index, span } => {
+            let container = evaluate(container, env, writer)?;
+            let index = evaluate(index, env, writer)?;
+            special::delete_item(&container, &index, *span, env, writer)
+        }
+        Expr::Attribute { value: object, name, span } => {
+            let object = evaluate(object, env, writer)?;
+            let removed = match &object {
+                Value::Instance(instance) => instance.attributes.borrow_mut().remove(name),
+                Value::Class(class) if class.module != "builtins" => class.attributes.borrow_mut().remove(name),
+                _ => {
+                    get_attribute(&object, name).map_err(|e| e.at(*span))?;
+                    return Err(PyError::new(
+                        ErrorKind::AttributeError,
+                        format!("'{}' object attribute '{}' is read-only", object.type_name(), name),
+                    )
+                    .at(*span));
+                }
+            };
+            match (removed, &object) {
+                (Some(_), _) => Ok(()),
+                (None, Value::Class(class)) => Err(PyError::new(
+                    ErrorKind::AttributeError,
+                    format!("type object '{}' has no attribute '{}'", class.name, name),
+                )
+                .at(*span)),
+                (None, _) => Err(PyError::new(
+                    ErrorKind::AttributeError,
+                    format!("'{}' object has no attribute '{}'", object.type_name(), name),
+                )
+                .at(*span)),
+            }
+        }
+        Expr::Tuple(targets) | Expr::List(targets) => {
+            for target in targets {
+                delete(target, env, writer)?;
+            }
+            Ok(())
+        }
+        _ => Err(PyError::new(ErrorKind::SyntaxError, "cannot delete expression")),
+    }
+}
+
+// Runs statements in order, stopping early at a return, break or continue.
+pub fn execute_block<W: Write>(
+    body: &[Expr],
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Flow, PyError> {
+    for stmt in body {
+        match execute(stmt, env, writer)? {
+            Flow::Normal => {}
+            flow => return Ok(flow),
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+pub fn evaluate<W: Write>(
+    expr: &Expr,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    match expr {
+        Expr::Number(val) => Ok(Value::Int(*val)),
+        Expr::BigNumber(val) => Ok(Value::BigInt(Rc::clone(val))),
+        Expr::Float(val) => Ok(Value::Float(*val)),
+        Expr::String(s) => Ok(Value::Str(s.clone())),
+        Expr::Bytes(bytes) => Ok(Value::Bytes(Rc::clone(bytes))),
+        Expr::JoinedString(parts) => {
+            let mut text = String::new();
+            for part in parts {
+                // Every part evaluates to a str.
+                if let Value::Str(s) = evaluate(part, env, writer)? {
+                    text.push_str(&s);
+                }
+            }
+            Ok(Value::Str(text))
+        }
+        Expr::FormattedValue { value, conversion, spec, span } => {
+            let value = evaluate(value, env, writer)?;
+            let spec = match spec {
+                Some(spec) => {
+                    let spec = evaluate(spec, env, writer)?;
+                    special::to_str(&spec, *span, env, writer)?
                 }
+                None => String::new(),
+            };
+            Ok(Value::Str(special::format(&value, *conversion, &spec, *span, env, writer)?))
+        }
+        Expr::Bool(b) => Ok(Value::Bool(*b)),
+        Expr::None => Ok(Value::None),
+        Expr::Variable(name, span) => env.get(name).ok_or_else(|| env.unbound(name).at(*span)),
+        Expr::List(elements) => Ok(Value::new_list(evaluate_elements(elements, env, writer)?)),
+        Expr::Tuple(elements) => Ok(Value::new_tuple(evaluate_elements(elements, env, writer)?)),
+        Expr::Set(elements, span) => {
+            let mut set = special::new_set(evaluate_elements(elements, env, writer)?, *span, env, writer)?;
+            // CPython folds a display of three or more constants into a
+            // frozenset (built twice over: once from the source order, then
+            // again from that set's order when constants are deduplicated) and
+            // copies it. Each step can lay the table out, and so order the
+            // items, differently from adding them one by one.
+            if elements.len() >= 3 && elements.iter().all(is_foldable_constant) {
+                let constants = Set::from_value(&Value::new_list(set.items()))?;
+                set = Set::new();
+                set.merge(&constants);
             }
-            None
+            Ok(Value::new_set(set))
+        }
+        Expr::Starred(_, span) => {
+            Err(PyError::new(ErrorKind::SyntaxError, "can't use starred expression here").at(*span))
+        }
+        Expr::Dict(entries, span) => {
+            let dict = RefCell::new(Dict::new());
+            for (key, value) in entries {
+                let key = evaluate(key, env, writer)?;
+                let value = evaluate(value, env, writer)?;
+                special::dict_insert(&dict, key, value, *span, env, writer)?;
+            }
+            Ok(Value::new_dict(dict.into_inner()))
+        }
+        Expr::Subscript { value, index, span } => {
+            let container = evaluate(value, env, writer)?;
+            let index = evaluate(index, env, writer)?;
+            special::get_item(&container, &index, *span, env, writer)
+        }
+        Expr::Slice { lower, upper, step } => {
+            let mut bound = |expr: &Option<Box<Expr>>| match expr {
+                Some(expr) => evaluate(expr, env, writer),
+                None => Ok(Value::None),
+            };
+            let slice = Slice { lower: bound(lower)?, upper: bound(upper)?, step: bound(step)? };
+            Ok(Value::Slice(Rc::new(slice)))
+        }
+        Expr::Attribute { value, name, span } => {
+            let object = evaluate(value, env, writer)?;
+            get_attribute(&object, name).map_err(|e| e.at(*span))
+        }
+        Expr::Arithmetic { left, operator, right, span } => {
+            let left_value = evaluate(left, env, writer)?;
+            let right_value = evaluate(right, env, writer)?;
+            special::binary_op(operator, &left_value, &right_value, *span, env, writer)
         }
-        Expr::Return(_) => None,
+        Expr::UnaryOp { operator, operand, span } => {
+            let value = evaluate(operand, env, writer)?;
+            special::unary_op(operator, &value, *span, env, writer)
+        }
+        Expr::Compare { left, operators, comparators, span } => {
+            let mut left_value = evaluate(left, env, writer)?;
+            // A chain gives the first falsy result, or else the last one;
+            // rich comparisons may give something other than a bool.
+            let mut result = Value::Bool(true);
+            for (operator, comparator) in operators.iter().zip(comparators) {
+                let right_value = evaluate(comparator, env, writer)?;
+                result = special::compare(operator, &left_value, &right_value, *span, env, writer)?;
+                if !special::truthy(&result, *span, env, writer)? {
+                    return Ok(result);
+                }
+                left_value = right_value;
+            }
+            Ok(result)
+        }
+        Expr::Logical { left, operator, right, span } => {
+            let left_value = evaluate(left, env, writer)?;
+            // `or` stops at the first truthy operand, `and` at the first falsy one.
+            if special::truthy(&left_value, *span, env, writer)? == (operator == "or") {
+                return Ok(left_value);
+            }
+            evaluate(right, env, writer)
+        }
+        Expr::Not(operand, span) => {
+            let value = evaluate(operand, env, writer)?;
+            Ok(Value::Bool(!special::truthy(&value, *span, env, writer)?))
+        }
+        Expr::Call { function, args, keywords, span } => {
+            let function = evaluate(function, env, writer)?;
+            let mut arg_values = Vec::with_capacity(args.len());
+            for arg in args {
+                arg_values.push(evaluate(arg, env, writer)?);
+            }
+            let mut kwargs = Vec::with_capacity(keywords.len());
+            for (name, arg) in keywords {
+                kwargs.push((name.clone(), evaluate(arg, env, writer)?));
+            }
+            let result = call_function(&function, arg_values, kwargs, *span, env, writer);
+            // Generators only the call held, like that of `next(g())`, go once it returns.
+            drop(function);
+            generator::close_dropped(*span, env, writer);
+            result
+        }
+        Expr::GeneratorExp { qualname, body, iterable, scope, span } => {
+            // The outermost iterable is evaluated right away; the rest of the
+            // expression runs as the body of a generator, which is passed its
+            // iterator as `.0`.
+            let iterable = evaluate(iterable, env, writer)?;
+            let iterator = special::iter(&iterable, *span, env, writer).map_err(|e| e.at(*span))?;
+            let function = Rc::new(Function {
+                name: "<genexpr>".to_string(),
+                qualname: qualname.clone(),
+                params: vec![".0".to_string()],
+                body: Rc::clone(body),
+                class: RefCell::new(Weak::new()),
+                is_generator: true,
+                scope: Rc::clone(scope),
+                closure: env.capture(scope),
+            });
+            let locals = Locals::new(&function, HashMap::from([(".0".to_string(), iterator)]));
+            Ok(Value::Generator(Rc::new(Generator::new(function, locals))))
+        }
+        Expr::Lambda { qualname, params, body, is_generator, scope } => Ok(Value::Function(Rc::new(Function {
+            name: "<lambda>".to_string(),
+            qualname: qualname.clone(),
+            params: params.clone(),
+            body: Rc::clone(body),
+            class: RefCell::new(Weak::new()),
+            is_generator: *is_generator,
+            scope: Rc::clone(scope),
+            closure: env.capture(scope),
+        }))),
+        Expr::Yield(..) | Expr::YieldFrom(..) => {
+            Err(PyError::new(ErrorKind::SyntaxError, "'yield' outside function"))
+        }
+        Expr::Bare(expr, _) => evaluate(expr, env, writer),
+        _ => Err(PyError::new(ErrorKind::SyntaxError, "invalid syntax")),
+    }
+}
+
+// The items of a list or tuple display, expanding any `*iterable` in place.
+fn evaluate_elements<W: Write>(
+    elements: &[Expr],
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Vec<Value>, PyError> {
+    let mut items = Vec::with_capacity(elements.len());
+    for element in elements {
+        if let Expr::Starred(value, span) = element {
+            let value = evaluate(value, env, writer)?;
+            let unpacked = special::items(&value, *span, env, writer).map_err(|error| match error.kind {
+                ErrorKind::TypeError if error.span.is_none() => PyError::new(
+                    ErrorKind::TypeError,
+                    format!("Value after * must be an iterable, not {}", value.type_name()),
+                )
+                .at(*span),
+                _ => error,
+            })?;
+            items.extend(unpacked);
+        } else {
+            items.push(evaluate(element, env, writer)?);
+        }
+    }
+    Ok(items)
+}
+
+pub fn call_function<W: Write>(
+    function: &Value,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let function = match function {
+        Value::Function(function) => function,
+        Value::Builtin(builtin) => {
+            return call_builtin(builtin, args, kwargs, span, env, writer).map_err(|e| e.at(span));
+        }
+        Value::Method(method) => {
+            let mut method_args = Vec::with_capacity(args.len() + 1);
+            method_args.push(method.receiver.clone());
+            method_args.extend(args);
+            let function = Value::Function(Rc::clone(&method.function));
+            return call_function(&function, method_args, kwargs, span, env, writer);
+        }
+        Value::Class(class) => return instantiate(class, args, kwargs, span, env, writer),
+        Value::Instance(_) => return special::call(function, args, kwargs, span, env, writer),
+        _ => {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("'{}' object is not callable", function.type_name()),
+            )
+            .at(span))
+        }
+    };
+    // Each call gets a fresh local scope holding the parameters.
+    let values = bind_arguments(function, args, kwargs).map_err(|e| e.at(span))?;
+    let mut locals = Locals::new(function, values);
+    if function.is_generator {
+        return Ok(Value::Generator(Rc::new(Generator::new(Rc::clone(function), locals))));
+    }
+    let flow = env.in_frame(function, &mut locals, span, |env| execute_block(&function.body, env, writer));
+    // Generators only the call's variables held go with them.
+    drop(locals);
+    generator::close_dropped(span, env, writer);
+    match flow? {
+        Flow::Return(value) => Ok(value),
+        // The parser rejects `break` and `continue` outside a loop body.
+        Flow::Normal | Flow::Break | Flow::Continue => Ok(Value::None),
+    }
+}
+
+// Calling a class creates an instance and passes the arguments on to its
+// `__init__`; a class that inherits object's `__init__` takes no arguments,
+// except that exceptions keep theirs as `args`.
+fn instantiate<W: Write>(
+    class: &Rc<Class>,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let instance = Instance::new(Rc::clone(class));
+    let is_exception = exception::is_exception_class(class);
+    if is_exception {
+        exception::init_exception(&instance, args.clone());
+    }
+    let instance = Value::Instance(Rc::new(instance));
+    match class.lookup("__init__") {
+        Some(Value::Function(init)) => {
+            let mut init_args = Vec::with_capacity(args.len() + 1);
+            init_args.push(instance.clone());
+            init_args.extend(args);
+            let result = call_function(&Value::Function(init), init_args, kwargs, span, env, writer)?;
+            if !matches!(result, Value::None) {
+                return Err(PyError::new(
+                    ErrorKind::TypeError,
+                    format!("__init__() should return None, not '{}'", result.type_name()),
+                )
+                .at(span));
+            }
+        }
+        _ if is_exception && !kwargs.is_empty() => {
+            return Err(
+                PyError::new(ErrorKind::TypeError, format!("{}() takes no keyword arguments", class.name)).at(span)
+            );
+        }
+        _ if !is_exception && (!args.is_empty() || !kwargs.is_empty()) => {
+            return Err(PyError::new(ErrorKind::TypeError, format!("{}() takes no arguments", class.name)).at(span));
+        }
+        _ => {}
+    }
+    Ok(instance)
+}
+
+// Matches call arguments to parameters: positionally first, then by keyword.
+fn bind_arguments(
+    function: &Function,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+) -> Result<HashMap<String, Value>, PyError> {
+    let params = &function.params;
+    if args.len() > params.len() {
+        return Err(PyError::new(
+            ErrorKind::TypeError,
+            format!(
+                "{}() takes {} positional argument{} but {} {} given",
+                function.qualname,
+                params.len(),
+                if params.len() == 1 { "" } else { "s" },
+                args.len(),
+                if args.len() == 1 { "was" } else { "were" }
+            ),
+        ));
+    }
+    let mut locals: HashMap<String, Value> = params.iter().cloned().zip(args).collect();
+    for (name, value) in kwargs {
+        if !params.contains(&name) {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("{}() got an unexpected keyword argument '{}'", function.qualname, name),
+            ));
+        }
+        if locals.contains_key(&name) {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("{}() got multiple values for argument '{}'", function.qualname, name),
+            ));
+        }
+        locals.insert(name, value);
+    }
+    let missing: Vec<String> = params
+        .iter()
+        .filter(|param| !locals.contains_key(*param))
+        .map(|param| format!("'{}'", param))
+        .collect();
+    if !missing.is_empty() {
+        // CPython lists them as 'a', 'a' and 'b', or 'a', 'b', and 'c'.
+        let names = match missing.as_slice() {
+            [only] => only.clone(),
+            [first, second] => format!("{} and {}", first, second),
+            [rest @ .., last] => format!("{}, and {}", rest.join(", "), last),
+            [] => unreachable!(),
+        };
+        return Err(PyError::new(
+            ErrorKind::TypeError,
+            format!(
+                "{}() missing {} required positional argument{}: {}",
+                function.qualname,
+                missing.len(),
+                if missing.len() == 1 { "" } else { "s" },
+                names
+            ),
+        ));
     }
+    Ok(locals)
 }
 
 fn evaluate_for_loop<W: Write>(
-    iterator: &str,
-    start: f64,
-    end: f64,
+    target: &Expr,
+    iterable: &Value,
     body: &[Expr],
-    variables: &mut HashMap<String, Value>,
+    orelse: &[Expr],
+    span: Span,
+    env: &mut Environment,
     writer: &mut W,
-) {
-    for i in (start as i64)..(end as i64) {
-        let i_f = i as f64;
-        // Update the loop variable without printing it:
-        variables.insert(iterator.to_string(), Value::Number(i_f));
-        // Evaluate the body of the loop
-        for expr in body {
-            evaluate(expr, variables, writer);
+) -> Result<Flow, PyError> {
+    let iterator = special::iterate(iterable, span, env, writer)?;
+    while let Some(item) = iterator.next(span, env, writer)? {
+        assign(target, item, span, env, writer)?;
+        match execute_block(body, env, writer)? {
+            Flow::Break => return Ok(Flow::Normal),
+            Flow::Return(value) => return Ok(Flow::Return(value)),
+            Flow::Normal | Flow::Continue => {}
         }
     }
+    execute_block(orelse, env, writer)
 }
 
 fn evaluate_while_loop<W: Write>(
     condition: &Expr,
     body: &[Expr],
-    variables: &mut HashMap<String, Value>,
+    orelse: &[Expr],
+    span: Span,
+    env: &mut Environment,
     writer: &mut W,
-) {
-    while let Some(Value::Number(cond_val)) = evaluate(condition, variables, writer) {
-        if cond_val <= 0.0 {
+) -> Result<Flow, PyError> {
+    loop {
+        let value = evaluate(condition, env, writer)?;
+        if !special::truthy(&value, Span::whole_line(span.line), env, writer)? {
             break;
         }
-        for expr in body {
-            evaluate(expr, variables, writer);
+        match execute_block(body, env, writer)? {
+            Flow::Break => return Ok(Flow::Normal),
+            Flow::Return(value) => return Ok(Flow::Return(value)),
+            Flow::Normal | Flow::Continue => {}
         }
     }
+    execute_block(orelse, env, writer)
 }
@@ -0,0 +1,289 @@
+use crate::ast::Value;
+use crate::class::{object_class, Class, Instance};
+use crate::error::{render_traceback, ErrorKind, Frame, PyError, Span};
+use crate::evaluator::Environment;
+use crate::special;
+use std::io::Write;
+use std::rc::Rc;
+
+// The built-in exception types below BaseException, each after the type it
+// derives from.
+const HIERARCHY: &[(ErrorKind, ErrorKind)] = &[
+    (ErrorKind::Exception, ErrorKind::BaseException),
+    (ErrorKind::GeneratorExit, ErrorKind::BaseException),
+    (ErrorKind::ArithmeticError, ErrorKind::Exception),
+    (ErrorKind::OverflowError, ErrorKind::ArithmeticError),
+    (ErrorKind::ZeroDivisionError, ErrorKind::ArithmeticError),
+    (ErrorKind::AssertionError, ErrorKind::Exception),
+    (ErrorKind::AttributeError, ErrorKind::Exception),
+    (ErrorKind::ImportError, ErrorKind::Exception),
+    (ErrorKind::ModuleNotFoundError, ErrorKind::ImportError),
+    (ErrorKind::LookupError, ErrorKind::Exception),
+    (ErrorKind::IndexError, ErrorKind::LookupError),
+    (ErrorKind::KeyError, ErrorKind::LookupError),
+    (ErrorKind::MemoryError, ErrorKind::Exception),
+    (ErrorKind::NameError, ErrorKind::Exception),
+    (ErrorKind::UnboundLocalError, ErrorKind::NameError),
+    (ErrorKind::OSError, ErrorKind::Exception),
+    (ErrorKind::RuntimeError, ErrorKind::Exception),
+    (ErrorKind::NotImplementedError, ErrorKind::RuntimeError),
+    (ErrorKind::RecursionError, ErrorKind::RuntimeError),
+    (ErrorKind::StopIteration, ErrorKind::Exception),
+    (ErrorKind::SyntaxError, ErrorKind::Exception),
+    (ErrorKind::IndentationError, ErrorKind::SyntaxError),
+    (ErrorKind::TypeError, ErrorKind::Exception),
+    (ErrorKind::ValueError, ErrorKind::Exception),
+];
+
+thread_local! {
+    static CLASSES: Vec<(ErrorKind, Rc<Class>)> = {
+        let mut classes = vec![(ErrorKind::BaseException, Class::builtin("BaseException", object_class()))];
+        for &(kind, base) in HIERARCHY {
+            let base = classes.iter().find(|(k, _)| *k == base).map(|(_, class)| Rc::clone(class));
+            classes.push((kind, Class::builtin(&kind.to_string(), base.expect("bases come first"))));
+        }
+        classes
+    };
+}
+
+pub fn exception_class(kind: ErrorKind) -> Rc<Class> {
+    CLASSES.with(|classes| {
+        let class = classes.iter().find(|(k, _)| *k == kind).map(|(_, class)| Rc::clone(class));
+        class.expect("every kind has a class")
+    })
+}
+
+// The built-in exception type with the given name.
+pub fn lookup(name: &str) -> Option<Value> {
+    CLASSES.with(|classes| {
+        classes.iter().find(|(_, class)| class.name == name).map(|(_, class)| Value::Class(Rc::clone(class)))
+    })
+}
+
+// The built-in type an exception class is, or derives from, which errors of
+// that class are treated as.
+pub fn kind_of(class: &Rc<Class>) -> ErrorKind {
+    CLASSES.with(|classes| {
+        class.mro().iter().find_map(|ancestor| {
+            classes.iter().find(|(_, class)| Rc::ptr_eq(class, ancestor)).map(|(kind, _)| *kind)
+        })
+    })
+    .unwrap_or(ErrorKind::BaseException)
+}
+
+pub fn is_exception_class(class: &Rc<Class>) -> bool {
+    class.is_subclass(&exception_class(ErrorKind::BaseException))
+}
+
+pub fn is_exception(value: &Value) -> bool {
+    matches!(value, Value::Instance(instance) if is_exception_class(&instance.class))
+}
+
+// Gives a new exception the attributes every exception has; `args` are the
+// arguments it was created with. A StopIteration also has the value of the
+// generator return it stands for.
+pub fn init_exception(instance: &Instance, args: Vec<Value>) {
+    let mut attributes = instance.attributes.borrow_mut();
+    if kind_of(&instance.class) == ErrorKind::StopIteration {
+        attributes.insert("value".to_string(), args.first().cloned().unwrap_or(Value::None));
+    }
+    attributes.insert("args".to_string(), Value::new_tuple(args));
+    for name in ["__traceback__", "__cause__", "__context__"] {
+        attributes.insert(name.to_string(), Value::None);
+    }
+    attributes.insert("__suppress_context__".to_string(), Value::Bool(false));
+}
+
+pub fn new_exception(kind: ErrorKind, args: Vec<Value>) -> Value {
+    let instance = Instance::new(exception_class(kind));
+    init_exception(&instance, args);
+    Value::Instance(Rc::new(instance))
+}
+
+fn attribute(object: &Value, name: &str) -> Value {
+    match object {
+        Value::Instance(instance) => instance.attributes.borrow().get(name).cloned().unwrap_or(Value::None),
+        _ => Value::None,
+    }
+}
+
+fn set_attribute(object: &Value, name: &str, value: Value) {
+    if let Value::Instance(instance) = object {
+        instance.attributes.borrow_mut().insert(name.to_string(), value);
+    }
+}
+
+fn is_same(left: &Value, right: &Value) -> bool {
+    matches!((left, right), (Value::Instance(l), Value::Instance(r)) if Rc::ptr_eq(l, r))
+}
+
+// The exception object for an error, made from its type and message the
+// first time it is needed.
+pub fn exception_object(error: &mut PyError) -> Value {
+    if let Some(object) = &error.exception {
+        return object.clone();
+    }
+    let args = if error.message.is_empty() { Vec::new() } else { vec![Value::Str(error.message.clone())] };
+    let object = new_exception(error.kind, args);
+    error.exception = Some(object.clone());
+    object
+}
+
+// The StopIteration ending a generator that returned `value`.
+pub fn stop_iteration(value: Value) -> PyError {
+    let args = if matches!(value, Value::None) { Vec::new() } else { vec![value] };
+    let mut error = PyError::new(ErrorKind::StopIteration, "");
+    error.exception = Some(new_exception(ErrorKind::StopIteration, args));
+    error
+}
+
+// The value a StopIteration carries: what the generator returned.
+pub fn stop_value(error: &mut PyError) -> Value {
+    attribute(&exception_object(error), "value")
+}
+
+// The error of raising an exception object at `span`, or re-raising it
+// (without a location of its own) for a bare `raise`. An object raised before
+// brings its traceback along.
+pub fn raise(object: Value, span: Option<Span>) -> PyError {
+    let kind = match &object {
+        Value::Instance(instance) => kind_of(&instance.class),
+        _ => ErrorKind::BaseException,
+    };
+    let mut error = PyError::new(kind, "");
+    if let Value::Traceback(frames) = attribute(&object, "__traceback__") {
+        error.traceback = frames.to_vec();
+    }
+    error.span = span;
+    error.exception = Some(object);
+    error
+}
+
+// Catches an error in the frame running `function`: its exception object,
+// which records the traceback so far.
+pub fn catch(error: &mut PyError, function: &str) -> Value {
+    let object = exception_object(error);
+    let mut frames = error.traceback.clone();
+    if let Some(span) = error.span {
+        frames.insert(0, Frame { function: function.to_string(), span });
+    }
+    set_attribute(&object, "__traceback__", Value::Traceback(Rc::new(frames)));
+    object
+}
+
+// Whether an `except` clause for `types`, a class or a tuple of them,
+// handles the error.
+pub fn handles(types: &Value, error: &PyError) -> Result<bool, PyError> {
+    let class = match &error.exception {
+        Some(Value::Instance(instance)) => Rc::clone(&instance.class),
+        _ => exception_class(error.kind),
+    };
+    is_handled(&class, types)
+}
+
+fn is_handled(class: &Rc<Class>, types: &Value) -> Result<bool, PyError> {
+    match types {
+        Value::Class(handled) if is_exception_class(handled) => Ok(class.is_subclass(handled)),
+        Value::Tuple(items) => {
+            for item in items.iter() {
+                if is_handled(class, item)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        _ => Err(PyError::new(
+            ErrorKind::TypeError,
+            "catching classes that do not inherit from BaseException is not allowed",
+        )),
+    }
+}
+
+// Records the exception being handled as the context of an error raised
+// while handling it.
+pub fn chain(error: &mut PyError, context: &Value) {
+    let object = exception_object(error);
+    if !is_same(&object, context) && matches!(attribute(&object, "__context__"), Value::None) {
+        set_attribute(&object, "__context__", context.clone());
+    }
+}
+
+// `raise ... from cause`: the cause is shown before the exception, in place
+// of its context. `from None` just hides the context.
+pub fn set_cause(object: &Value, cause: Value) {
+    set_attribute(object, "__cause__", cause);
+    set_attribute(object, "__suppress_context__", Value::Bool(true));
+}
+
+// str() of an exception: its only argument (a KeyError's in repr form), or
+// else the repr of all of them.
+pub fn to_str<W: Write>(object: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<String, PyError> {
+    let args = attribute(object, "args");
+    let key_error = matches!(object, Value::Instance(instance) if kind_of(&instance.class) == ErrorKind::KeyError);
+    match &args {
+        Value::Tuple(items) if items.is_empty() => Ok(String::new()),
+        Value::Tuple(items) if items.len() == 1 && key_error => special::repr(&items[0], span, env, writer),
+        Value::Tuple(items) if items.len() == 1 => special::to_str(&items[0], span, env, writer),
+        _ => special::repr(&args, span, env, writer),
+    }
+}
+
+// repr() of an exception: its type called with its arguments.
+pub fn repr<W: Write>(object: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<String, PyError> {
+    let args = attribute(object, "args");
+    match &args {
+        Value::Tuple(items) if items.len() == 1 => {
+            Ok(format!("{}({})", object.type_name(), special::repr(&items[0], span, env, writer)?))
+        }
+        _ => Ok(format!("{}{}", object.type_name(), special::repr(&args, span, env, writer)?)),
+    }
+}
+
+// What is printed for an exception nothing caught: its traceback, preceded
+// by those of the exceptions it was raised from or while handling.
+pub fn report<W: Write>(
+    mut error: PyError,
+    env: &mut Environment,
+    writer: &mut W,
+    filename: &str,
+    source: &str,
+) -> String {
+    let object = catch(&mut error, "<module>");
+    describe(&object, &mut Vec::new(), env, writer, filename, source)
+}
+
+fn describe<W: Write>(
+    object: &Value,
+    seen: &mut Vec<Value>,
+    env: &mut Environment,
+    writer: &mut W,
+    filename: &str,
+    source: &str,
+) -> String {
+    seen.push(object.clone());
+    let mut out = String::new();
+    let cause = attribute(object, "__cause__");
+    let context = attribute(object, "__context__");
+    let unseen = |value: &Value, seen: &[Value]| is_exception(value) && !seen.iter().any(|other| is_same(other, value));
+    if unseen(&cause, seen) {
+        out.push_str(&describe(&cause, seen, env, writer, filename, source));
+        out.push_str("\n\nThe above exception was the direct cause of the following exception:\n\n");
+    } else if unseen(&context, seen) && !attribute(object, "__suppress_context__").is_truthy() {
+        out.push_str(&describe(&context, seen, env, writer, filename, source));
+        out.push_str("\n\nDuring handling of the above exception, another exception occurred:\n\n");
+    }
+    match attribute(object, "__traceback__") {
+        Value::Traceback(frames) if !frames.is_empty() => out.push_str(&render_traceback(&frames, filename, source)),
+        _ => {}
+    }
+    let name = match object {
+        Value::Instance(instance) => instance.class.qualname.clone(),
+        _ => object.type_name().to_string(),
+    };
+    match special::to_str(object, Span::whole_line(1), env, writer) {
+        Ok(message) if message.is_empty() => out.push_str(&name),
+        Ok(message) => out.push_str(&format!("{}: {}", name, message)),
+        Err(_) => out.push_str(&format!("{}: <exception str() failed>", name)),
+    }
+    out
+}
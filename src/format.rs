@@ -0,0 +1,683 @@
+use crate::ast::{format_float, Value};
+use crate::bigint::BigInt;
+use crate::builtins::get_attribute;
+use crate::error::{ErrorKind, PyError, Span};
+use crate::evaluator::Environment;
+use crate::operators::key_error;
+use crate::special;
+use std::io::Write;
+use std::iter;
+
+// A parsed format spec, `[[fill]align][sign][z][#][0][width][grouping][.precision][type]`,
+// the mini-language shared by format(), str.format and f-strings.
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    sign: Option<char>,
+    coerce_zero: bool, // 'z': show negative zero as zero
+    alternate: bool,   // '#'
+    zero_pad: bool,    // a '0' before the width
+    width: usize,
+    grouping: Option<char>,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+fn value_error(message: String) -> PyError {
+    PyError::new(ErrorKind::ValueError, message)
+}
+
+fn parse_spec(spec: &str, type_name: &str) -> Result<Spec, PyError> {
+    let chars: Vec<char> = spec.chars().collect();
+    let mut i = 0;
+    let is_align = |c: Option<&char>| matches!(c, Some('<' | '>' | '=' | '^'));
+    let (mut fill, mut align) = (None, None);
+    if is_align(chars.get(1)) {
+        fill = Some(chars[0]);
+        align = Some(chars[1]);
+        i = 2;
+    } else if is_align(chars.first()) {
+        align = Some(chars[0]);
+        i = 1;
+    }
+    let mut sign = None;
+    if let Some(&c @ ('+' | '-' | ' ')) = chars.get(i) {
+        sign = Some(c);
+        i += 1;
+    }
+    let coerce_zero = chars.get(i) == Some(&'z');
+    i += coerce_zero as usize;
+    let alternate = chars.get(i) == Some(&'#');
+    i += alternate as usize;
+    let zero_pad = chars.get(i) == Some(&'0');
+    i += zero_pad as usize;
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+            *i += 1;
+        }
+        (*i > start).then(|| chars[start..*i].iter().collect::<String>())
+    };
+    // Like CPython, a width or precision must fit in an isize.
+    let number = |digits: String| {
+        let too_large = || value_error("Too many decimal digits in format string".to_string());
+        digits.parse().ok().filter(|&n: &usize| n <= isize::MAX as usize).ok_or_else(too_large)
+    };
+    let width = match digits(&mut i) {
+        Some(width) => number(width)?,
+        None => 0,
+    };
+    let mut grouping = None;
+    if let Some(&c @ (',' | '_')) = chars.get(i) {
+        grouping = Some(c);
+        i += 1;
+        match chars.get(i) {
+            Some(&next @ (',' | '_')) if next == c => {
+                return Err(value_error(format!("Cannot specify '{}' with '{}'.", c, c)))
+            }
+            Some(',' | '_') => return Err(value_error("Cannot specify both ',' and '_'.".to_string())),
+            _ => {}
+        }
+    }
+    let mut precision = None;
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        match digits(&mut i) {
+            Some(digits) => precision = Some(number(digits)?),
+            None => return Err(value_error("Format specifier missing precision".to_string())),
+        }
+    }
+    let kind = match &chars[i..] {
+        [] => None,
+        [kind] => Some(*kind),
+        _ => {
+            return Err(value_error(format!(
+                "Invalid format specifier '{}' for object of type '{}'",
+                spec, type_name
+            )))
+        }
+    };
+    Ok(Spec { fill, align, sign, coerce_zero, alternate, zero_pad, width, grouping, precision, kind })
+}
+
+// format(value, spec): the value's str() when the spec is empty; otherwise
+// ints, floats and strings follow the spec and other types refuse it.
+pub fn format_value(value: &Value, spec: &str) -> Result<String, PyError> {
+    if spec.is_empty() {
+        return Ok(value.to_string());
+    }
+    match value {
+        Value::Str(s) => format_str(s, &parse_spec(spec, "str")?),
+        Value::Int(_) | Value::BigInt(_) | Value::Bool(_) => {
+            format_int(&value.as_bigint().unwrap(), &parse_spec(spec, "int")?)
+        }
+        Value::Float(n) => format_float_spec(*n, &parse_spec(spec, "float")?, "float"),
+        _ => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("unsupported format string passed to {}.__format__", value.type_name()),
+        )),
+    }
+}
+
+fn format_str(s: &str, spec: &Spec) -> Result<String, PyError> {
+    if let Some(kind) = spec.kind.filter(|kind| *kind != 's') {
+        return Err(unknown_code(kind, "str"));
+    }
+    let refuse = |what: &str| Err(value_error(format!("{} not allowed in string format specifier", what)));
+    if spec.sign.is_some() {
+        return refuse("Sign");
+    }
+    if spec.coerce_zero {
+        return refuse("Negative zero coercion (z)");
+    }
+    if spec.alternate {
+        return refuse("Alternate form (#)");
+    }
+    if spec.align == Some('=') {
+        return refuse("'=' alignment");
+    }
+    if let Some(grouping) = spec.grouping {
+        return Err(value_error(format!("Cannot specify '{}' with 's'.", grouping)));
+    }
+    let text: String = match spec.precision {
+        Some(precision) => s.chars().take(precision).collect(),
+        None => s.to_string(),
+    };
+    let fill = spec.fill.unwrap_or(if spec.zero_pad { '0' } else { ' ' });
+    pad(String::new(), text, fill, spec.align.unwrap_or('<'), spec.width)
+}
+
+fn unknown_code(kind: char, type_name: &str) -> PyError {
+    value_error(format!("Unknown format code '{}' for object of type '{}'", kind, type_name))
+}
+
+fn format_int(n: &BigInt, spec: &Spec) -> Result<String, PyError> {
+    let kind = spec.kind.unwrap_or('d');
+    if matches!(kind, 'e' | 'E' | 'f' | 'F' | 'g' | 'G' | '%') {
+        return format_float_spec(n.to_f64(), spec, "int");
+    }
+    let (radix, prefix) = match kind {
+        'd' | 'n' | 'c' => (10, ""),
+        'b' => (2, "0b"),
+        'o' => (8, "0o"),
+        'x' => (16, "0x"),
+        'X' => (16, "0X"),
+        _ => return Err(unknown_code(kind, "int")),
+    };
+    if spec.precision.is_some() {
+        return Err(value_error("Precision not allowed in integer format specifier".to_string()));
+    }
+    if spec.coerce_zero {
+        return Err(value_error("Negative zero coercion (z) not allowed in integer format specifier".to_string()));
+    }
+    match spec.grouping {
+        Some(',') if kind != 'd' => return Err(value_error(format!("Cannot specify ',' with '{}'.", kind))),
+        Some('_') if matches!(kind, 'n' | 'c') => {
+            return Err(value_error(format!("Cannot specify '_' with '{}'.", kind)))
+        }
+        _ => {}
+    }
+    if kind == 'c' {
+        if spec.sign.is_some() {
+            return Err(value_error("Sign not allowed with integer format specifier 'c'".to_string()));
+        }
+        if spec.alternate {
+            return Err(value_error("Alternate form (#) not allowed with integer format specifier 'c'".to_string()));
+        }
+        let c = n.to_i64().and_then(|code| u32::try_from(code).ok()).and_then(char::from_u32).ok_or_else(|| {
+            PyError::new(ErrorKind::OverflowError, "%c arg not in range(0x110000)")
+        })?;
+        return number(spec, "", "", "", &c.to_string(), 0);
+    }
+    let mut digits = n.magnitude_digits(radix);
+    if kind == 'X' {
+        digits = digits.to_uppercase();
+    }
+    let prefix = if spec.alternate { prefix } else { "" };
+    let group_size = if radix == 10 { 3 } else { 4 };
+    number(spec, sign_of(spec, n.is_negative()), prefix, &digits, "", group_size)
+}
+
+fn sign_of(spec: &Spec, negative: bool) -> &'static str {
+    match (negative, spec.sign) {
+        (true, _) => "-",
+        (false, Some('+')) => "+",
+        (false, Some(' ')) => " ",
+        _ => "",
+    }
+}
+
+fn format_float_spec(n: f64, spec: &Spec, type_name: &str) -> Result<String, PyError> {
+    let kind = spec.kind;
+    if let Some(kind) = kind.filter(|kind| !matches!(kind, 'e' | 'E' | 'f' | 'F' | 'g' | 'G' | 'n' | '%')) {
+        return Err(unknown_code(kind, type_name));
+    }
+    if spec.precision.is_some_and(|precision| precision > i32::MAX as usize) {
+        return Err(value_error("precision too big".to_string()));
+    }
+    let magnitude = n.abs();
+    let precision = spec.precision.unwrap_or(6);
+    let mut body = if !n.is_finite() {
+        format_float(magnitude)
+    } else {
+        match kind {
+            Some('f' | 'F') => fixed(magnitude, precision, spec.alternate),
+            Some('e' | 'E') => scientific(magnitude, precision, spec.alternate),
+            Some('g' | 'G' | 'n') => general(magnitude, precision, spec.alternate, false),
+            Some(_) => fixed(magnitude * 100.0, precision, spec.alternate),
+            // With no type, a precision counts significant digits as 'g'
+            // does; without one the value prints as str() would.
+            None => match spec.precision {
+                Some(precision) => general(magnitude, precision, spec.alternate, true),
+                None => format_float(magnitude),
+            },
+        }
+    };
+    if kind == Some('%') {
+        body.push('%');
+    }
+    if matches!(kind, Some('E' | 'F' | 'G')) {
+        body = body.to_uppercase();
+    }
+    let mut negative = n.is_sign_negative() && !n.is_nan();
+    if spec.coerce_zero && body.chars().all(|c| !c.is_ascii_digit() || c == '0') {
+        negative = false;
+    }
+    // Grouping applies to the digits before the point or exponent.
+    let split = body.find(|c: char| !c.is_ascii_digit()).unwrap_or(body.len());
+    let (digits, rest) = body.split_at(split);
+    number(spec, sign_of(spec, negative), "", digits, rest, 3)
+}
+
+// `{:.*}` rounds exactly, as Python does; '#' keeps the point even with no
+// decimals.
+fn fixed(n: f64, precision: usize, alternate: bool) -> String {
+    let mut text = format!("{:.*}", precision, n);
+    if alternate && precision == 0 {
+        text.push('.');
+    }
+    text
+}
+
+// Scientific notation with Python's exponent: signed and at least two digits.
+fn scientific(n: f64, precision: usize, alternate: bool) -> String {
+    let text = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = text.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let point = if alternate && precision == 0 { "." } else { "" };
+    format!("{}{}e{}{:02}", mantissa, point, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+// The 'g' format: `precision` significant digits, in fixed or scientific
+// notation depending on the exponent, with trailing zeros dropped unless
+// '#' is given. With no type given, fixed notation keeps one decimal.
+fn general(n: f64, precision: usize, alternate: bool, keep_decimal: bool) -> String {
+    let precision = precision.max(1);
+    let exponent = if n == 0.0 {
+        0
+    } else {
+        let text = format!("{:.*e}", precision - 1, n);
+        text.split_once('e').unwrap().1.parse::<i64>().unwrap()
+    };
+    let strip = |text: String| {
+        if alternate || !text.contains('.') {
+            return text;
+        }
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    };
+    // Fixed notation is used while it needs no more than `precision` digits,
+    // counting the decimal kept when there's no type.
+    let limit = precision as i64 - keep_decimal as i64;
+    if -4 <= exponent && exponent < limit {
+        let mut text = strip(fixed(n, (precision as i64 - 1 - exponent) as usize, alternate));
+        if keep_decimal && !text.contains('.') {
+            text.push_str(".0");
+        }
+        text
+    } else {
+        let text = scientific(n, precision - 1, alternate);
+        let (mantissa, exponent) = text.split_once('e').unwrap();
+        format!("{}e{}", strip(mantissa.to_string()), exponent)
+    }
+}
+
+// Lays out a formatted number: sign, prefix such as "0x", digits (grouped if
+// asked), then the rest (fraction, exponent, '%'), padded to the width.
+fn number(
+    spec: &Spec,
+    sign: &str,
+    prefix: &str,
+    digits: &str,
+    rest: &str,
+    group_size: usize,
+) -> Result<String, PyError> {
+    let fill = spec.fill.unwrap_or(if spec.zero_pad { '0' } else { ' ' });
+    let align = spec.align.unwrap_or(if spec.zero_pad { '=' } else { '>' });
+    let group = |digits: &str| match spec.grouping {
+        Some(separator) => group_digits(digits, separator, group_size),
+        None => digits.to_string(),
+    };
+    let mut grouped = group(digits);
+    // Zero padding with grouping pads with grouped zeros, as in "00,001,234".
+    if fill == '0' && align == '=' && spec.grouping.is_some() {
+        let room = spec.width.saturating_sub(sign.len() + prefix.len() + rest.chars().count());
+        let mut padded = allocate(room)?;
+        padded.push_str(digits);
+        while grouped.chars().count() < room {
+            padded.insert(0, '0');
+            grouped = group(&padded);
+        }
+    }
+    pad(format!("{}{}", sign, prefix), grouped + rest, fill, align, spec.width)
+}
+
+fn group_digits(digits: &str, separator: char, size: usize) -> String {
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(size) {
+            out.push(separator);
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Pads to the width; '=' alignment puts the fill between the sign (and
+// prefix) and the digits.
+fn pad(head: String, body: String, fill: char, align: char, width: usize) -> Result<String, PyError> {
+    let padding = width.saturating_sub(head.chars().count() + body.chars().count());
+    let (before, between, after) = match align {
+        '<' => (0, 0, padding),
+        '^' => (padding / 2, 0, padding - padding / 2),
+        '=' => (0, padding, 0),
+        _ => (padding, 0, 0),
+    };
+    let mut out = allocate(padding.saturating_mul(fill.len_utf8()).saturating_add(head.len() + body.len()))?;
+    out.extend(iter::repeat_n(fill, before));
+    out.push_str(&head);
+    out.extend(iter::repeat_n(fill, between));
+    out.push_str(&body);
+    out.extend(iter::repeat_n(fill, after));
+    Ok(out)
+}
+
+// An empty string with room for `len` bytes; like CPython, a width too large
+// to allocate is a MemoryError.
+fn allocate(len: usize) -> Result<String, PyError> {
+    let mut out = String::new();
+    out.try_reserve_exact(len).map_err(|_| PyError::new(ErrorKind::MemoryError, ""))?;
+    Ok(out)
+}
+
+// A repr with anything beyond ASCII escaped, as ascii() and `!a` give it.
+pub fn escape_ascii(repr: &str) -> String {
+    let mut out = String::new();
+    for c in repr.chars() {
+        match c as u32 {
+            0..=0x7f => out.push(c),
+            code @ 0x80..=0xff => out.push_str(&format!("\\x{:02x}", code)),
+            code @ 0x100..=0xffff => out.push_str(&format!("\\u{:04x}", code)),
+            code => out.push_str(&format!("\\U{:08x}", code)),
+        }
+    }
+    out
+}
+
+// How str.format numbers its `{}` fields: automatically or explicitly, but
+// never a mix of the two.
+enum Numbering {
+    Unknown,
+    Automatic(usize),
+    Manual,
+}
+
+// str.format: fills in the `{field!conversion:spec}` replacement fields of a
+// template from positional and keyword arguments. Fields format as f-string
+// fields do, so instances go through `__format__`, `__str__` and `__repr__`.
+pub fn format_string<W: Write>(
+    template: &str,
+    args: &[Value],
+    kwargs: &[(String, Value)],
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<String, PyError> {
+    let mut fields = Fields { args, kwargs, numbering: Numbering::Unknown, span, env, writer };
+    fields.format(template, 2)
+}
+
+// The arguments of a str.format call and the state of its numbering.
+struct Fields<'a, W: Write> {
+    args: &'a [Value],
+    kwargs: &'a [(String, Value)],
+    numbering: Numbering,
+    span: Span,
+    env: &'a mut Environment,
+    writer: &'a mut W,
+}
+
+impl<W: Write> Fields<'_, W> {
+    fn format(&mut self, template: &str, depth: usize) -> Result<String, PyError> {
+        if depth == 0 {
+            return Err(value_error("Max string recursion exceeded".to_string()));
+        }
+        let chars: Vec<char> = template.chars().collect();
+        let mut out = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if (c == '{' || c == '}') && chars.get(i + 1) == Some(&c) {
+                out.push(c);
+                i += 2;
+                continue;
+            }
+            if c == '}' {
+                return Err(value_error("Single '}' encountered in format string".to_string()));
+            }
+            if c != '{' {
+                out.push(c);
+                i += 1;
+                continue;
+            }
+            // The field runs to the matching '}', past any fields nested in its spec.
+            let start = i + 1;
+            let mut braces = 0;
+            let mut end = start;
+            loop {
+                match chars.get(end) {
+                    None if end == start => {
+                        return Err(value_error("Single '{' encountered in format string".to_string()))
+                    }
+                    None => return Err(value_error("expected '}' before end of string".to_string())),
+                    Some('{') => braces += 1,
+                    Some('}') if braces == 0 => break,
+                    Some('}') => braces -= 1,
+                    _ => {}
+                }
+                end += 1;
+            }
+            let field: String = chars[start..end].iter().collect();
+            i = end + 1;
+            // The field name stops at a '!' or ':' outside any `[...]` index.
+            let mut brackets = false;
+            let name_end = field
+                .find(|c: char| {
+                    match c {
+                        '[' => brackets = true,
+                        ']' => brackets = false,
+                        _ => {}
+                    }
+                    !brackets && (c == '!' || c == ':')
+                })
+                .unwrap_or(field.len());
+            let (name, mut tail) = field.split_at(name_end);
+            let mut conversion = None;
+            if let Some(rest) = tail.strip_prefix('!') {
+                let mut rest_chars = rest.chars();
+                let Some(c) = rest_chars.next() else {
+                    return Err(value_error("end of string while looking for conversion specifier".to_string()));
+                };
+                if !matches!(c, 'r' | 's' | 'a') {
+                    return Err(value_error(format!("Unknown conversion specifier {}", c)));
+                }
+                tail = rest_chars.as_str();
+                if !tail.is_empty() && !tail.starts_with(':') {
+                    return Err(value_error("expected ':' after conversion specifier".to_string()));
+                }
+                conversion = Some(c);
+            }
+            let spec = match tail.strip_prefix(':') {
+                Some(spec) => self.format(spec, depth - 1)?,
+                None => String::new(),
+            };
+            let value = self.resolve_field(name)?;
+            out.push_str(&special::format(&value, conversion, &spec, self.span, self.env, self.writer)?);
+        }
+        Ok(out)
+    }
+
+    // Looks up a field name such as `0`, `name`, `0.attr` or `items[2]`; an
+    // empty name takes the next positional argument.
+    fn resolve_field(&mut self, name: &str) -> Result<Value, PyError> {
+        let first_end = name.find(['.', '[']).unwrap_or(name.len());
+        let (first, mut rest) = name.split_at(first_end);
+        let mut value = if first.is_empty() || first.chars().all(|c| c.is_ascii_digit()) {
+            let index = if first.is_empty() {
+                let next = match self.numbering {
+                    Numbering::Manual => {
+                        return Err(value_error(
+                            "cannot switch from manual field specification to automatic field numbering".to_string(),
+                        ))
+                    }
+                    Numbering::Automatic(next) => next,
+                    Numbering::Unknown => 0,
+                };
+                self.numbering = Numbering::Automatic(next + 1);
+                next
+            } else {
+                if let Numbering::Automatic(_) = self.numbering {
+                    return Err(value_error(
+                        "cannot switch from automatic field numbering to manual field specification".to_string(),
+                    ));
+                }
+                self.numbering = Numbering::Manual;
+                first.parse().unwrap_or(usize::MAX)
+            };
+            self.args.get(index).cloned().ok_or_else(|| {
+                PyError::new(
+                    ErrorKind::IndexError,
+                    format!("Replacement index {} out of range for positional args tuple", index),
+                )
+            })?
+        } else {
+            match self.kwargs.iter().find(|(key, _)| key == first) {
+                Some((_, value)) => value.clone(),
+                None => return Err(key_error(&Value::Str(first.to_string()))),
+            }
+        };
+        while !rest.is_empty() {
+            if let Some(attribute) = rest.strip_prefix('.') {
+                let end = attribute.find(['.', '[']).unwrap_or(attribute.len());
+                value = get_attribute(&value, &attribute[..end])?;
+                rest = &attribute[end..];
+            } else if let Some(index) = rest.strip_prefix('[') {
+                let Some(end) = index.find(']') else {
+                    return Err(value_error("Missing ']' in format string".to_string()));
+                };
+                let key = &index[..end];
+                let key = match key.parse::<i64>() {
+                    Ok(n) if key.chars().all(|c| c.is_ascii_digit()) => Value::Int(n),
+                    _ => Value::Str(key.to_string()),
+                };
+                value = special::get_item(&value, &key, self.span, self.env, self.writer)?;
+                rest = &index[end + 1..];
+            } else {
+                return Err(value_error("Only '.' or '[' may follow ']' in format field specifier".to_string()));
+            }
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_string, format_value};
+    use crate::ast::Value;
+    use crate::bigint::BigInt;
+    use crate::error::Span;
+    use crate::evaluator::Environment;
+    use std::rc::Rc;
+
+    fn big(digits: &str) -> Value {
+        Value::BigInt(Rc::new(BigInt::parse(digits).unwrap()))
+    }
+
+    fn error(value: &Value, spec: &str) -> String {
+        format_value(value, spec).unwrap_err().message
+    }
+
+    fn template(template: &str, args: &[Value], kwargs: &[(String, Value)]) -> String {
+        format_string(template, args, kwargs, Span::new(1, 0, 0), &mut Environment::new(), &mut Vec::new()).unwrap()
+    }
+
+    // Each expected string is what CPython's format(value, spec) gives.
+    #[test]
+    fn specs_match_cpython() {
+        let cases = [
+            (Value::Int(42), "", "42"),
+            (Value::Int(42), "5", "   42"),
+            (Value::Int(42), "<5", "42   "),
+            (Value::Int(42), "^6", "  42  "),
+            (Value::Int(-42), "=6", "-   42"),
+            (Value::Int(-42), "06", "-00042"),
+            (Value::Int(42), "+", "+42"),
+            (Value::Int(42), " ", " 42"),
+            (Value::Int(1234567), ",", "1,234,567"),
+            (Value::Int(1234567), "_", "1_234_567"),
+            (Value::Int(255), "x", "ff"),
+            (Value::Int(255), "#X", "0XFF"),
+            (Value::Int(255), "#o", "0o377"),
+            (Value::Int(5), "#010b", "0b00000101"),
+            (Value::Int(65), "c", "A"),
+            (Value::Int(1234), "n", "1234"),
+            (Value::Int(1234), ".2f", "1234.00"),
+            (Value::Int(1000000), "e", "1.000000e+06"),
+            (Value::Int(3), "%", "300.000000%"),
+            (Value::Int(3735928559), "_x", "dead_beef"),
+            (Value::Int(-3), "*^9", "***-3****"),
+            (Value::Int(7), "08,d", "0,000,007"),
+            (big("100000000000000000000"), ",", "100,000,000,000,000,000,000"),
+            (big("-1180591620717411303424"), "#x", "-0x400000000000000000"),
+            (big("10000000000000000000000000"), ".3e", "1.000e+25"),
+            (Value::Float(1.23456), ".2f", "1.23"),
+            (Value::Float(1.23456), "10.3f", "     1.235"),
+            (Value::Float(-1.23456), "<10.1f", "-1.2      "),
+            (Value::Float(1234567.891), ",.2f", "1,234,567.89"),
+            (Value::Float(0.000123456), "e", "1.234560e-04"),
+            (Value::Float(123456789.0), ".3e", "1.235e+08"),
+            (Value::Float(0.5), "%", "50.000000%"),
+            (Value::Float(0.12345), ".1%", "12.3%"),
+            (Value::Float(1e+16), "", "1e+16"),
+            (Value::Float(1e+16), "g", "1e+16"),
+            (Value::Float(123456.0), "g", "123456"),
+            (Value::Float(1234567.0), "g", "1.23457e+06"),
+            (Value::Float(0.0001), "g", "0.0001"),
+            (Value::Float(1e-05), "g", "1e-05"),
+            (Value::Float(1.5), "#g", "1.50000"),
+            (Value::Float(2.0), ".0f", "2"),
+            (Value::Float(2.5), ".0f", "2"),
+            (Value::Float(2.0), "#.0f", "2."),
+            (Value::Float(-0.0), "z.1f", "0.0"),
+            (Value::Float(-0.001), "z.1f", "0.0"),
+            (Value::Float(f64::INFINITY), "+", "+inf"),
+            (Value::Float(f64::NAN), "E", "NAN"),
+            (Value::Float(f64::NEG_INFINITY), "010", "-000000inf"),
+            (Value::Float(1.0), "+08.2f", "+0001.00"),
+            (Value::Float(12.5), "_", "12.5"),
+            (Value::Float(1e+300), ".3", "1e+300"),
+            (Value::Float(0.1), ".20f", "0.10000000000000000555"),
+            (Value::Float(3.0), "n", "3"),
+            (Value::Float(1.0), ".3", "1.0"),
+            (Value::Float(100.0), ".2", "1e+02"),
+            (Value::Str("hi".to_string()), "5", "hi   "),
+            (Value::Str("hi".to_string()), ">5", "   hi"),
+            (Value::Str("hi".to_string()), "^6", "  hi  "),
+            (Value::Str("hello".to_string()), ".3", "hel"),
+            (Value::Str("hi".to_string()), "*<4s", "hi**"),
+            (Value::Bool(true), "d", "1"),
+            (Value::Bool(true), ">5", "    1"),
+        ];
+        for (value, spec, expected) in cases {
+            assert_eq!(format_value(&value, spec).unwrap(), expected, "format({:?}, {:?})", value, spec);
+        }
+    }
+
+    #[test]
+    fn invalid_specs_match_cpython() {
+        assert_eq!(error(&Value::Float(1.5), "d"), "Unknown format code 'd' for object of type 'float'");
+        assert_eq!(error(&Value::Str("s".to_string()), "+"), "Sign not allowed in string format specifier");
+        assert_eq!(error(&Value::Int(1), ".2"), "Precision not allowed in integer format specifier");
+        assert_eq!(error(&Value::Int(1), ",,"), "Cannot specify ',' with ','.");
+        assert_eq!(error(&Value::Int(1), "_,"), "Cannot specify both ',' and '_'.");
+        assert_eq!(error(&Value::Int(65), ",c"), "Cannot specify ',' with 'c'.");
+        assert_eq!(error(&Value::Float(1.0), "."), "Format specifier missing precision");
+        assert_eq!(error(&Value::Int(1), "xx"), "Invalid format specifier 'xx' for object of type 'int'");
+        let text = Value::Str("x".to_string());
+        assert_eq!(error(&text, "=5"), "'=' alignment not allowed in string format specifier");
+        assert_eq!(error(&text, "#"), "Alternate form (#) not allowed in string format specifier");
+        let too_many = "Too many decimal digits in format string";
+        assert_eq!(error(&Value::Int(1), "9999999999999999999"), too_many);
+        assert_eq!(error(&Value::Int(1), "9223372036854775808"), too_many);
+        assert_eq!(error(&Value::Float(1.5), ".9999999999999999999"), too_many);
+        assert_eq!(error(&Value::Float(1.5), ".2147483648f"), "precision too big");
+        assert_eq!(error(&Value::Float(1.5), ".2147483648"), "precision too big");
+    }
+
+    #[test]
+    fn templates_match_cpython() {
+        let args = [Value::Str("ab".to_string()), Value::Int(5)];
+        let kwargs = [("x".to_string(), Value::Float(1.23456)), ("p".to_string(), Value::Int(2))];
+        assert_eq!(template("{0:>{1}}|{x:.{p}f}|{{}}|{0!r:^7}", &args, &kwargs), "   ab|1.23|{}| 'ab'  ");
+        assert_eq!(template("{}-{}", &[Value::Int(1), Value::Int(2)], &[]), "1-2");
+    }
+}
@@ -0,0 +1,565 @@
+use crate::ast::{Expr, Function, Value};
+use crate::builtins::get_attribute;
+use crate::error::{ErrorKind, PyError, Span};
+use crate::evaluator::{
+    assign, call_function, evaluate, execute, find_handler, statement_line, Environment, Flow, Locals,
+};
+use crate::exception;
+use crate::special::{self, Iteration};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+// A generator object: the suspended frame of a call to a generator function.
+//
+// The body runs on a separate executor from `execute`, one that can stop at
+// a `yield` and pick up again from there. Where it stopped is kept as a chain
+// of positions, one per compound statement it is inside (with whatever that
+// statement needs to carry on, such as the iterator of a `for` loop), ending
+// at the `yield`.
+//
+// CPython closes a suspended generator when it is garbage collected, so that
+// the `finally` blocks around the `yield` run. Dropping one here has no
+// environment to run them in, so it is set aside instead, and closed once the
+// statement or call that let go of it finishes: `for v in g(): break` runs
+// `g`'s `finally` blocks before the statement after the loop.
+pub struct Generator {
+    pub function: Rc<Function>,
+    state: RefCell<State>,
+    running: Cell<bool>,
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Generator({})", self.function.qualname)
+    }
+}
+
+enum State {
+    Created(Locals),
+    Suspended(Locals, Block),
+    Finished,
+}
+
+// Where a block of statements stopped: the statement it was running, and
+// where within that statement.
+struct Block {
+    index: usize,
+    statement: Box<Statement>,
+}
+
+// Where a statement stopped.
+enum Statement {
+    Yield,
+    YieldFrom(Value),    // the iterator being delegated to
+    Branch(bool, Block), // in the body of an `if` (true) or its `else`
+    While(Block),
+    For(Iteration, Block),
+    LoopElse(Block),
+    Try(Stage, Block),
+}
+
+// The part of a `try` statement that was running.
+enum Stage {
+    Body,
+    Handler(usize, Value), // the handler's index and the exception it caught
+    Else,
+    // The `finally` block, with the outcome of the rest of the statement and
+    // the exception object of a propagating error.
+    Finally(Result<Flow, PyError>, Option<Value>),
+}
+
+// How running part of a generator's body went: it yielded a value, stopping
+// at the position given, or finished.
+enum Step<T> {
+    Yield(Value, T),
+    Done(Flow),
+}
+
+// What a suspended generator is resumed with: a value for the `yield` to
+// evaluate to, or an error for it to raise.
+pub enum Resume {
+    Send(Value),
+    Throw(PyError),
+}
+
+// What a subiterator did when `yield from` resumed it.
+enum Delegated {
+    Yielded(Value),
+    Returned(Value),
+}
+
+impl Generator {
+    pub fn new(function: Rc<Function>, locals: Locals) -> Self {
+        Self { function, state: RefCell::new(State::Created(locals)), running: Cell::new(false) }
+    }
+}
+
+thread_local! {
+    // Generators dropped while suspended, oldest first, waiting for close_dropped.
+    static DROPPED: RefCell<VecDeque<Generator>> = const { RefCell::new(VecDeque::new()) };
+}
+
+impl Drop for Generator {
+    fn drop(&mut self) {
+        if !matches!(self.state.get_mut(), State::Suspended(..)) {
+            return;
+        }
+        let state = self.state.replace(State::Finished);
+        let function = Rc::clone(&self.function);
+        let generator = Generator { function, state: RefCell::new(state), running: Cell::new(false) };
+        // Those dropped as the thread exits are never closed.
+        let _ = DROPPED.try_with(|dropped| dropped.borrow_mut().push_back(generator));
+    }
+}
+
+// Closes the generators dropped while suspended. As when CPython collects
+// one, an error raised in closing it goes no further.
+pub fn close_dropped<W: Write>(span: Span, env: &mut Environment, writer: &mut W) {
+    while let Some(generator) = DROPPED.with(|dropped| dropped.borrow_mut().pop_front()) {
+        let _ = close(&Rc::new(generator), span, env, writer);
+    }
+}
+
+// Runs a generator until it next yields, giving the value yielded. Once it
+// returns, this raises StopIteration carrying the return value.
+pub fn resume<W: Write>(
+    generator: &Rc<Generator>,
+    resume: Resume,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if generator.running.get() {
+        return Err(PyError::new(ErrorKind::ValueError, "generator already executing").at(span));
+    }
+    let (mut locals, position) = match generator.state.replace(State::Finished) {
+        State::Finished => {
+            return match resume {
+                Resume::Send(_) => Err(PyError::new(ErrorKind::StopIteration, "")),
+                Resume::Throw(error) => Err(error),
+            }
+        }
+        State::Created(locals) => match resume {
+            Resume::Send(Value::None) => (locals, None),
+            Resume::Send(_) => {
+                generator.state.replace(State::Created(locals));
+                return Err(PyError::new(
+                    ErrorKind::TypeError,
+                    "can't send non-None value to a just-started generator",
+                ));
+            }
+            // The generator never runs, and is finished.
+            Resume::Throw(error) => return Err(error),
+        },
+        State::Suspended(locals, block) => (locals, Some((block, resume))),
+    };
+    let function = Rc::clone(&generator.function);
+    generator.running.set(true);
+    let result = env.in_frame(&function, &mut locals, span, |env| {
+        run_block(&function.body, position, env, writer).map_err(|mut error| {
+            if error.kind != ErrorKind::StopIteration {
+                return error;
+            }
+            // A StopIteration escaping the body would pass for the generator
+            // finishing, so it becomes a RuntimeError.
+            let object = exception::catch(&mut error, &env.frame_name());
+            let mut replaced = PyError::new(ErrorKind::RuntimeError, "generator raised StopIteration");
+            let replacement = exception::exception_object(&mut replaced);
+            exception::chain(&mut replaced, &object);
+            exception::set_cause(&replacement, object);
+            replaced
+        })
+    });
+    generator.running.set(false);
+    match result? {
+        Step::Yield(value, block) => {
+            generator.state.replace(State::Suspended(locals, block));
+            Ok(value)
+        }
+        Step::Done(Flow::Return(value)) => Err(exception::stop_iteration(value)),
+        Step::Done(_) => Err(exception::stop_iteration(Value::None)),
+    }
+}
+
+// generator.close(): raises GeneratorExit at the `yield` the generator is
+// suspended at, so that its `finally` blocks run.
+pub fn close<W: Write>(
+    generator: &Rc<Generator>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let started = matches!(*generator.state.borrow(), State::Suspended(..));
+    if !started {
+        generator.state.replace(State::Finished);
+        return Ok(Value::None);
+    }
+    let exit = Resume::Throw(PyError::new(ErrorKind::GeneratorExit, ""));
+    match resume(generator, exit, span, env, writer) {
+        Ok(_) => Err(PyError::new(ErrorKind::RuntimeError, "generator ignored GeneratorExit").at(span)),
+        Err(error) if matches!(error.kind, ErrorKind::GeneratorExit | ErrorKind::StopIteration) => Ok(Value::None),
+        Err(error) => Err(error),
+    }
+}
+
+// The error generator.throw() raises in the generator: that of an exception
+// instance, or of an exception class called with the value given (several
+// values when it is a tuple).
+pub fn thrown<W: Write>(args: &[Value], span: Span, env: &mut Environment, writer: &mut W) -> Result<PyError, PyError> {
+    let value = args.get(1).cloned().unwrap_or(Value::None);
+    let object = match &args[0] {
+        Value::Class(class) if exception::is_exception_class(class) => match value {
+            Value::Instance(instance) if instance.class.is_subclass(class) => Value::Instance(instance),
+            Value::None => call_function(&args[0], Vec::new(), Vec::new(), span, env, writer)?,
+            Value::Tuple(items) => call_function(&args[0], items.to_vec(), Vec::new(), span, env, writer)?,
+            value => call_function(&args[0], vec![value], Vec::new(), span, env, writer)?,
+        },
+        object if exception::is_exception(object) => {
+            if !matches!(value, Value::None) {
+                return Err(PyError::new(ErrorKind::TypeError, "instance exception may not have a separate value"));
+            }
+            object.clone()
+        }
+        other => {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!(
+                    "exceptions must be classes or instances deriving from BaseException, not {}",
+                    other.type_name()
+                ),
+            ))
+        }
+    };
+    Ok(exception::raise(object, None))
+}
+
+// Runs a block of statements from the start, or resumes it where it stopped.
+fn run_block<W: Write>(
+    body: &[Expr],
+    position: Option<(Block, Resume)>,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Step<Block>, PyError> {
+    let (mut index, mut resume) = match position {
+        Some((block, resume)) => (block.index, Some((*block.statement, resume))),
+        None => (0, None),
+    };
+    while let Some(stmt) = body.get(index) {
+        match run_statement(stmt, resume.take(), env, writer)? {
+            Step::Yield(value, statement) => {
+                return Ok(Step::Yield(value, Block { index, statement: Box::new(statement) }));
+            }
+            Step::Done(Flow::Normal) => index += 1,
+            Step::Done(flow) => return Ok(Step::Done(flow)),
+        }
+    }
+    Ok(Step::Done(Flow::Normal))
+}
+
+fn run_statement<W: Write>(
+    stmt: &Expr,
+    position: Option<(Statement, Resume)>,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Step<Statement>, PyError> {
+    // An error not located more precisely is reported on the statement's line.
+    let line = statement_line(stmt);
+    let result = resume_statement(stmt, position, env, writer).map_err(|error| match line {
+        Some(line) => error.at(Span::whole_line(line)),
+        None => error,
+    });
+    // Generators the statement let go of, as `execute` closes them.
+    close_dropped(Span::whole_line(line.unwrap_or(0)), env, writer);
+    result
+}
+
+fn resume_statement<W: Write>(
+    stmt: &Expr,
+    position: Option<(Statement, Resume)>,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Step<Statement>, PyError> {
+    match stmt {
+        Expr::Bare(value, span) | Expr::Assignment(_, value, span)
+            if matches!(**value, Expr::Yield(..) | Expr::YieldFrom(..)) =>
+        {
+            let target = match stmt {
+                Expr::Assignment(target, ..) => Some(&**target),
+                _ => None,
+            };
+            run_yield(value, target, *span, position, env, writer)
+        }
+        Expr::IfCondition { condition, body, orelse, span } => {
+            let (branch, position) = match position {
+                Some((Statement::Branch(branch, block), resume)) => (branch, Some((block, resume))),
+                _ => {
+                    let condition = evaluate(condition, env, writer)?;
+                    // CPython reports a failing truth test on the statement's line.
+                    (special::truthy(&condition, Span::whole_line(span.line), env, writer)?, None)
+                }
+            };
+            let block = if branch { body } else { orelse };
+            Ok(match run_block(block, position, env, writer)? {
+                Step::Yield(value, block) => Step::Yield(value, Statement::Branch(branch, block)),
+                Step::Done(flow) => Step::Done(flow),
+            })
+        }
+        Expr::WhileLoop { condition, body, orelse, span } => {
+            let mut position = match position {
+                Some((Statement::While(block), resume)) => Some((block, resume)),
+                Some((Statement::LoopElse(block), resume)) => {
+                    return run_loop_else(orelse, Some((block, resume)), env, writer)
+                }
+                _ => None,
+            };
+            loop {
+                if position.is_none() {
+                    let value = evaluate(condition, env, writer)?;
+                    if !special::truthy(&value, Span::whole_line(span.line), env, writer)? {
+                        break;
+                    }
+                }
+                match run_block(body, position.take(), env, writer)? {
+                    Step::Yield(value, block) => return Ok(Step::Yield(value, Statement::While(block))),
+                    Step::Done(Flow::Break) => return Ok(Step::Done(Flow::Normal)),
+                    Step::Done(Flow::Return(value)) => return Ok(Step::Done(Flow::Return(value))),
+                    Step::Done(Flow::Normal | Flow::Continue) => {}
+                }
+            }
+            run_loop_else(orelse, None, env, writer)
+        }
+        Expr::ForLoop { target, iterable, body, orelse, span } => {
+            let (iterator, mut position) = match position {
+                Some((Statement::For(iterator, block), resume)) => (iterator, Some((block, resume))),
+                Some((Statement::LoopElse(block), resume)) => {
+                    return run_loop_else(orelse, Some((block, resume)), env, writer)
+                }
+                _ => {
+                    let iterable = evaluate(iterable, env, writer)?;
+                    (special::iterate(&iterable, *span, env, writer)?, None)
+                }
+            };
+            loop {
+                if position.is_none() {
+                    let Some(item) = iterator.next(*span, env, writer)? else { break };
+                    assign(target, item, *span, env, writer)?;
+                }
+                match run_block(body, position.take(), env, writer)? {
+                    Step::Yield(value, block) => return Ok(Step::Yield(value, Statement::For(iterator, block))),
+                    Step::Done(Flow::Break) => return Ok(Step::Done(Flow::Normal)),
+                    Step::Done(Flow::Return(value)) => return Ok(Step::Done(Flow::Return(value))),
+                    Step::Done(Flow::Normal | Flow::Continue) => {}
+                }
+            }
+            run_loop_else(orelse, None, env, writer)
+        }
+        Expr::Try { body, handlers, orelse, finalbody } => {
+            let (mut stage, mut position) = match position {
+                Some((Statement::Try(stage, block), resume)) => (stage, Some((block, resume))),
+                _ => (Stage::Body, None),
+            };
+            // Each stage runs until the generator yields, or hands on to the
+            // next stage.
+            loop {
+                let result = match stage {
+                    Stage::Body => match run_block(body, position.take(), env, writer) {
+                        Ok(Step::Yield(value, block)) => {
+                            return Ok(Step::Yield(value, Statement::Try(Stage::Body, block)))
+                        }
+                        Ok(Step::Done(Flow::Normal)) => {
+                            stage = Stage::Else;
+                            continue;
+                        }
+                        Ok(Step::Done(flow)) => Ok(flow),
+                        Err(mut error) => match find_handler(&mut error, handlers, env, writer)? {
+                            Some((index, object)) => {
+                                stage = Stage::Handler(index, object);
+                                continue;
+                            }
+                            None => Err(error),
+                        },
+                    },
+                    Stage::Handler(index, object) => {
+                        let handler = &handlers[index];
+                        let position = position.take();
+                        match env.while_handling(&object, |env| run_block(&handler.body, position, env, writer)) {
+                            Ok(Step::Yield(value, block)) => {
+                                return Ok(Step::Yield(value, Statement::Try(Stage::Handler(index, object), block)));
+                            }
+                            result => {
+                                // The name is unbound once the handler is done.
+                                if let Some(name) = &handler.name {
+                                    env.remove(name);
+                                }
+                                result.map(|step| match step {
+                                    Step::Done(flow) => flow,
+                                    Step::Yield(..) => unreachable!("yields are returned above"),
+                                })
+                            }
+                        }
+                    }
+                    Stage::Else => match run_block(orelse, position.take(), env, writer)? {
+                        Step::Yield(value, block) => return Ok(Step::Yield(value, Statement::Try(Stage::Else, block))),
+                        Step::Done(flow) => Ok(flow),
+                    },
+                    Stage::Finally(result, object) => {
+                        return run_finally(finalbody, result, object, position.take(), env, writer);
+                    }
+                };
+                if finalbody.is_empty() {
+                    return result.map(Step::Done);
+                }
+                stage = Stage::Finally(result, None);
+            }
+        }
+        stmt => execute(stmt, env, writer).map(Step::Done),
+    }
+}
+
+// The `else` block of a loop that ran to the end.
+fn run_loop_else<W: Write>(
+    orelse: &[Expr],
+    position: Option<(Block, Resume)>,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Step<Statement>, PyError> {
+    Ok(match run_block(orelse, position, env, writer)? {
+        Step::Yield(value, block) => Step::Yield(value, Statement::LoopElse(block)),
+        Step::Done(flow) => Step::Done(flow),
+    })
+}
+
+// Runs, or resumes, the `finally` block of a `try` statement once the rest
+// has finished with `result`. As with `execute`, a return, break or continue
+// in the block replaces that outcome.
+fn run_finally<W: Write>(
+    finalbody: &[Expr],
+    mut result: Result<Flow, PyError>,
+    object: Option<Value>,
+    position: Option<(Block, Resume)>,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Step<Statement>, PyError> {
+    // An error propagating through the block is being handled while it runs.
+    let object = match (&mut result, object) {
+        (_, Some(object)) => Some(object),
+        (Err(error), None) => Some(exception::catch(error, &env.frame_name())),
+        (Ok(_), None) => None,
+    };
+    let step = match &object {
+        Some(object) => env.while_handling(object, |env| run_block(finalbody, position, env, writer))?,
+        None => run_block(finalbody, position, env, writer)?,
+    };
+    match step {
+        Step::Yield(value, block) => Ok(Step::Yield(value, Statement::Try(Stage::Finally(result, object), block))),
+        Step::Done(Flow::Normal) => result.map(Step::Done),
+        Step::Done(flow) => Ok(Step::Done(flow)),
+    }
+}
+
+// A `yield` or `yield from` statement, or an assignment of what one evaluates
+// to. Resuming it gives the value sent, or raises the error thrown, there.
+fn run_yield<W: Write>(
+    value: &Expr,
+    target: Option<&Expr>,
+    span: Span,
+    position: Option<(Statement, Resume)>,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Step<Statement>, PyError> {
+    // Errors raised at the `yield` are located at it.
+    let (Expr::Yield(_, yield_span) | Expr::YieldFrom(_, yield_span)) = value else {
+        unreachable!("only yields are run here")
+    };
+    let received = match (value, position) {
+        (Expr::Yield(value, _), None) => {
+            let value = match value {
+                Some(value) => evaluate(value, env, writer)?,
+                None => Value::None,
+            };
+            return Ok(Step::Yield(value, Statement::Yield));
+        }
+        (_, Some((Statement::Yield, Resume::Send(value)))) => value,
+        (_, Some((Statement::Yield, Resume::Throw(mut error)))) => {
+            // The error is raised at the `yield`, whatever traceback it had.
+            error.span = Some(*yield_span);
+            return Err(error);
+        }
+        (Expr::YieldFrom(iterable, _), None) => {
+            let iterable = evaluate(iterable, env, writer)?;
+            let iterator = special::iter(&iterable, *yield_span, env, writer).map_err(|e| e.at(*yield_span))?;
+            match delegate(&iterator, Resume::Send(Value::None), *yield_span, env, writer)? {
+                Delegated::Yielded(value) => return Ok(Step::Yield(value, Statement::YieldFrom(iterator))),
+                Delegated::Returned(value) => value,
+            }
+        }
+        (_, Some((Statement::YieldFrom(iterator), resume))) => {
+            match delegate(&iterator, resume, *yield_span, env, writer)? {
+                Delegated::Yielded(value) => return Ok(Step::Yield(value, Statement::YieldFrom(iterator))),
+                Delegated::Returned(value) => value,
+            }
+        }
+        _ => unreachable!("a yield statement resumes where it stopped"),
+    };
+    if let Some(target) = target {
+        assign(target, received, span, env, writer)?;
+    }
+    Ok(Step::Done(Flow::Normal))
+}
+
+// Passes what a generator running `yield from` is resumed with on to the
+// iterator it delegates to. The value the iterator returns, carried by its
+// StopIteration, is what `yield from` evaluates to.
+fn delegate<W: Write>(
+    iterator: &Value,
+    resume: Resume,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Delegated, PyError> {
+    let result = match (iterator, resume) {
+        (Value::Generator(generator), Resume::Throw(error)) if error.kind == ErrorKind::GeneratorExit => {
+            close(generator, span, env, writer)?;
+            return Err(error);
+        }
+        (Value::Generator(generator), resume) => self::resume(generator, resume, span, env, writer),
+        (_, Resume::Send(Value::None)) => special::next(iterator, span, env, writer).map_err(|e| e.at(span)),
+        (_, Resume::Send(value)) => call_method(iterator, "send", vec![value], span, env, writer),
+        (_, Resume::Throw(mut error)) => {
+            let name = if error.kind == ErrorKind::GeneratorExit { "close" } else { "throw" };
+            match get_attribute(iterator, name) {
+                Ok(method) if name == "close" => {
+                    call_function(&method, Vec::new(), Vec::new(), span, env, writer)?;
+                    Err(error)
+                }
+                Ok(method) => {
+                    let object = exception::exception_object(&mut error);
+                    call_function(&method, vec![object], Vec::new(), span, env, writer)
+                }
+                Err(_) => Err(error),
+            }
+        }
+    };
+    match result {
+        Ok(value) => Ok(Delegated::Yielded(value)),
+        Err(mut error) if error.kind == ErrorKind::StopIteration => {
+            Ok(Delegated::Returned(exception::stop_value(&mut error)))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn call_method<W: Write>(
+    object: &Value,
+    name: &str,
+    args: Vec<Value>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let method = get_attribute(object, name).map_err(|e| e.at(span))?;
+    call_function(&method, args, Vec::new(), span, env, writer)
+}
@@ -0,0 +1,152 @@
+use crate::ast::{Value, ViewKind};
+use crate::error::{ErrorKind, PyError};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::vec;
+
+#[derive(Debug)]
+enum State {
+    // Lists and tuples are walked by index, so items appended to a list while
+    // it is being iterated are produced too. So are objects with `__getitem__`
+    // but no `__iter__`, which the caller indexes until IndexError.
+    Sequence { sequence: Value, index: usize },
+    Range { next: i64, step: i64, remaining: usize },
+    // Strings, bytes, dicts and sets are iterated over a snapshot of their items.
+    // Dicts and sets keep the container and its starting size, since
+    // resizing one mid-iteration is an error.
+    Snapshot { items: vec::IntoIter<Value>, source: Option<(Value, usize)> },
+    Exhausted,
+}
+
+// The iterator object returned by iter(), which is also what for loops and
+// unpacking walk under the hood.
+#[derive(Debug)]
+pub struct Iter {
+    type_name: &'static str,
+    state: State,
+}
+
+impl Iter {
+    // iter(value): a fresh iterator over any iterable value.
+    pub fn new(value: &Value) -> Result<Iter, PyError> {
+        let snapshot = |items: Vec<Value>, source: Option<(Value, usize)>| State::Snapshot {
+            items: items.into_iter(),
+            source,
+        };
+        let (type_name, state) = match value {
+            Value::List(_) => ("list_iterator", State::Sequence { sequence: value.clone(), index: 0 }),
+            Value::Tuple(_) => ("tuple_iterator", State::Sequence { sequence: value.clone(), index: 0 }),
+            Value::Range(range) => (
+                "range_iterator",
+                State::Range { next: range.start, step: range.step, remaining: range.len() },
+            ),
+            Value::Str(s) => {
+                let type_name = if s.is_ascii() { "str_ascii_iterator" } else { "str_iterator" };
+                (type_name, snapshot(s.chars().map(|c| Value::Str(c.to_string())).collect(), None))
+            }
+            Value::Bytes(bytes) => {
+                ("bytes_iterator", snapshot(bytes.iter().map(|&b| Value::Int(b as i64)).collect(), None))
+            }
+            Value::Dict(dict) | Value::DictView(dict, _) => {
+                let dict_ref = dict.borrow();
+                let (type_name, items) = match value {
+                    Value::DictView(_, ViewKind::Values) => ("dict_valueiterator", dict_ref.values()),
+                    Value::DictView(_, ViewKind::Items) => ("dict_itemiterator", dict_ref.items()),
+                    _ => ("dict_keyiterator", dict_ref.keys()),
+                };
+                (type_name, snapshot(items, Some((Value::Dict(Rc::clone(dict)), dict_ref.len()))))
+            }
+            Value::Set(set) => {
+                let set_ref = set.borrow();
+                ("set_iterator", snapshot(set_ref.items(), Some((value.clone(), set_ref.len()))))
+            }
+            Value::FrozenSet(set) => ("set_iterator", snapshot(set.items(), None)),
+            _ => {
+                return Err(PyError::new(
+                    ErrorKind::TypeError,
+                    format!("'{}' object is not iterable", value.type_name()),
+                ))
+            }
+        };
+        Ok(Iter { type_name, state })
+    }
+
+    // iter() of an object that only supports indexing.
+    pub fn indexed(object: Value) -> Iter {
+        Iter { type_name: "iterator", state: State::Sequence { sequence: object, index: 0 } }
+    }
+
+    // For an iterator made by `indexed`, the object and the index to fetch
+    // next, moving past it.
+    pub fn next_index(&mut self) -> Option<(Value, usize)> {
+        match &mut self.state {
+            State::Sequence { sequence: object @ Value::Instance(_), index } => {
+                *index += 1;
+                Some((object.clone(), *index - 1))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.state = State::Exhausted;
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    // The next item, or None once the iterator is exhausted; an exhausted
+    // iterator stays exhausted even if its container grows again.
+    pub fn next(&mut self) -> Result<Option<Value>, PyError> {
+        let item = match &mut self.state {
+            State::Sequence { sequence, index } => {
+                let item = match sequence {
+                    Value::List(items) => items.borrow().get(*index).cloned(),
+                    Value::Tuple(items) => items.get(*index).cloned(),
+                    _ => None,
+                };
+                *index += 1;
+                item
+            }
+            State::Range { next, step, remaining } => {
+                if *remaining == 0 {
+                    None
+                } else {
+                    let item = *next;
+                    *remaining -= 1;
+                    // The step past the last item may overflow; it's never used.
+                    *next = next.wrapping_add(*step);
+                    Some(Value::Int(item))
+                }
+            }
+            State::Snapshot { items, source } => {
+                if let Some((source, len)) = source {
+                    let (current, message) = match source {
+                        Value::Set(set) => (set.borrow().len(), "Set changed size during iteration"),
+                        Value::Dict(dict) => (dict.borrow().len(), "dictionary changed size during iteration"),
+                        _ => (*len, ""),
+                    };
+                    if current != *len {
+                        self.state = State::Exhausted;
+                        return Err(PyError::new(ErrorKind::RuntimeError, message));
+                    }
+                }
+                items.next()
+            }
+            State::Exhausted => None,
+        };
+        if item.is_none() {
+            self.state = State::Exhausted;
+        }
+        Ok(item)
+    }
+}
+
+// Iterating an iterator continues it; any other iterable starts afresh.
+pub fn iterate(value: &Value) -> Result<Rc<RefCell<Iter>>, PyError> {
+    match value {
+        Value::Iterator(iter) => Ok(Rc::clone(iter)),
+        _ => Ok(Rc::new(RefCell::new(Iter::new(value)?))),
+    }
+}
@@ -1,75 +1,174 @@
-use crate::token::Token;
+use crate::bigint::BigInt;
+use crate::error::{ErrorKind, PyError, Span};
+use crate::token::{FStringPart, SpannedToken, Token};
 
-pub fn lex(input: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut indent_stack: Vec<usize> = vec![0];
+// Augmented assignment operators, longest first so `**=` wins over `*=`.
+const AUGMENTED_OPERATORS: &[&str] = &["**=", "//=", "<<=", ">>=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^="];
 
-    // Process input line by line (to handle indentation)
-    for line in input.lines() {
+// A string literal still open at the end of a line: triple-quoted strings
+// run on until their closing quotes, and a backslash at the end of a line
+// continues any string onto the next.
+struct OpenString {
+    quote: &'static str,
+    raw: bool,
+    bytes: bool,
+    formatted: bool, // an f-string
+    body: String, // the source text between the quotes so far
+    span: Span,   // where the literal (including its prefix) starts
+}
+
+// Turns source text into tokens one physical line at a time, so that callers
+// such as the REPL can feed input incrementally while the indent stack and
+// any string literal still open carry over from line to line.
+pub struct Lexer {
+    tokens: Vec<SpannedToken>,
+    indent_stack: Vec<usize>,
+    line: usize,
+    depth: usize, // open brackets; lines inside brackets join implicitly
+    open_string: Option<OpenString>,
+}
+
+impl Default for Lexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lexer {
+    pub fn new() -> Self {
+        Self { tokens: Vec::new(), indent_stack: vec![0], line: 0, depth: 0, open_string: None }
+    }
+
+    fn push(&mut self, token: Token, start: usize, end: usize) {
+        let span = Span::new(self.line, start, end);
+        self.tokens.push(SpannedToken { token, span });
+    }
+
+    pub fn lex_line(&mut self, line: &str) -> Result<(), PyError> {
+        self.line += 1;
+        // A line that carries on a string literal has no indentation of its own.
+        if let Some(open) = self.open_string.take() {
+            return match self.scan_string(open, line, 0)? {
+                Some(end) => self.lex_tokens(line, end),
+                None => Ok(()),
+            };
+        }
         let indent = line.chars().take_while(|c| *c == ' ').count();
         let trimmed = line.trim_start();
-        if trimmed.is_empty() {
-            continue;
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Ok(());
         }
-        // Emit Indent/Dedent tokens
-        let current_indent = *indent_stack.last().unwrap();
-        if indent > current_indent {
-            indent_stack.push(indent);
-            tokens.push(Token::Indent);
-        } else {
-            while indent < *indent_stack.last().unwrap() {
-                indent_stack.pop();
-                tokens.push(Token::Dedent);
+        // Emit Indent/Dedent tokens, except on continuation lines inside brackets.
+        let current_indent = *self.indent_stack.last().unwrap();
+        if self.depth == 0 && indent > current_indent {
+            self.indent_stack.push(indent);
+            self.push(Token::Indent, 0, indent);
+        } else if self.depth == 0 {
+            while indent < *self.indent_stack.last().unwrap() {
+                self.indent_stack.pop();
+                self.push(Token::Dedent, 0, indent);
+            }
+            if indent != *self.indent_stack.last().unwrap() {
+                return Err(PyError::new(
+                    ErrorKind::IndentationError,
+                    "unindent does not match any outer indentation level",
+                )
+                .at(Span::new(self.line, 0, indent)));
             }
         }
-        // Lex the tokens for the current (trimmed) line
-        let mut chars = trimmed.chars().peekable();
-        while let Some(&ch) = chars.peek() {
-            match ch {
-                ' ' | '\t' => { chars.next(); } // skip inner whitespace
-                '#' => {
-                    // Skip comment until newline
-                    while let Some(c) = chars.next() {
-                        if c == '\n' { break; }
-                    }
+        self.lex_tokens(line, line.len() - trimmed.len())
+    }
+
+    // Lexes the tokens of a line from the given byte offset on; positions are
+    // byte offsets into the full line.
+    fn lex_tokens(&mut self, line: &str, offset: usize) -> Result<(), PyError> {
+        let mut chars = line[offset..].char_indices().map(|(i, c)| (i + offset, c)).peekable();
+        let line_end = line.len();
+        while let Some(&(start, ch)) = chars.peek() {
+            if let Some(operator) = AUGMENTED_OPERATORS.iter().find(|op| line[start..].starts_with(**op)) {
+                for _ in 0..operator.len() {
+                    chars.next();
                 }
-                '0'..='9' => {
+                self.push(Token::AugmentedAssign(operator.to_string()), start, start + operator.len());
+                continue;
+            }
+            if let Some((prefix, quote)) = string_start(&line[start..]) {
+                let prefix = prefix.to_ascii_lowercase();
+                let open = OpenString {
+                    quote,
+                    raw: prefix.contains('r'),
+                    bytes: prefix.contains('b'),
+                    formatted: prefix.contains('f'),
+                    body: String::new(),
+                    span: Span::new(self.line, start, start + 1),
+                };
+                match self.scan_string(open, line, start + prefix.len() + quote.len())? {
+                    Some(end) => while chars.next_if(|&(i, _)| i < end).is_some() {},
+                    None => return Ok(()), // the string goes on past this line
+                }
+                continue;
+            }
+            let token = match ch {
+                ' ' | '\t' => { chars.next(); continue; } // skip inner whitespace
+                '#' => break, // comment runs to the end of the line
+                // A leading '.' starts a number only when a digit follows, as in `.5`.
+                '0'..='9' | '.' if ch != '.' || line[start + 1..].starts_with(|c: char| c.is_ascii_digit()) => {
                     let mut number = String::new();
-                    while let Some(&digit) = chars.peek() {
-                        if digit.is_digit(10) || digit == '.' {
+                    while let Some(&(_, digit)) = chars.peek() {
+                        if digit.is_ascii_digit() || digit == '.' {
                             number.push(digit);
                             chars.next();
                         } else {
                             break;
                         }
                     }
-                    if number.contains('.') {
-                        if let Ok(float_value) = number.parse::<f64>() {
-                            tokens.push(Token::Float(float_value));
-                        } else {
-                            tokens.push(Token::Unknown('.'));
+                    // An exponent such as `e10` or `E-5` makes the literal a float.
+                    if let Some(&(_, 'e' | 'E')) = chars.peek() {
+                        let mut lookahead = chars.clone();
+                        let marker = lookahead.next().unwrap().1;
+                        let sign = match lookahead.peek() {
+                            Some(&(_, sign @ ('+' | '-'))) => {
+                                lookahead.next();
+                                Some(sign)
+                            }
+                            _ => None,
+                        };
+                        if matches!(lookahead.peek(), Some((_, c)) if c.is_ascii_digit()) {
+                            number.push(marker);
+                            number.extend(sign);
+                            while let Some(&(_, digit)) = lookahead.peek() {
+                                if !digit.is_ascii_digit() {
+                                    break;
+                                }
+                                number.push(digit);
+                                lookahead.next();
+                            }
+                            chars = lookahead;
                         }
-                    } else if let Ok(int_value) = number.parse::<i64>() {
-                        tokens.push(Token::Number(int_value));
-                    }
-                }
-                '\'' => {
-                    // String literal (using single quotes)
-                    chars.next(); // consume opening quote
-                    let mut literal = String::new();
-                    while let Some(&c) = chars.peek() {
-                        if c == '\'' { break; }
-                        literal.push(c);
-                        chars.next();
                     }
-                    if let Some(&'\'') = chars.peek() {
-                        chars.next(); // consume closing quote
+                    let span = Span::new(self.line, start, start + number.len());
+                    if number.contains(['.', 'e', 'E']) {
+                        match number.parse::<f64>() {
+                            Ok(float_value) => Token::Float(float_value),
+                            Err(_) => {
+                                return Err(PyError::new(ErrorKind::SyntaxError, "invalid decimal literal").at(span))
+                            }
+                        }
+                    } else {
+                        match number.parse::<i64>() {
+                            Ok(int_value) => Token::Number(int_value),
+                            Err(_) => match BigInt::parse(&number) {
+                                Some(big_value) => Token::BigNumber(big_value),
+                                None => {
+                                    return Err(PyError::new(ErrorKind::SyntaxError, "invalid decimal literal").at(span))
+                                }
+                            },
+                        }
                     }
-                    tokens.push(Token::StringLiteral(literal));
                 }
-                'a'..='z' | 'A'..='Z' => {
+                'a'..='z' | 'A'..='Z' | '_' => {
                     let mut ident = String::new();
-                    while let Some(&c) = chars.peek() {
+                    while let Some(&(_, c)) = chars.peek() {
                         if c.is_alphanumeric() || c == '_' {
                             ident.push(c);
                             chars.next();
@@ -78,56 +177,570 @@ pub fn lex(input: &str) -> Vec<Token> {
                         }
                     }
                     match ident.as_str() {
-                        "print" => tokens.push(Token::Print),
-                        "def" => tokens.push(Token::Def),
-                        "if" => tokens.push(Token::If),
-                        "else" => tokens.push(Token::Else),
-                        "return" => tokens.push(Token::Return),
-                        "for" => tokens.push(Token::For),
-                        "while" => tokens.push(Token::While),
-                        "in" => tokens.push(Token::In),
-                        "range" => tokens.push(Token::Range),
-                        _ => tokens.push(Token::Identifier(ident)),
+                        "print" => Token::Print,
+                        "def" => Token::Def,
+                        "class" => Token::Class,
+                        "pass" => Token::Pass,
+                        "if" => Token::If,
+                        "elif" => Token::Elif,
+                        "else" => Token::Else,
+                        "return" => Token::Return,
+                        "break" => Token::Break,
+                        "continue" => Token::Continue,
+                        "for" => Token::For,
+                        "while" => Token::While,
+                        "in" => Token::In,
+                        "True" => Token::True,
+                        "False" => Token::False,
+                        "None" => Token::None,
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "not" => Token::Not,
+                        "is" => Token::Is,
+                        "import" => Token::Import,
+                        "del" => Token::Del,
+                        "try" => Token::Try,
+                        "except" => Token::Except,
+                        "finally" => Token::Finally,
+                        "raise" => Token::Raise,
+                        "from" => Token::From,
+                        "yield" => Token::Yield,
+                        "lambda" => Token::Lambda,
+                        "global" => Token::Global,
+                        "nonlocal" => Token::Nonlocal,
+                        "as" => Token::As,
+                        _ => Token::Identifier(ident),
                     }
                 }
-                '>' => { tokens.push(Token::Greater); chars.next(); }
-                '<' => { tokens.push(Token::Less); chars.next(); }
-                '-' => { tokens.push(Token::Minus); chars.next(); }
-                '+' => { tokens.push(Token::Plus); chars.next(); }
                 '*' => {
                     chars.next();
-                    if let Some(&'*') = chars.peek() {
+                    if let Some(&(_, '*')) = chars.peek() {
                         chars.next();
-                        tokens.push(Token::DoubleStar);
+                        Token::DoubleStar
                     } else {
-                        tokens.push(Token::Star);
+                        Token::Star
                     }
                 }
                 '/' => {
                     chars.next();
-                    if let Some(&'/') = chars.peek() {
+                    if let Some(&(_, '/')) = chars.peek() {
                         chars.next();
-                        tokens.push(Token::DoubleSlash);
+                        Token::DoubleSlash
                     } else {
-                        tokens.push(Token::Slash);
+                        Token::Slash
+                    }
+                }
+                '<' | '>' if line[start + 1..].starts_with(ch) => {
+                    chars.next();
+                    chars.next();
+                    if ch == '<' { Token::LeftShift } else { Token::RightShift }
+                }
+                '<' | '>' | '=' | '!' => {
+                    chars.next();
+                    let followed_by_equals = matches!(chars.peek(), Some(&(_, '=')));
+                    if followed_by_equals {
+                        chars.next();
+                    }
+                    match (ch, followed_by_equals) {
+                        ('<', true) => Token::LessEqual,
+                        ('<', false) => Token::Less,
+                        ('>', true) => Token::GreaterEqual,
+                        ('>', false) => Token::Greater,
+                        ('=', true) => Token::EqualEqual,
+                        ('=', false) => Token::Equals,
+                        ('!', true) => Token::NotEqual,
+                        _ => {
+                            let span = Span::new(self.line, start, start + 1);
+                            return Err(PyError::new(ErrorKind::SyntaxError, "invalid syntax").at(span));
+                        }
                     }
                 }
-                '=' => { tokens.push(Token::Equals); chars.next(); }
-                ',' => { tokens.push(Token::Comma); chars.next(); }
-                '(' => { tokens.push(Token::OpenParen); chars.next(); }
-                ')' => { tokens.push(Token::CloseParen); chars.next(); }
-                ':' => { tokens.push(Token::Colon); chars.next(); }
                 _ => {
-                    tokens.push(Token::Unknown(ch));
+                    let token = match ch {
+                        '-' => Token::Minus,
+                        '+' => Token::Plus,
+                        '%' => Token::Percent,
+                        '|' => Token::Pipe,
+                        '&' => Token::Ampersand,
+                        '^' => Token::Caret,
+                        '~' => Token::Tilde,
+                        ',' => Token::Comma,
+                        '(' | '[' | '{' => {
+                            self.depth += 1;
+                            match ch {
+                                '(' => Token::OpenParen,
+                                '[' => Token::OpenBracket,
+                                _ => Token::OpenBrace,
+                            }
+                        }
+                        ')' | ']' | '}' => {
+                            self.depth = self.depth.saturating_sub(1);
+                            match ch {
+                                ')' => Token::CloseParen,
+                                ']' => Token::CloseBracket,
+                                _ => Token::CloseBrace,
+                            }
+                        }
+                        '.' => Token::Dot,
+                        ':' => Token::Colon,
+                        _ => {
+                            let span = Span::new(self.line, start, start + ch.len_utf8());
+                            let message = format!("invalid character '{}'", ch);
+                            return Err(PyError::new(ErrorKind::SyntaxError, message).at(span));
+                        }
+                    };
+                    chars.next();
+                    token
+                }
+            };
+            let end = chars.peek().map_or(line_end, |&(i, _)| i);
+            self.push(token, start, end);
+        }
+        if self.depth == 0 {
+            self.push(Token::Newline, line_end, line_end + 1);
+        }
+        Ok(())
+    }
+
+    // Scans the rest of a string literal from the given offset. Returns the
+    // offset just past the closing quote, or None when the literal continues
+    // on the next line.
+    fn scan_string(&mut self, mut open: OpenString, line: &str, from: usize) -> Result<Option<usize>, PyError> {
+        let mut chars = line[from..].char_indices().map(|(i, c)| (i + from, c));
+        let mut continued = false;
+        while let Some((i, c)) = chars.next() {
+            if line[i..].starts_with(open.quote) {
+                let end = i + open.quote.len();
+                // A literal spanning several lines is located by its first line.
+                let span = if open.span.line == self.line { Span::new(self.line, open.span.start, end) } else { open.span };
+                let token = decode_string(&open, span)?;
+                self.tokens.push(SpannedToken { token, span });
+                return Ok(Some(end));
+            }
+            open.body.push(c);
+            // A backslash keeps the next character (even a quote) in the string.
+            if c == '\\' {
+                match chars.next() {
+                    Some((_, escaped)) => open.body.push(escaped),
+                    None => continued = true,
+                }
+            }
+        }
+        if open.quote.len() == 1 && !continued {
+            return Err(unterminated_string(&open, self.line));
+        }
+        open.body.push('\n');
+        self.open_string = Some(open);
+        Ok(None)
+    }
+
+    // True while the input so far is unfinished: a bracket or string is still
+    // open, a line ended with ':' and its body hasn't started, or an indented
+    // block is still open.
+    pub fn needs_more(&self) -> bool {
+        self.depth > 0
+            || self.in_string()
+            || self.indent_stack.len() > 1
+            || matches!(self.tokens.iter().rev().nth(1), Some(SpannedToken { token: Token::Colon, .. }))
+    }
+
+    // True while a string literal runs on past the last line lexed.
+    pub fn in_string(&self) -> bool {
+        self.open_string.is_some()
+    }
+
+    // Closes any blocks still open at the end of input.
+    pub fn finish(mut self) -> Result<Vec<SpannedToken>, PyError> {
+        if let Some(open) = &self.open_string {
+            return Err(unterminated_string(open, self.line));
+        }
+        while self.indent_stack.len() > 1 {
+            self.indent_stack.pop();
+            self.push(Token::Dedent, 0, 0);
+        }
+        Ok(self.tokens)
+    }
+}
+
+pub fn lex(input: &str) -> Result<Vec<SpannedToken>, PyError> {
+    let mut lexer = Lexer::new();
+    // Process input line by line (to handle indentation)
+    for line in input.lines() {
+        lexer.lex_line(line)?;
+    }
+    lexer.finish()
+}
+
+// The prefix and opening quote of a string literal starting the text, if one
+// does: `'`, `"`, or their tripled forms, after an optional r, u, b or f prefix.
+fn string_start(text: &str) -> Option<(&str, &'static str)> {
+    let prefix_len = text.find(['\'', '"'])?;
+    let prefix = &text[..prefix_len];
+    if !matches!(prefix.to_ascii_lowercase().as_str(), "" | "r" | "u" | "b" | "rb" | "br" | "f" | "rf" | "fr") {
+        return None;
+    }
+    let quote = ["\'\'\'", "\"\"\"", "'", "\""].into_iter().find(|quote| text[prefix_len..].starts_with(quote))?;
+    Some((prefix, quote))
+}
+
+fn unterminated_string(open: &OpenString, line: usize) -> PyError {
+    let kind = if open.quote.len() == 3 { "triple-quoted string" } else { "string" };
+    let message = format!("unterminated {} literal (detected at line {})", kind, line);
+    PyError::new(ErrorKind::SyntaxError, message).at(open.span)
+}
+
+// The token for a complete string literal, with its escapes processed.
+fn decode_string(open: &OpenString, span: Span) -> Result<Token, PyError> {
+    if open.bytes && !open.body.is_ascii() {
+        return Err(PyError::new(ErrorKind::SyntaxError, "bytes can only contain ASCII literal characters").at(span));
+    }
+    let end = Span::new(span.line, span.end, span.end + 1);
+    if open.formatted {
+        return Ok(Token::FString(split_fstring(&open.body, open.raw, 0, span, end)?));
+    }
+    let text = if open.raw {
+        open.body.clone()
+    } else {
+        unescape(&open.body, open.bytes).map_err(|message| PyError::new(ErrorKind::SyntaxError, message).at(end))?
+    };
+    Ok(if open.bytes { Token::Bytes(text.chars().map(|c| c as u8).collect()) } else { Token::StringLiteral(text) })
+}
+
+// Processes the backslash escapes of a literal. For bytes literals each
+// resulting char stands for one byte, so stays below 256.
+fn unescape(body: &str, bytes: bool) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = body.chars().peekable();
+    // Error positions count as CPython does, where each non-ASCII character
+    // has been spelled out as a ten-character \UXXXXXXXX escape.
+    let width = |c: char| if c.is_ascii() { 1 } else { 10 };
+    let mut position = 0;
+    let decode_error = |start: usize, end: usize, reason: &str| {
+        format!("(unicode error) 'unicodeescape' codec can't decode bytes in position {}-{}: {}", start, end - 1, reason)
+    };
+    while let Some(c) = chars.next() {
+        let start = position;
+        position += width(c);
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let Some(escape) = chars.next() else {
+            out.push(c); // only an f-string's text before a field can end in a backslash
+            break;
+        };
+        position += width(escape);
+        match escape {
+            '\n' => {} // a backslash at the end of a line joins it to the next
+            '\\' | '\'' | '"' => out.push(escape),
+            'a' => out.push('\x07'),
+            'b' => out.push('\x08'),
+            'f' => out.push('\x0c'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'v' => out.push('\x0b'),
+            '0'..='7' => {
+                let mut value = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) else { break };
+                    value = value * 8 + digit;
+                    position += 1;
+                    chars.next();
+                }
+                out.push(char::from_u32(if bytes { value & 0xff } else { value }).unwrap());
+            }
+            'x' | 'u' | 'U' if escape == 'x' || !bytes => {
+                let digits = match escape {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let mut value = 0u32;
+                for _ in 0..digits {
+                    let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) else {
+                        return Err(if bytes {
+                            format!("(value error) invalid \\x escape at position {}", start)
+                        } else {
+                            decode_error(start, position, &format!("truncated \\{}{} escape", escape, "X".repeat(digits)))
+                        });
+                    };
+                    value = value * 16 + digit;
+                    position += 1;
                     chars.next();
                 }
+                match char::from_u32(value) {
+                    Some(c) => out.push(c),
+                    None => return Err(decode_error(start, position, "illegal Unicode character")),
+                }
+            }
+            // There is no table of character names to look `\N{...}` up in.
+            'N' if !bytes => {
+                for c in chars.by_ref() {
+                    position += width(c);
+                    if c == '}' {
+                        break;
+                    }
+                }
+                return Err(decode_error(start, position, "unknown Unicode character name"));
+            }
+            // Unrecognized escapes are kept as written.
+            _ => {
+                out.push('\\');
+                out.push(escape);
+            }
+        }
+    }
+    Ok(out)
+}
+
+// Splits the text of an f-string into literal text and replacement fields,
+// lexing the expression of each field. Format specs are split the same way,
+// one level of fields deep. `span` locates the whole literal and `end` is
+// where errors point.
+fn split_fstring(body: &str, raw: bool, depth: usize, span: Span, end: Span) -> Result<Vec<FStringPart>, PyError> {
+    let error = |message: &str| PyError::new(ErrorKind::SyntaxError, message).at(end);
+    let chars: Vec<char> = body.chars().collect();
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if (c == '{' || c == '}') && chars.get(i + 1) == Some(&c) {
+            literal.push(c);
+            i += 2;
+            continue;
+        }
+        if c == '}' {
+            return Err(error("f-string: single '}' is not allowed"));
+        }
+        if c != '{' {
+            literal.push(c);
+            i += 1;
+            continue;
+        }
+        if depth >= 2 {
+            return Err(error("f-string: expressions nested too deeply"));
+        }
+        // The expression runs to the first '=', '!', ':' or '}' outside any
+        // brackets or strings, where a '=' or '!' starting an operator such
+        // as `==` or `!=` doesn't count.
+        let start = i + 1;
+        let mut j = start;
+        let mut brackets = 0;
+        let mut quote = None;
+        loop {
+            let Some(&c) = chars.get(j) else { return Err(error("f-string: expecting '}'")) };
+            match (c, quote) {
+                ('\\', _) => return Err(error("f-string expression part cannot include a backslash")),
+                (_, Some(q)) if c == q => quote = None,
+                (_, Some(_)) => {}
+                ('\'' | '"', None) => quote = Some(c),
+                ('(' | '[' | '{', None) => brackets += 1,
+                (')' | ']' | '}', None) if brackets > 0 => brackets -= 1,
+                ('}' | ':', None) if brackets == 0 => break,
+                ('!', None) if brackets == 0 && chars.get(j + 1) != Some(&'=') => break,
+                ('=', None)
+                    if brackets == 0
+                        && chars.get(j + 1) != Some(&'=')
+                        && !matches!(chars.get(j.wrapping_sub(1)), Some('=' | '!' | '<' | '>')) =>
+                {
+                    break
+                }
+                _ => {}
+            }
+            j += 1;
+        }
+        let expression: String = chars[start..j].iter().collect();
+        if expression.trim().is_empty() {
+            return Err(error("f-string: empty expression not allowed"));
+        }
+        // `{name=}` prints the expression's text before its value.
+        let mut debug = None;
+        if chars[j] == '=' {
+            j += 1;
+            while chars.get(j).is_some_and(|c| c.is_whitespace()) {
+                j += 1;
+            }
+            debug = Some(chars[start..j].iter().collect::<String>());
+        }
+        let mut conversion = None;
+        if chars.get(j) == Some(&'!') {
+            match (chars.get(j + 1), chars.get(j + 2)) {
+                (Some(&c @ ('s' | 'r' | 'a')), Some(':' | '}')) => conversion = Some(c),
+                _ => return Err(error("f-string: invalid conversion character: expected 's', 'r', or 'a'")),
+            }
+            j += 2;
+        }
+        let mut spec = Vec::new();
+        if chars.get(j) == Some(&':') {
+            let spec_start = j + 1;
+            let mut braces = 0;
+            j = spec_start;
+            while let Some(&c) = chars.get(j) {
+                match c {
+                    '{' => braces += 1,
+                    '}' if braces == 0 => break,
+                    '}' => braces -= 1,
+                    _ => {}
+                }
+                j += 1;
             }
+            let spec_text: String = chars[spec_start..j.min(chars.len())].iter().collect();
+            spec = split_fstring(&spec_text, raw, depth + 1, span, end)?;
+        }
+        if chars.get(j) != Some(&'}') {
+            return Err(error("f-string: expecting '}'"));
         }
-        tokens.push(Token::Newline);
+        i = j + 1;
+        if let Some(text) = debug {
+            literal.push_str(&text);
+            if conversion.is_none() && spec.is_empty() {
+                conversion = Some('r');
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(fstring_literal(&literal, raw, end)?);
+            literal.clear();
+        }
+        parts.push(FStringPart::Field { tokens: lex_fstring_expression(&expression, span)?, conversion, spec });
+    }
+    if !literal.is_empty() {
+        parts.push(fstring_literal(&literal, raw, end)?);
     }
-    while indent_stack.len() > 1 {
-        indent_stack.pop();
-        tokens.push(Token::Dedent);
+    Ok(parts)
+}
+
+fn fstring_literal(text: &str, raw: bool, end: Span) -> Result<FStringPart, PyError> {
+    if raw {
+        return Ok(FStringPart::Literal(text.to_string()));
+    }
+    let text = unescape(text, false).map_err(|message| PyError::new(ErrorKind::SyntaxError, message).at(end))?;
+    Ok(FStringPart::Literal(text))
+}
+
+// Lexes the expression of an f-string field, parenthesized as CPython does so
+// that it may span lines and start with spaces. Its tokens are all located at
+// the f-string itself.
+fn lex_fstring_expression(expression: &str, span: Span) -> Result<Vec<SpannedToken>, PyError> {
+    let mut lexer = Lexer::new();
+    for line in format!("({})", expression).lines() {
+        lexer.lex_line(line).map_err(|error| fstring_error(error, span))?;
+    }
+    let mut tokens = lexer.finish().map_err(|error| fstring_error(error, span))?;
+    for token in &mut tokens {
+        token.span = span;
+    }
+    Ok(tokens)
+}
+
+// Errors within an f-string's expressions name the f-string.
+pub fn fstring_error(mut error: PyError, span: Span) -> PyError {
+    if error.kind == ErrorKind::SyntaxError && !error.message.starts_with("f-string") {
+        error.message = format!("f-string: {}", error.message);
+    }
+    error.span = Some(span);
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The tokens of each replacement field in the f-string `source`, with
+    // the field's format spec as literal text.
+    fn fields(source: &str) -> Vec<(Vec<Token>, String)> {
+        let tokens = lex(source).expect("the f-string lexes");
+        let Some(Token::FString(parts)) = tokens.into_iter().map(|t| t.token).next() else {
+            panic!("expected an f-string");
+        };
+        parts
+            .into_iter()
+            .filter_map(|part| match part {
+                FStringPart::Field { tokens, spec, .. } => {
+                    let spec = spec
+                        .iter()
+                        .map(|part| match part {
+                            FStringPart::Literal(text) => text.clone(),
+                            FStringPart::Field { .. } => "{}".to_string(),
+                        })
+                        .collect();
+                    // Fields are lexed inside the parentheses that wrap them.
+                    let tokens: Vec<Token> = tokens.into_iter().map(|t| t.token).filter(|t| *t != Token::Newline).collect();
+                    assert_eq!((tokens.first(), tokens.last()), (Some(&Token::OpenParen), Some(&Token::CloseParen)));
+                    Some((tokens[1..tokens.len() - 1].to_vec(), spec))
+                }
+                FStringPart::Literal(_) => None,
+            })
+            .collect()
+    }
+
+    fn identifier(name: &str) -> Token {
+        Token::Identifier(name.to_string())
+    }
+
+    #[test]
+    fn slice_colons_stay_in_the_expression() {
+        let expected = vec![
+            identifier("s"),
+            Token::OpenBracket,
+            Token::Number(1),
+            Token::Colon,
+            Token::Number(3),
+            Token::CloseBracket,
+        ];
+        assert_eq!(fields("f'{s[1:3]}'"), vec![(expected.clone(), String::new())]);
+        assert_eq!(fields("f'{s[1:3]:>8}'"), vec![(expected, ">8".to_string())]);
+    }
+
+    #[test]
+    fn dict_literal_braces_and_colons_stay_in_the_expression() {
+        let expected = vec![
+            Token::OpenBrace,
+            Token::StringLiteral("a".to_string()),
+            Token::Colon,
+            Token::Number(1),
+            Token::CloseBrace,
+            Token::OpenBracket,
+            Token::StringLiteral("a".to_string()),
+            Token::CloseBracket,
+        ];
+        assert_eq!(fields("f'{ {\"a\": 1}[\"a\"] }'"), vec![(expected, String::new())]);
+    }
+
+    #[test]
+    fn keyword_argument_is_not_a_debug_specifier() {
+        let expected = vec![
+            identifier("l"),
+            Token::Dot,
+            identifier("sort"),
+            Token::OpenParen,
+            identifier("reverse"),
+            Token::Equals,
+            Token::True,
+            Token::CloseParen,
+        ];
+        assert_eq!(fields("f'{l.sort(reverse=True)}'"), vec![(expected, String::new())]);
+    }
+
+    #[test]
+    fn lambda_colon_inside_parentheses() {
+        let expected = vec![
+            Token::OpenParen,
+            Token::Lambda,
+            Token::Colon,
+            Token::Number(1),
+            Token::CloseParen,
+            Token::OpenParen,
+            Token::CloseParen,
+        ];
+        assert_eq!(fields("f'{(lambda: 1)()}'"), vec![(expected, String::new())]);
+    }
+
+    #[test]
+    fn conversion_and_spec_after_brackets() {
+        let (tokens, spec) = fields("f'{d[\"k\"]!r:{w}}'").remove(0);
+        assert_eq!(tokens.last(), Some(&Token::CloseBracket));
+        assert_eq!(spec, "{}");
     }
-    tokens
 }
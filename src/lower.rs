@@ -0,0 +1,506 @@
+use crate::ast::Expr;
+use crate::error::{ErrorKind, PyError, Span};
+
+// A generator can only be suspended at a `yield` statement or at an
+// assignment whose value is a `yield`; that is where its executor resumes.
+// A `yield` anywhere else in a generator's body, as in `x += yield x` or
+// `print((yield))`, is moved out of the expression around it into such an
+// assignment to a temporary, and the expression uses the temporary instead.
+// Whatever Python evaluates before the `yield` is saved in temporaries
+// first, so evaluation order and short-circuiting are unchanged. The
+// temporaries are named `.t0`, `.t1` and so on, which no Python code can use.
+pub fn generator_body(body: Vec<Expr>) -> Result<Vec<Expr>, PyError> {
+    Lowering { temporaries: 0 }.block(body)
+}
+
+// Where the first `yield` evaluated as part of an expression is, if any. The
+// bodies of functions and generator expressions nested in it don't count.
+pub fn find_yield(expr: &Expr) -> Option<Span> {
+    match expr {
+        Expr::Yield(_, span) | Expr::YieldFrom(_, span) => Some(*span),
+        Expr::JoinedString(exprs) | Expr::List(exprs) | Expr::Tuple(exprs) | Expr::Set(exprs, _) => first_yield(exprs),
+        Expr::FormattedValue { value, spec, .. } => find_yield(value).or_else(|| spec.as_deref().and_then(find_yield)),
+        Expr::Starred(value, _)
+        | Expr::Attribute { value, .. }
+        | Expr::UnaryOp { operand: value, .. }
+        | Expr::Not(value, _)
+        | Expr::GeneratorExp { iterable: value, .. } => find_yield(value),
+        Expr::Dict(entries, _) => first_yield(entries.iter().flat_map(|(key, value)| [key, value])),
+        Expr::Subscript { value, index, .. } => find_yield(value).or_else(|| find_yield(index)),
+        Expr::Slice { lower, upper, step } => first_yield([lower, upper, step].into_iter().flatten().map(|e| &**e)),
+        Expr::Arithmetic { left, right, .. } | Expr::Logical { left, right, .. } => {
+            find_yield(left).or_else(|| find_yield(right))
+        }
+        Expr::Compare { left, comparators, .. } => find_yield(left).or_else(|| first_yield(comparators)),
+        Expr::Call { function, args, keywords, .. } => find_yield(function)
+            .or_else(|| first_yield(args))
+            .or_else(|| first_yield(keywords.iter().map(|(_, arg)| arg))),
+        _ => None,
+    }
+}
+
+fn first_yield<'a>(exprs: impl IntoIterator<Item = &'a Expr>) -> Option<Span> {
+    exprs.into_iter().find_map(find_yield)
+}
+
+fn variable(name: &str, span: Span) -> Expr {
+    Expr::Variable(name.to_string(), span)
+}
+
+// Evaluating these has no effects and always gives the same value, so they
+// needn't be saved before a `yield`.
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::BigNumber(_) | Expr::Float(_) | Expr::String(_) | Expr::Bytes(_) => true,
+        Expr::Bool(_) | Expr::None => true,
+        // A temporary keeps its value once the expression it holds is done.
+        Expr::Variable(name, _) => name.starts_with('.'),
+        _ => false,
+    }
+}
+
+struct Lowering {
+    temporaries: usize,
+}
+
+impl Lowering {
+    fn block(&mut self, body: Vec<Expr>) -> Result<Vec<Expr>, PyError> {
+        let mut statements = Vec::new();
+        for stmt in body {
+            self.statement(stmt, &mut statements)?;
+        }
+        Ok(statements)
+    }
+
+    fn fresh(&mut self) -> String {
+        self.temporaries += 1;
+        format!(".t{}", self.temporaries - 1)
+    }
+
+    // Assigns the value to a new temporary, giving its name.
+    fn temporary(&mut self, value: Expr, span: Span, out: &mut Vec<Expr>) -> String {
+        let name = self.fresh();
+        out.push(Expr::Assignment(Box::new(variable(&name, span)), Box::new(value), span));
+        name
+    }
+
+    // The value, evaluated now unless evaluating it later makes no difference.
+    fn save(&mut self, value: Expr, span: Span, out: &mut Vec<Expr>) -> Expr {
+        if is_constant(&value) {
+            return value;
+        }
+        let name = self.temporary(value, span, out);
+        variable(&name, span)
+    }
+
+    // The statements of a lowered generator body each come from one
+    // statement of the original, with the original's span.
+    fn statement(&mut self, stmt: Expr, out: &mut Vec<Expr>) -> Result<(), PyError> {
+        match stmt {
+            Expr::Bare(value, span) => {
+                let value = match *value {
+                    value @ (Expr::Yield(..) | Expr::YieldFrom(..)) => self.suspension(value, span, out),
+                    value => self.expr(value, span, out),
+                };
+                out.push(Expr::Bare(Box::new(value), span));
+            }
+            Expr::Assignment(target, value, span) => {
+                let value = match *value {
+                    value @ (Expr::Yield(..) | Expr::YieldFrom(..)) => self.suspension(value, span, out),
+                    value => self.expr(value, span, out),
+                };
+                self.assign(*target, value, span, out);
+            }
+            Expr::AugmentedAssignment { target, operator, value, span } => {
+                if find_yield(&target).is_none() && find_yield(&value).is_none() {
+                    out.push(Expr::AugmentedAssignment { target, operator, value, span });
+                } else {
+                    self.augmented_assign(*target, operator, *value, span, out);
+                }
+            }
+            Expr::Print(args, span) => {
+                let args = self.in_order(args, span, out);
+                out.push(Expr::Print(args, span));
+            }
+            Expr::Return(value, span) => {
+                let value = value.map(|value| Box::new(self.expr(*value, span, out)));
+                out.push(Expr::Return(value, span));
+            }
+            Expr::IfCondition { condition, body, orelse, span } => {
+                let condition = Box::new(self.expr(*condition, span, out));
+                let body = self.block(body)?;
+                let orelse = self.block(orelse)?;
+                out.push(Expr::IfCondition { condition, body, orelse, span });
+            }
+            Expr::WhileLoop { condition, body, orelse, span } => {
+                let body = self.block(body)?;
+                let orelse = self.block(orelse)?;
+                self.while_loop(*condition, body, orelse, span, out);
+            }
+            Expr::ForLoop { target, iterable, body, orelse, span } => {
+                let iterable = Box::new(self.expr(*iterable, span, out));
+                let mut body = self.block(body)?;
+                let orelse = self.block(orelse)?;
+                let mut target = *target;
+                // A target such as `items[(yield)]` is assigned from a
+                // temporary at the start of each iteration.
+                if find_yield(&target).is_some() {
+                    let name = self.fresh();
+                    let mut assignment = Vec::new();
+                    self.assign(target, variable(&name, span), span, &mut assignment);
+                    body.splice(0..0, assignment);
+                    target = variable(&name, span);
+                }
+                out.push(Expr::ForLoop { target: Box::new(target), iterable, body, orelse, span });
+            }
+            Expr::Delete(targets, span) => {
+                if targets.iter().all(|target| find_yield(target).is_none()) {
+                    out.push(Expr::Delete(targets, span));
+                } else {
+                    // Each target is deleted before the next is evaluated.
+                    for target in targets {
+                        let target = self.expr(target, span, out);
+                        out.push(Expr::Delete(vec![target], span));
+                    }
+                }
+            }
+            Expr::Raise { exception, cause, span } => {
+                let mut parts = self.in_order(exception.into_iter().chain(cause).map(|e| *e).collect(), span, out);
+                let cause = if parts.len() == 2 { parts.pop().map(Box::new) } else { None };
+                out.push(Expr::Raise { exception: parts.pop().map(Box::new), cause, span });
+            }
+            Expr::ClassDef { name, qualname, bases, body, scope, span } => {
+                let bases = self.in_order(bases, span, out);
+                out.push(Expr::ClassDef { name, qualname, bases, body, scope, span });
+            }
+            Expr::Try { body, handlers, orelse, finalbody } => {
+                let body = self.block(body)?;
+                let mut lowered = Vec::with_capacity(handlers.len());
+                for mut handler in handlers {
+                    // The exception types are only evaluated once an exception
+                    // is being handled, in the middle of the `try` statement.
+                    if let Some(span) = handler.types.as_ref().and_then(find_yield) {
+                        return Err(PyError::new(
+                            ErrorKind::SyntaxError,
+                            "'yield' in the exception types of an 'except' clause is not supported",
+                        )
+                        .at(span));
+                    }
+                    handler.body = self.block(handler.body)?;
+                    lowered.push(handler);
+                }
+                let orelse = self.block(orelse)?;
+                let finalbody = self.block(finalbody)?;
+                out.push(Expr::Try { body, handlers: lowered, orelse, finalbody });
+            }
+            stmt => out.push(stmt),
+        }
+        Ok(())
+    }
+
+    // A `yield` or `yield from` as the generator runs it: its operand
+    // evaluated, then suspending.
+    fn suspension(&mut self, value: Expr, span: Span, out: &mut Vec<Expr>) -> Expr {
+        match value {
+            Expr::Yield(value, yield_span) => {
+                Expr::Yield(value.map(|value| Box::new(self.expr(*value, span, out))), yield_span)
+            }
+            Expr::YieldFrom(iterable, yield_span) => {
+                Expr::YieldFrom(Box::new(self.expr(*iterable, span, out)), yield_span)
+            }
+            value => value,
+        }
+    }
+
+    // An expression with no `yield` in it, after adding the statements that
+    // evaluate its yields (and what comes before them) to `out`.
+    fn expr(&mut self, expr: Expr, span: Span, out: &mut Vec<Expr>) -> Expr {
+        if find_yield(&expr).is_none() {
+            return expr;
+        }
+        match expr {
+            value @ (Expr::Yield(..) | Expr::YieldFrom(..)) => {
+                let value = self.suspension(value, span, out);
+                let name = self.temporary(value, span, out);
+                variable(&name, span)
+            }
+            Expr::JoinedString(parts) => Expr::JoinedString(self.in_order(parts, span, out)),
+            Expr::FormattedValue { value, conversion, spec, span: field_span } => {
+                let mut parts = self.in_order(std::iter::once(*value).chain(spec.map(|e| *e)).collect(), span, out);
+                let spec = if parts.len() == 2 { parts.pop().map(Box::new) } else { None };
+                let value = Box::new(parts.pop().expect("the value is always there"));
+                Expr::FormattedValue { value, conversion, spec, span: field_span }
+            }
+            Expr::List(elements) => Expr::List(self.in_order(elements, span, out)),
+            Expr::Tuple(elements) => Expr::Tuple(self.in_order(elements, span, out)),
+            Expr::Set(elements, set_span) => Expr::Set(self.in_order(elements, span, out), set_span),
+            Expr::Starred(value, starred_span) => Expr::Starred(Box::new(self.expr(*value, span, out)), starred_span),
+            Expr::Dict(entries, dict_span) => {
+                let flat = entries.into_iter().flat_map(|(key, value)| [key, value]).collect();
+                let mut flat = self.in_order(flat, span, out).into_iter();
+                let mut entries = Vec::new();
+                while let (Some(key), Some(value)) = (flat.next(), flat.next()) {
+                    entries.push((key, value));
+                }
+                Expr::Dict(entries, dict_span)
+            }
+            Expr::Subscript { value, index, span: item_span } => {
+                let [value, index] = self.pair(*value, *index, span, out);
+                Expr::Subscript { value: Box::new(value), index: Box::new(index), span: item_span }
+            }
+            Expr::Slice { lower, upper, step } => {
+                let present = [lower.is_some(), upper.is_some(), step.is_some()];
+                let parts = [lower, upper, step].into_iter().flatten().map(|e| *e).collect();
+                let mut parts = self.in_order(parts, span, out).into_iter();
+                let mut bound = |present: bool| if present { parts.next().map(Box::new) } else { None };
+                Expr::Slice { lower: bound(present[0]), upper: bound(present[1]), step: bound(present[2]) }
+            }
+            Expr::Attribute { value, name, span: attribute_span } => {
+                Expr::Attribute { value: Box::new(self.expr(*value, span, out)), name, span: attribute_span }
+            }
+            Expr::Arithmetic { left, operator, right, span: operator_span } => {
+                let [left, right] = self.pair(*left, *right, span, out);
+                Expr::Arithmetic { left: Box::new(left), operator, right: Box::new(right), span: operator_span }
+            }
+            Expr::UnaryOp { operator, operand, span: operator_span } => {
+                Expr::UnaryOp { operator, operand: Box::new(self.expr(*operand, span, out)), span: operator_span }
+            }
+            Expr::Not(operand, not_span) => Expr::Not(Box::new(self.expr(*operand, span, out)), not_span),
+            Expr::Compare { left, operators, comparators, span: compare_span } => {
+                if comparators.iter().all(|comparator| find_yield(comparator).is_none()) {
+                    let left = Box::new(self.expr(*left, span, out));
+                    return Expr::Compare { left, operators, comparators, span: compare_span };
+                }
+                let left = self.expr(*left, span, out);
+                let left = self.save(left, span, out);
+                let result = self.fresh();
+                let pairs = operators.into_iter().zip(comparators).collect();
+                self.chain(left, pairs, &result, compare_span, span, out);
+                variable(&result, span)
+            }
+            // `a and (yield)` becomes `result = a`, then `if result:
+            // result = (yield)`; `or` tests `not result` instead.
+            Expr::Logical { left, operator, right, span: operator_span } => {
+                let left = self.expr(*left, span, out);
+                if find_yield(&right).is_none() {
+                    return Expr::Logical { left: Box::new(left), operator, right, span: operator_span };
+                }
+                let result = self.temporary(left, span, out);
+                let mut body = Vec::new();
+                let right = self.expr(*right, span, &mut body);
+                body.push(Expr::Assignment(Box::new(variable(&result, span)), Box::new(right), span));
+                let condition = match operator.as_str() {
+                    "and" => variable(&result, span),
+                    _ => Expr::Not(Box::new(variable(&result, span)), operator_span),
+                };
+                out.push(Expr::IfCondition { condition: Box::new(condition), body, orelse: Vec::new(), span });
+                variable(&result, span)
+            }
+            Expr::Call { function, args, keywords, span: call_span } => {
+                let count = args.len();
+                let names: Vec<String> = keywords.iter().map(|(name, _)| name.clone()).collect();
+                let parts =
+                    std::iter::once(*function).chain(args).chain(keywords.into_iter().map(|(_, arg)| arg)).collect();
+                let mut parts = self.in_order(parts, span, out).into_iter();
+                let function = Box::new(parts.next().expect("the function is always there"));
+                let args = parts.by_ref().take(count).collect();
+                let keywords = names.into_iter().zip(parts).collect();
+                Expr::Call { function, args, keywords, span: call_span }
+            }
+            Expr::GeneratorExp { qualname, body, iterable, scope, span: generator_span } => {
+                let iterable = Box::new(self.expr(*iterable, span, out));
+                Expr::GeneratorExp { qualname, body, iterable, scope, span: generator_span }
+            }
+            expr => expr,
+        }
+    }
+
+    // Expressions evaluated one after another: those before the last that
+    // yields are saved before it runs.
+    fn in_order(&mut self, exprs: Vec<Expr>, span: Span, out: &mut Vec<Expr>) -> Vec<Expr> {
+        let Some(last) = exprs.iter().rposition(|expr| find_yield(expr).is_some()) else {
+            return exprs;
+        };
+        let mut lowered = Vec::with_capacity(exprs.len());
+        for (i, expr) in exprs.into_iter().enumerate() {
+            let expr = match expr {
+                _ if i > last => expr,
+                // What is saved is the value being unpacked.
+                Expr::Starred(value, starred_span) => {
+                    let value = self.expr(*value, span, out);
+                    let value = if i < last { self.save(value, span, out) } else { value };
+                    Expr::Starred(Box::new(value), starred_span)
+                }
+                expr => {
+                    let expr = self.expr(expr, span, out);
+                    if i < last {
+                        self.save(expr, span, out)
+                    } else {
+                        expr
+                    }
+                }
+            };
+            lowered.push(expr);
+        }
+        lowered
+    }
+
+    fn pair(&mut self, first: Expr, second: Expr, span: Span, out: &mut Vec<Expr>) -> [Expr; 2] {
+        let mut pair = self.in_order(vec![first, second], span, out).into_iter();
+        [pair.next().unwrap(), pair.next().unwrap()]
+    }
+
+    // The rest of a comparison chain once `left` is known: each comparison
+    // is assigned to `result`, and the next only runs when it is true.
+    fn chain(
+        &mut self,
+        left: Expr,
+        mut pairs: Vec<(String, Expr)>,
+        result: &str,
+        compare_span: Span,
+        span: Span,
+        out: &mut Vec<Expr>,
+    ) {
+        let rest = pairs.split_off(1);
+        let (operator, right) = pairs.pop().expect("a comparison has an operator");
+        let right = self.expr(right, span, out);
+        // An operand shared with the next comparison is kept in a temporary.
+        let (right, next) = if rest.is_empty() {
+            (right, None)
+        } else {
+            let name = self.temporary(right, span, out);
+            (variable(&name, span), Some(name))
+        };
+        let comparison = Expr::Compare {
+            left: Box::new(left),
+            operators: vec![operator],
+            comparators: vec![right],
+            span: compare_span,
+        };
+        out.push(Expr::Assignment(Box::new(variable(result, span)), Box::new(comparison), span));
+        let Some(next) = next else { return };
+        let mut body = Vec::new();
+        self.chain(variable(&next, span), rest, result, compare_span, span, &mut body);
+        out.push(Expr::IfCondition { condition: Box::new(variable(result, span)), body, orelse: Vec::new(), span });
+    }
+
+    // `target = value`, where the value has been lowered already.
+    fn assign(&mut self, target: Expr, value: Expr, span: Span, out: &mut Vec<Expr>) {
+        if find_yield(&target).is_none() {
+            out.push(Expr::Assignment(Box::new(target), Box::new(value), span));
+            return;
+        }
+        // The value is evaluated before the target.
+        let value = self.save(value, span, out);
+        match target {
+            // Unpacking happens before any of the targets is evaluated, so
+            // the items go to temporaries first.
+            Expr::Tuple(targets) | Expr::List(targets) => {
+                let mut names = Vec::with_capacity(targets.len());
+                let mut pattern = Vec::with_capacity(targets.len());
+                for target in &targets {
+                    let name = self.fresh();
+                    pattern.push(match target {
+                        Expr::Starred(_, starred_span) => Expr::Starred(Box::new(variable(&name, span)), *starred_span),
+                        _ => variable(&name, span),
+                    });
+                    names.push(name);
+                }
+                out.push(Expr::Assignment(Box::new(Expr::Tuple(pattern)), Box::new(value), span));
+                for (target, name) in targets.into_iter().zip(names) {
+                    let target = match target {
+                        Expr::Starred(target, _) => *target,
+                        target => target,
+                    };
+                    self.assign(target, variable(&name, span), span, out);
+                }
+            }
+            target => {
+                let target = self.expr(target, span, out);
+                out.push(Expr::Assignment(Box::new(target), Box::new(value), span));
+            }
+        }
+    }
+
+    // `target op= value` as Python runs it: the target's container (or
+    // object) and index, then its current value, then the value. The update
+    // happens in place on a temporary holding the current value, which is
+    // then stored back.
+    fn augmented_assign(&mut self, target: Expr, operator: String, value: Expr, span: Span, out: &mut Vec<Expr>) {
+        let (current, store) = match target {
+            Expr::Variable(name, variable_span) => {
+                (Expr::Variable(name.clone(), variable_span), Expr::Variable(name, variable_span))
+            }
+            Expr::Subscript { value: container, index, span: item_span } => {
+                let container = self.expr(*container, span, out);
+                let container = self.temporary(container, span, out);
+                let index = self.expr(*index, span, out);
+                let index = self.temporary(index, span, out);
+                let item = |container: &str, index: &str| Expr::Subscript {
+                    value: Box::new(variable(container, span)),
+                    index: Box::new(variable(index, span)),
+                    span: item_span,
+                };
+                (item(&container, &index), item(&container, &index))
+            }
+            Expr::Attribute { value: object, name, span: attribute_span } => {
+                let object = self.expr(*object, span, out);
+                let object = self.temporary(object, span, out);
+                let attribute = |object: &str, name: &str| Expr::Attribute {
+                    value: Box::new(variable(object, span)),
+                    name: name.to_string(),
+                    span: attribute_span,
+                };
+                (attribute(&object, &name), attribute(&object, &name))
+            }
+            // The parser only allows the targets above.
+            target => {
+                let value = Box::new(self.expr(value, span, out));
+                out.push(Expr::AugmentedAssignment { target: Box::new(target), operator, value, span });
+                return;
+            }
+        };
+        let current = self.temporary(current, span, out);
+        let value = Box::new(self.expr(value, span, out));
+        let target = Box::new(variable(&current, span));
+        out.push(Expr::AugmentedAssignment { target, operator, value, span });
+        out.push(Expr::Assignment(Box::new(store), Box::new(variable(&current, span)), span));
+    }
+
+    // A `while` loop whose condition yields: the condition is evaluated at
+    // the top of an endless loop that it breaks out of. The `else` block,
+    // which runs only when the condition ends the loop, follows it behind a
+    // flag.
+    fn while_loop(&mut self, condition: Expr, body: Vec<Expr>, orelse: Vec<Expr>, span: Span, out: &mut Vec<Expr>) {
+        if find_yield(&condition).is_none() {
+            out.push(Expr::WhileLoop { condition: Box::new(condition), body, orelse, span });
+            return;
+        }
+        let mut statements = Vec::new();
+        let condition = self.expr(condition, span, &mut statements);
+        let mut exit = vec![Expr::Break];
+        let flag = if orelse.is_empty() {
+            None
+        } else {
+            let flag = self.temporary(Expr::Bool(false), span, out);
+            exit.insert(0, Expr::Assignment(Box::new(variable(&flag, span)), Box::new(Expr::Bool(true)), span));
+            Some(flag)
+        };
+        statements.push(Expr::IfCondition {
+            condition: Box::new(Expr::Not(Box::new(condition), span)),
+            body: exit,
+            orelse: Vec::new(),
+            span,
+        });
+        statements.extend(body);
+        out.push(Expr::WhileLoop { condition: Box::new(Expr::Bool(true)), body: statements, orelse: Vec::new(), span });
+        if let Some(flag) = flag {
+            out.push(Expr::IfCondition {
+                condition: Box::new(variable(&flag, span)),
+                body: orelse,
+                orelse: Vec::new(),
+                span,
+            });
+        }
+    }
+}
@@ -1,40 +1,165 @@
 use std::time::Instant;
-use std::collections::HashMap;
-use std::io::{self, BufWriter, Write};
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path;
+use std::process;
+use std::thread;
 
 mod token;
 mod ast;
+mod bigint;
 mod lexer;
 mod parser;
+mod lower;
+mod scope;
 mod evaluator;
+mod operators;
+mod builtins;
+mod class;
+mod dict;
+mod iter;
+mod set;
+mod special;
+mod format;
+mod error;
+mod exception;
+mod generator;
+mod repl;
 use crate::lexer::lex;
 use crate::parser::Parser;
-use crate::evaluator::evaluate;
-use crate::ast::Value;
+use crate::evaluator::{execute, Environment};
+use crate::error::PyError;
+
+// Deeply recursive scripts recurse in the evaluator too, so the interpreter runs
+// on a thread with a much larger stack than the main thread's default.
+const STACK_SIZE: usize = 1 << 30;
+
+const USAGE: &str = "usage: pyrust [--time] [-c cmd | file | -] [arg] ...";
+
+// Where the program text comes from.
+enum Source {
+    File(String),
+    Command(String),
+    Stdin,
+    // No script given: a REPL when stdin is a terminal, otherwise read stdin.
+    Interactive,
+}
+
+struct Options {
+    source: Source,
+    // What the script sees as sys.argv: the script name followed by its arguments.
+    argv: Vec<String>,
+    time: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut time = false;
+    let (source, argv0) = loop {
+        match args.next().as_deref() {
+            Some("--time") => time = true,
+            Some("-c") => match args.next() {
+                Some(code) => break (Source::Command(code), "-c".to_string()),
+                None => return Err("Argument expected for the -c option".to_string()),
+            },
+            Some("-") => break (Source::Stdin, "-".to_string()),
+            Some(flag) if flag.starts_with('-') => return Err(format!("Unknown option: {}", flag)),
+            Some(path) => break (Source::File(path.to_string()), path.to_string()),
+            None => break (Source::Interactive, String::new()),
+        }
+    };
+    let mut argv = vec![argv0];
+    argv.extend(args);
+    Ok(Options { source, argv, time })
+}
+
+fn read_source(source: &Source) -> Result<String, String> {
+    match source {
+        Source::File(path) => fs::read_to_string(path)
+            .map_err(|e| format!("can't open file '{}': {}", path, e)),
+        Source::Command(code) => Ok(code.clone()),
+        Source::Stdin | Source::Interactive => {
+            let mut code = String::new();
+            io::stdin()
+                .read_to_string(&mut code)
+                .map_err(|e| format!("can't read from stdin: {}", e))?;
+            Ok(code)
+        }
+    }
+}
+
+// The name shown for the program in error messages; like CPython, a script
+// is named by its absolute path.
+fn display_name(options: &Options) -> String {
+    match &options.source {
+        Source::File(path) => path::absolute(path).map_or(path.clone(), |path| path.display().to_string()),
+        Source::Command(_) => "<string>".to_string(),
+        Source::Stdin | Source::Interactive => "<stdin>".to_string(),
+    }
+}
+
+// Runs a program; if it fails, the error report to print: a syntax error, or
+// the traceback of an exception nothing caught.
+fn run<W: Write>(code: &str, filename: &str, argv: Vec<String>, writer: &mut W) -> Result<(), String> {
+    let syntax_error = |error: PyError| error.render(filename, code);
+    let tokens = lex(code).map_err(syntax_error)?;
+    let mut parser = Parser::new(tokens);
+    let ast = parser.parse().map_err(syntax_error)?;
+    let mut env = Environment::with_argv(argv);
+
+    for stmt in &ast {
+        if let Err(error) = execute(stmt, &mut env, writer) {
+            return Err(exception::report(error, &mut env, writer, filename, code));
+        }
+    }
+    Ok(())
+}
 
 fn main() {
-    const CODE: &str = "
-x = 1000000
-for i in range(0, x):
-    print(i)
-";
+    let interpreter = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_main)
+        .expect("failed to start interpreter thread");
+    let status = interpreter.join().unwrap_or(101);
+    process::exit(status);
+}
+
+fn run_main() -> i32 {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            return 2;
+        }
+    };
+    if matches!(options.source, Source::Interactive) && io::stdin().is_terminal() {
+        repl::run_repl();
+        return 0;
+    }
+    let code = match read_source(&options.source) {
+        Ok(code) => code,
+        Err(message) => {
+            eprintln!("pyrust: {}", message);
+            return 2;
+        }
+    };
+
     // Start timing before lexing/parsing/evaluation.
     let start = Instant::now();
 
-    let tokens = lex(CODE);
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse();
-    let mut variables: HashMap<String, Value> = HashMap::new();
-
     let stdout = io::stdout();
     let mut writer = BufWriter::new(stdout.lock());
-
-    for expr in &ast {
-        evaluate(expr, &mut variables, &mut writer);
-    }
+    let result = run(&code, &display_name(&options), options.argv.clone(), &mut writer);
     writer.flush().unwrap();
 
     // Stop timing after execution.
-    let duration = start.elapsed();
-    println!("Time elapsed: {:?}", duration);
+    if options.time {
+        eprintln!("Time elapsed: {:?}", start.elapsed());
+    }
+
+    if let Err(report) = result {
+        eprintln!("{}", report);
+        return 1;
+    }
+    0
 }
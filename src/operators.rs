@@ -0,0 +1,1143 @@
+use crate::ast::{Range, Slice, Value, ViewKind};
+use crate::bigint::BigInt;
+use crate::error::{ErrorKind, PyError};
+use crate::exception::new_exception;
+use crate::iter::iterate;
+use crate::set::Set;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+
+fn unsupported(operator: &str, left: &Value, right: &Value) -> PyError {
+    PyError::new(
+        ErrorKind::TypeError,
+        format!(
+            "unsupported operand type(s) for {}: '{}' and '{}'",
+            operator,
+            left.type_name(),
+            right.type_name()
+        ),
+    )
+}
+
+fn overflow() -> PyError {
+    PyError::new(ErrorKind::OverflowError, "exponent too large")
+}
+
+fn zero_division(message: &str) -> PyError {
+    PyError::new(ErrorKind::ZeroDivisionError, message)
+}
+
+// What CPython raises for a result too large to allocate.
+fn memory_error() -> PyError {
+    PyError::new(ErrorKind::MemoryError, "")
+}
+
+// Applies an arithmetic operator. Two ints (or bools) stay ints, except for `/`
+// and negative powers; any float operand promotes the operation to float.
+// Ints run on i64 and fall back to arbitrary precision when that overflows.
+pub fn binary_op(operator: &str, left: &Value, right: &Value) -> Result<Value, PyError> {
+    match arithmetic(operator, left, right)? {
+        Some(result) => Ok(result),
+        None => Err(unsupported(operator, left, right)),
+    }
+}
+
+// `left op= right`, with the operator including its '='. Lists and sets are
+// updated in place and returned; other types fall back to the plain operator.
+pub fn inplace_op(operator: &str, left: &Value, right: &Value) -> Result<Value, PyError> {
+    let base = operator.strip_suffix('=').unwrap_or(operator);
+    match (base, left, right) {
+        ("*", Value::List(items), _) => {
+            if let Some(Value::List(repeated)) = sequence_op(base, left, right)? {
+                *items.borrow_mut() = repeated.take();
+                return Ok(left.clone());
+            }
+        }
+        ("|" | "&" | "-" | "^", Value::Set(set), Value::Set(_) | Value::FrozenSet(_)) => {
+            let other = as_set(right).unwrap();
+            let mut set = set.borrow_mut();
+            match base {
+                "|" => set.merge(&other),
+                "&" => *set = set.intersection(&other),
+                "-" => set.difference_update(&other),
+                _ => set.symmetric_difference_update(&other),
+            }
+            return Ok(left.clone());
+        }
+        _ => {}
+    }
+    match arithmetic(base, left, right)? {
+        Some(result) => Ok(result),
+        None => Err(unsupported(operator, left, right)),
+    }
+}
+
+// The operators shared by binary_op and inplace_op; Ok(None) means the
+// operand types don't support the operator.
+fn arithmetic(operator: &str, left: &Value, right: &Value) -> Result<Option<Value>, PyError> {
+    // `&`, `|` and `^` on two bools give a bool, as in Python.
+    if let (Value::Bool(l), Value::Bool(r)) = (left, right) {
+        match operator {
+            "&" => return Ok(Some(Value::Bool(l & r))),
+            "|" => return Ok(Some(Value::Bool(l | r))),
+            "^" => return Ok(Some(Value::Bool(l ^ r))),
+            _ => {}
+        }
+    }
+    if let (Some(l), Some(r)) = (left.as_int(), right.as_int()) {
+        if let Some(result) = int_op(operator, l, r)? {
+            return Ok(Some(result));
+        }
+    }
+    if let (Some(l), Some(r)) = (left.as_bigint(), right.as_bigint()) {
+        return bigint_op(operator, &l, &r).map(Some);
+    }
+    if let (Some(l), Some(r)) = (left.as_float(), right.as_float()) {
+        // The bitwise and shift operators are defined on ints only.
+        if matches!(operator, "&" | "|" | "^" | "<<" | ">>") {
+            return Ok(None);
+        }
+        if l.is_infinite() && left.is_int() || r.is_infinite() && right.is_int() {
+            return Err(PyError::new(ErrorKind::OverflowError, "int too large to convert to float"));
+        }
+        return float_op(operator, l, r).map(Some);
+    }
+    if let Some(result) = sequence_op(operator, left, right)? {
+        return Ok(Some(result));
+    }
+    Ok(set_op(operator, left, right))
+}
+
+// `+` and `*` on strings, bytes, lists and tuples: concatenation and
+// repetition, both producing new objects. Ok(None) means this isn't a
+// sequence operation.
+fn sequence_op(operator: &str, left: &Value, right: &Value) -> Result<Option<Value>, PyError> {
+    match (operator, left, right) {
+        ("+", Value::Str(l), Value::Str(r)) => Ok(Some(Value::Str(format!("{}{}", l, r)))),
+        ("+", Value::Bytes(l), Value::Bytes(r)) => Ok(Some(Value::Bytes(Rc::new([&l[..], &r[..]].concat())))),
+        ("+", Value::Bytes(_), _) => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("can't concat {} to bytes", right.type_name()),
+        )),
+        ("+", Value::List(l), Value::List(r)) => {
+            let mut items = l.borrow().clone();
+            items.extend(r.borrow().iter().cloned());
+            Ok(Some(Value::new_list(items)))
+        }
+        ("+", Value::Tuple(l), Value::Tuple(r)) => Ok(Some(Value::new_tuple([&l[..], &r[..]].concat()))),
+        ("+", Value::Str(_) | Value::List(_) | Value::Tuple(_), _) => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!(
+                "can only concatenate {} (not \"{}\") to {}",
+                left.type_name(),
+                right.type_name(),
+                left.type_name()
+            ),
+        )),
+        ("*", sequence @ (Value::Str(_) | Value::Bytes(_) | Value::List(_) | Value::Tuple(_)), count)
+        | ("*", count, sequence @ (Value::Str(_) | Value::Bytes(_) | Value::List(_) | Value::Tuple(_))) => {
+            let count = match count.as_int() {
+                Some(count) => count.max(0) as usize,
+                None if count.is_int() => {
+                    return Err(PyError::new(
+                        ErrorKind::OverflowError,
+                        "cannot fit 'int' into an index-sized integer",
+                    ))
+                }
+                None => {
+                    return Err(PyError::new(
+                        ErrorKind::TypeError,
+                        format!("can't multiply sequence by non-int of type '{}'", count.type_name()),
+                    ))
+                }
+            };
+            // Strings and bytes longer than isize::MAX are refused outright;
+            // anything shorter may still be too large to allocate.
+            let too_long = |len: usize| len.checked_mul(count).is_none_or(|len| len > isize::MAX as usize);
+            match sequence {
+                Value::Str(s) => {
+                    if too_long(s.chars().count()) {
+                        return Err(PyError::new(ErrorKind::OverflowError, "repeated string is too long"));
+                    }
+                    let repeated = repeat(s.as_bytes(), count)?;
+                    return Ok(Some(Value::Str(String::from_utf8(repeated).expect("copies of a str are UTF-8"))));
+                }
+                Value::Bytes(bytes) => {
+                    if too_long(bytes.len()) {
+                        return Err(PyError::new(ErrorKind::OverflowError, "repeated bytes are too long"));
+                    }
+                    return Ok(Some(Value::Bytes(Rc::new(repeat(bytes, count)?))));
+                }
+                _ => {}
+            }
+            let repeated = repeat(&sequence_items(sequence)?, count)?;
+            Ok(Some(match sequence {
+                Value::List(_) => Value::new_list(repeated),
+                _ => Value::new_tuple(repeated),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+// `count` copies of some items, back to back; like CPython, a result too
+// large to allocate is a MemoryError.
+fn repeat<T: Clone>(items: &[T], count: usize) -> Result<Vec<T>, PyError> {
+    let mut repeated = Vec::new();
+    let len = items.len().checked_mul(count).ok_or_else(memory_error)?;
+    repeated.try_reserve_exact(len).map_err(|_| memory_error())?;
+    if !items.is_empty() {
+        for _ in 0..count {
+            repeated.extend_from_slice(items);
+        }
+    }
+    Ok(repeated)
+}
+
+// The contents of a set or frozenset, or None for any other value.
+fn as_set(value: &Value) -> Option<Set> {
+    match value {
+        Value::Set(set) => Some(set.borrow().clone()),
+        Value::FrozenSet(set) => Some((**set).clone()),
+        _ => None,
+    }
+}
+
+// `| & - ^` on sets and frozensets; the result has the type of the left
+// operand, as in Python. None means this isn't a set operation.
+fn set_op(operator: &str, left: &Value, right: &Value) -> Option<Value> {
+    let (l, r) = (as_set(left)?, as_set(right)?);
+    let result = match operator {
+        "|" => l.union(&r),
+        "&" => l.intersection(&r),
+        "-" => l.difference(&r),
+        "^" => l.symmetric_difference(&r),
+        _ => return None,
+    };
+    Some(match left {
+        Value::FrozenSet(_) => Value::FrozenSet(Rc::new(result)),
+        _ => Value::new_set(result),
+    })
+}
+
+fn unknown_operator(operator: &str) -> PyError {
+    PyError::new(ErrorKind::SyntaxError, format!("unknown operator '{}'", operator))
+}
+
+// The i64 fast path; Ok(None) means the result overflowed.
+fn int_op(operator: &str, l: i64, r: i64) -> Result<Option<Value>, PyError> {
+    let result = match operator {
+        "+" => l.checked_add(r),
+        "-" => l.checked_sub(r),
+        "*" => l.checked_mul(r),
+        "/" => {
+            if r == 0 {
+                return Err(zero_division("division by zero"));
+            }
+            // Beyond 2^53 the operands themselves would round as floats.
+            const EXACT: u64 = 1 << f64::MANTISSA_DIGITS;
+            if l.unsigned_abs() > EXACT || r.unsigned_abs() > EXACT {
+                return true_divide(&BigInt::from_i64(l), &BigInt::from_i64(r)).map(Some);
+            }
+            return Ok(Some(Value::Float(l as f64 / r as f64)));
+        }
+        "//" => {
+            if r == 0 {
+                return Err(zero_division("integer division or modulo by zero"));
+            }
+            floor_div(l, r)
+        }
+        "%" => {
+            if r == 0 {
+                return Err(zero_division("integer modulo by zero"));
+            }
+            floor_mod(l, r)
+        }
+        "**" => {
+            if r < 0 {
+                if l == 0 {
+                    return Err(zero_division("0.0 cannot be raised to a negative power"));
+                }
+                return Ok(Some(Value::Float((l as f64).powf(r as f64))));
+            }
+            u32::try_from(r).ok().and_then(|r| l.checked_pow(r))
+        }
+        "&" => Some(l & r),
+        "|" => Some(l | r),
+        "^" => Some(l ^ r),
+        "<<" => {
+            if r < 0 {
+                return Err(negative_shift());
+            }
+            // Only shifts that lose no bits stay on the fast path.
+            (r < 64).then(|| l << r).filter(|shifted| shifted >> r == l)
+        }
+        ">>" => {
+            if r < 0 {
+                return Err(negative_shift());
+            }
+            Some(l >> r.min(63))
+        }
+        _ => return Err(unknown_operator(operator)),
+    };
+    Ok(result.map(Value::Int))
+}
+
+fn negative_shift() -> PyError {
+    PyError::new(ErrorKind::ValueError, "negative shift count")
+}
+
+fn true_divide(l: &BigInt, r: &BigInt) -> Result<Value, PyError> {
+    if r.is_zero() {
+        return Err(zero_division("division by zero"));
+    }
+    match l.true_div(r) {
+        Some(quotient) => Ok(Value::Float(quotient)),
+        None => Err(PyError::new(ErrorKind::OverflowError, "integer division result too large for a float")),
+    }
+}
+
+fn bigint_op(operator: &str, l: &BigInt, r: &BigInt) -> Result<Value, PyError> {
+    let result = match operator {
+        "+" => l.add(r),
+        "-" => l.sub(r),
+        "*" => l.mul(r),
+        "/" => return true_divide(l, r),
+        "//" => match l.div_mod_floor(r) {
+            Some((quotient, _)) => quotient,
+            None => return Err(zero_division("integer division or modulo by zero")),
+        },
+        "%" => match l.div_mod_floor(r) {
+            Some((_, remainder)) => remainder,
+            None => return Err(zero_division("integer modulo by zero")),
+        },
+        "**" => {
+            if r.is_negative() {
+                if l.is_zero() {
+                    return Err(zero_division("0.0 cannot be raised to a negative power"));
+                }
+                return Ok(Value::Float(l.to_f64().powf(r.to_f64())));
+            }
+            // Only 0, 1 and -1 can be raised to an exponent beyond u64 without
+            // exhausting memory; anything else is rejected up front.
+            match r.to_i64() {
+                Some(exponent) => l.pow(exponent as u64),
+                None if l.to_i64().is_some_and(|base| base.abs() <= 1) => {
+                    let odd = r.div_mod_floor(&BigInt::from_i64(2)).is_some_and(|(_, rem)| !rem.is_zero());
+                    match l.to_i64() {
+                        Some(-1) if !odd => BigInt::from_i64(1),
+                        _ => l.clone(),
+                    }
+                }
+                None => return Err(overflow()),
+            }
+        }
+        "&" => l.bitwise(r, |a, b| a & b),
+        "|" => l.bitwise(r, |a, b| a | b),
+        "^" => l.bitwise(r, |a, b| a ^ b),
+        "<<" | ">>" => {
+            if r.is_negative() {
+                return Err(negative_shift());
+            }
+            match (operator, r.to_i64()) {
+                ("<<", _) if l.is_zero() => l.clone(),
+                ("<<", Some(bits)) => l.shl(bits as u64),
+                ("<<", None) => return Err(PyError::new(ErrorKind::OverflowError, "too many digits in integer")),
+                (_, Some(bits)) => l.shr(bits as u64),
+                // Shifting right by more bits than any int has leaves only the sign.
+                (_, None) => BigInt::from_i64(if l.is_negative() { -1 } else { 0 }),
+            }
+        }
+        _ => return Err(unknown_operator(operator)),
+    };
+    Ok(Value::from_bigint(result))
+}
+
+// `-x`, `+x` and `~x`. Bools act as the ints 0 and 1; `~` is for ints only.
+pub fn unary_op(operator: &str, value: &Value) -> Result<Value, PyError> {
+    match (operator, value) {
+        ("-", Value::Float(n)) => return Ok(Value::Float(-n)),
+        ("+", Value::Float(n)) => return Ok(Value::Float(*n)),
+        _ => {}
+    }
+    if let Some(n) = value.as_int() {
+        match operator {
+            "-" if n != i64::MIN => return Ok(Value::Int(-n)),
+            "+" => return Ok(Value::Int(n)),
+            "~" => return Ok(Value::Int(!n)),
+            _ => {}
+        }
+    }
+    if let Some(n) = value.as_bigint() {
+        return match operator {
+            "-" => Ok(Value::from_bigint(n.neg())),
+            "+" => Ok(Value::from_bigint(n)),
+            "~" => Ok(Value::from_bigint(n.not())),
+            _ => Err(unknown_operator(operator)),
+        };
+    }
+    Err(PyError::new(
+        ErrorKind::TypeError,
+        format!("bad operand type for unary {}: '{}'", operator, value.type_name()),
+    ))
+}
+
+// Integer division rounding toward negative infinity, as Python's `//` does.
+fn floor_div(l: i64, r: i64) -> Option<i64> {
+    let quotient = l.checked_div(r)?;
+    if l % r != 0 && ((l < 0) != (r < 0)) {
+        Some(quotient - 1)
+    } else {
+        Some(quotient)
+    }
+}
+
+// The remainder matching floor_div: it takes the sign of the divisor.
+fn floor_mod(l: i64, r: i64) -> Option<i64> {
+    let remainder = l.checked_rem(r)?;
+    if remainder != 0 && ((remainder < 0) != (r < 0)) {
+        Some(remainder + r)
+    } else {
+        Some(remainder)
+    }
+}
+
+// CPython's float divmod. The quotient comes from subtracting the remainder
+// (exact, with fmod) rather than from flooring l / r, whose rounding can be
+// off by one; the remainder takes the divisor's sign, even when it is zero.
+fn float_div_mod(l: f64, r: f64) -> (f64, f64) {
+    let mut remainder = l % r;
+    let mut quotient = (l - remainder) / r;
+    if remainder == 0.0 {
+        remainder = 0.0f64.copysign(r);
+    } else if (r < 0.0) != (remainder < 0.0) {
+        remainder += r;
+        quotient -= 1.0;
+    }
+    // Snap the quotient to the nearest integer; a zero one takes the sign of l / r.
+    let floor = if quotient == 0.0 {
+        0.0f64.copysign(l / r)
+    } else if quotient - quotient.floor() > 0.5 {
+        quotient.floor() + 1.0
+    } else {
+        quotient.floor()
+    };
+    (floor, remainder)
+}
+
+fn float_op(operator: &str, l: f64, r: f64) -> Result<Value, PyError> {
+    let result = match operator {
+        "+" => l + r,
+        "-" => l - r,
+        "*" => l * r,
+        "/" => {
+            if r == 0.0 {
+                return Err(zero_division("float division by zero"));
+            }
+            l / r
+        }
+        "//" => {
+            if r == 0.0 {
+                return Err(zero_division("float floor division by zero"));
+            }
+            float_div_mod(l, r).0
+        }
+        "%" => {
+            if r == 0.0 {
+                return Err(zero_division("float modulo"));
+            }
+            float_div_mod(l, r).1
+        }
+        "**" => {
+            if l == 0.0 && r < 0.0 {
+                return Err(zero_division("0.0 cannot be raised to a negative power"));
+            }
+            l.powf(r)
+        }
+        _ => return Err(unknown_operator(operator)),
+    };
+    Ok(Value::Float(result))
+}
+
+pub fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Str(l), Value::Str(r)) => l == r,
+        (Value::Bytes(l), Value::Bytes(r)) => l == r,
+        (Value::List(l), Value::List(r)) => {
+            Rc::ptr_eq(l, r) || {
+                let (l, r) = (l.borrow(), r.borrow());
+                l.len() == r.len() && l.iter().zip(r.iter()).all(|(a, b)| values_equal(a, b))
+            }
+        }
+        (Value::Tuple(l), Value::Tuple(r)) => {
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|(a, b)| values_equal(a, b))
+        }
+        // Dicts are equal when they hold the same keys mapped to equal values.
+        (Value::Dict(l), Value::Dict(r)) => {
+            Rc::ptr_eq(l, r) || {
+                let (l, r) = (l.borrow(), r.borrow());
+                l.len() == r.len()
+                    && l.iter().all(|(key, value)| {
+                        matches!(r.get(key), Ok(Some(other)) if values_equal(value, &other))
+                    })
+            }
+        }
+        (Value::Set(_) | Value::FrozenSet(_), Value::Set(_) | Value::FrozenSet(_)) => {
+            let (l, r) = (as_set(left).unwrap(), as_set(right).unwrap());
+            l.len() == r.len() && l.is_subset(&r)
+        }
+        // Ranges are equal when they produce the same items.
+        (Value::Range(l), Value::Range(r)) => {
+            l.len() == r.len() && (l.len() == 0 || l.start == r.start && (l.len() == 1 || l.step == r.step))
+        }
+        (Value::Slice(l), Value::Slice(r)) => {
+            values_equal(&l.lower, &r.lower) && values_equal(&l.upper, &r.upper) && values_equal(&l.step, &r.step)
+        }
+        (Value::Function(l), Value::Function(r)) => Rc::ptr_eq(l, r),
+        (Value::Method(l), Value::Method(r)) => {
+            Rc::ptr_eq(&l.function, &r.function) && values_identical(&l.receiver, &r.receiver)
+        }
+        (Value::Builtin(_), Value::Builtin(_))
+        | (Value::Module(_), Value::Module(_))
+        | (Value::Class(_), Value::Class(_))
+        | (Value::Instance(_), Value::Instance(_))
+        | (Value::Super(_), Value::Super(_))
+        | (Value::Traceback(_), Value::Traceback(_))
+        | (Value::Generator(_), Value::Generator(_))
+        | (Value::DictView(..), Value::DictView(..))
+        | (Value::Iterator(_), Value::Iterator(_)) => values_identical(left, right),
+        (Value::None, Value::None) | (Value::NotImplemented, Value::NotImplemented) => true,
+        // bool is a numeric type: True == 1 and False == 0.
+        _ => compare_numbers(left, right) == Some(Ordering::Equal),
+    }
+}
+
+fn is_number(value: &Value) -> bool {
+    value.is_int() || matches!(value, Value::Float(_))
+}
+
+// How two numbers order. Ints and floats compare exactly, without rounding
+// the int to a float. None when either is NaN or not a number.
+fn compare_numbers(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Float(l), Value::Float(r)) => l.partial_cmp(r),
+        (Value::Float(l), _) => compare_int_float(right, *l).map(Ordering::reverse),
+        (_, Value::Float(r)) => compare_int_float(left, *r),
+        _ => match (left.as_int(), right.as_int()) {
+            (Some(l), Some(r)) => Some(l.cmp(&r)),
+            _ => Some(left.as_bigint()?.cmp(&right.as_bigint()?)),
+        },
+    }
+}
+
+// Compares an int with a float: with the float's integer part as an int,
+// and then its fractional part when those are equal.
+fn compare_int_float(int: &Value, float: f64) -> Option<Ordering> {
+    if !int.is_int() || float.is_nan() {
+        return None;
+    }
+    // Ints up to 2^53 are exact as floats.
+    if let Some(n) = int.as_int().filter(|n| n.unsigned_abs() <= 1 << f64::MANTISSA_DIGITS) {
+        return (n as f64).partial_cmp(&float);
+    }
+    if float.is_infinite() {
+        return Some(if float > 0.0 { Ordering::Less } else { Ordering::Greater });
+    }
+    let floor = float.floor();
+    let fractional = if float > floor { Ordering::Less } else { Ordering::Equal };
+    Some(int.as_bigint()?.cmp(&BigInt::from_f64(floor)).then(fractional))
+}
+
+pub fn compare_values(operator: &str, left: &Value, right: &Value) -> Result<bool, PyError> {
+    match operator {
+        "==" => return Ok(values_equal(left, right)),
+        "!=" => return Ok(!values_equal(left, right)),
+        "is" => return Ok(values_identical(left, right)),
+        "is not" => return Ok(!values_identical(left, right)),
+        "in" | "not in" => {
+            let contained = match (left, right) {
+                (Value::Str(needle), Value::Str(haystack)) => haystack.contains(needle.as_str()),
+                (Value::Bytes(needle), Value::Bytes(haystack)) => {
+                    needle.is_empty() || haystack.windows(needle.len()).any(|window| window == &needle[..])
+                }
+                (_, Value::Bytes(haystack)) => match left.as_int() {
+                    Some(byte @ 0..=255) => haystack.contains(&(byte as u8)),
+                    Some(_) => return Err(PyError::new(ErrorKind::ValueError, "byte must be in range(0, 256)")),
+                    None => {
+                        return Err(PyError::new(
+                            ErrorKind::TypeError,
+                            format!("a bytes-like object is required, not '{}'", left.type_name()),
+                        ))
+                    }
+                },
+                (_, Value::List(items)) => items.borrow().iter().any(|item| values_equal(item, left)),
+                (_, Value::Tuple(items)) => items.iter().any(|item| values_equal(item, left)),
+                (_, Value::Dict(dict)) | (_, Value::DictView(dict, ViewKind::Keys)) => dict.borrow().contains_key(left)?,
+                (_, Value::Set(set)) => set.borrow().contains(left)?,
+                (_, Value::FrozenSet(set)) => set.contains(left)?,
+                (_, Value::Range(range)) => range_contains(range, left)?,
+                (_, Value::DictView(_, _) | Value::Iterator(_)) => {
+                    // Searching an iterator consumes it up to the match.
+                    let iterator = iterate(right)?;
+                    let mut found = false;
+                    while let Some(item) = iterator.borrow_mut().next()? {
+                        if values_equal(&item, left) {
+                            found = true;
+                            break;
+                        }
+                    }
+                    found
+                }
+                (_, Value::Str(_)) => {
+                    return Err(PyError::new(
+                        ErrorKind::TypeError,
+                        format!("'in <string>' requires string as left operand, not {}", left.type_name()),
+                    ))
+                }
+                _ => {
+                    return Err(PyError::new(
+                        ErrorKind::TypeError,
+                        format!("argument of type '{}' is not iterable", right.type_name()),
+                    ))
+                }
+            };
+            return Ok(contained == (operator == "in"));
+        }
+        _ => {}
+    }
+    // Ordering comparisons: numbers numerically, strings lexicographically.
+    let ordering = match (left, right) {
+        (Value::List(l), Value::List(r)) => return compare_sequences(operator, &l.borrow(), &r.borrow()),
+        (Value::Tuple(l), Value::Tuple(r)) => return compare_sequences(operator, l, r),
+        // Sets order by inclusion: `<=` is subset and `<` proper subset.
+        (Value::Set(_) | Value::FrozenSet(_), Value::Set(_) | Value::FrozenSet(_)) => {
+            let (l, r) = (as_set(left).unwrap(), as_set(right).unwrap());
+            return Ok(match operator {
+                "<" => l.len() < r.len() && l.is_subset(&r),
+                "<=" => l.is_subset(&r),
+                ">" => r.len() < l.len() && r.is_subset(&l),
+                _ => r.is_subset(&l),
+            });
+        }
+        (Value::Str(l), Value::Str(r)) => Some(l.cmp(r)),
+        (Value::Bytes(l), Value::Bytes(r)) => Some(l.cmp(r)),
+        _ if is_number(left) && is_number(right) => compare_numbers(left, right),
+        _ => {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!(
+                    "'{}' not supported between instances of '{}' and '{}'",
+                    operator,
+                    left.type_name(),
+                    right.type_name()
+                ),
+            ))
+        }
+    };
+    // NaN is unordered, so every ordering comparison involving it is false.
+    let Some(ordering) = ordering else { return Ok(false) };
+    Ok(match operator {
+        "<" => ordering.is_lt(),
+        "<=" => ordering.is_le(),
+        ">" => ordering.is_gt(),
+        ">=" => ordering.is_ge(),
+        _ => return Err(PyError::new(ErrorKind::SyntaxError, format!("unknown comparison '{}'", operator))),
+    })
+}
+
+// Sequences order lexicographically: the first unequal pair of items decides,
+// and otherwise the shorter sequence is smaller.
+fn compare_sequences(operator: &str, left: &[Value], right: &[Value]) -> Result<bool, PyError> {
+    for (l, r) in left.iter().zip(right) {
+        if !values_equal(l, r) {
+            return compare_values(operator, l, r);
+        }
+    }
+    compare_values(operator, &Value::Int(left.len() as i64), &Value::Int(right.len() as i64))
+}
+
+// Identity for `is`: numbers and strings are immutable, so equal values of the
+// same type are treated as the same object (as CPython does for small ints and
+// interned strings); True, False and None are singletons. Mutable and
+// interpreter objects are identical only to themselves.
+pub fn values_identical(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::List(l), Value::List(r)) => Rc::ptr_eq(l, r),
+        (Value::Dict(l), Value::Dict(r)) => Rc::ptr_eq(l, r),
+        (Value::Set(l), Value::Set(r)) => Rc::ptr_eq(l, r),
+        (Value::FrozenSet(l), Value::FrozenSet(r)) => Rc::ptr_eq(l, r),
+        (Value::DictView(l, l_kind), Value::DictView(r, r_kind)) => Rc::ptr_eq(l, r) && l_kind == r_kind,
+        (Value::Slice(l), Value::Slice(r)) => Rc::ptr_eq(l, r),
+        (Value::Range(l), Value::Range(r)) => Rc::ptr_eq(l, r),
+        (Value::Iterator(l), Value::Iterator(r)) => Rc::ptr_eq(l, r),
+        (Value::Module(l), Value::Module(r)) => Rc::ptr_eq(l, r),
+        (Value::Class(l), Value::Class(r)) => Rc::ptr_eq(l, r),
+        (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
+        (Value::Method(l), Value::Method(r)) => Rc::ptr_eq(l, r),
+        (Value::Super(l), Value::Super(r)) => Rc::ptr_eq(l, r),
+        (Value::Traceback(l), Value::Traceback(r)) => Rc::ptr_eq(l, r),
+        (Value::Generator(l), Value::Generator(r)) => Rc::ptr_eq(l, r),
+        (Value::Builtin(l), Value::Builtin(r)) => {
+            l.name == r.name
+                && match (&l.receiver, &r.receiver) {
+                    (Some(l), Some(r)) => values_identical(l, r),
+                    (l, r) => l.is_none() && r.is_none(),
+                }
+        }
+        _ => mem::discriminant(left) == mem::discriminant(right) && values_equal(left, right),
+    }
+}
+
+// CPython reduces numeric hashes modulo this Mersenne prime, so that equal
+// ints, floats and bools hash alike.
+const HASH_BITS: u32 = 61;
+const HASH_MODULUS: u64 = (1 << HASH_BITS) - 1;
+const HASH_INF: i64 = 314159;
+
+// Hashes a value for dicts and sets. Numbers, tuples and frozensets hash
+// exactly as in CPython, which also gives sets CPython's iteration order;
+// values that compare equal always hash equally, and -1 is never a hash.
+pub fn hash_value(value: &Value) -> Result<i64, PyError> {
+    let hash = match value {
+        Value::Int(_) | Value::Bool(_) => {
+            let n = value.as_int().unwrap();
+            let hash = (n.unsigned_abs() % HASH_MODULUS) as i64;
+            if n < 0 { -hash } else { hash }
+        }
+        Value::BigInt(n) => {
+            let hash = n.magnitude_rem(HASH_MODULUS) as i64;
+            if n.is_negative() { -hash } else { hash }
+        }
+        Value::Float(n) => hash_float(*n),
+        Value::Str(s) => {
+            let mut hasher = DefaultHasher::new();
+            s.hash(&mut hasher);
+            hasher.finish() as i64
+        }
+        Value::Bytes(bytes) => {
+            let mut hasher = DefaultHasher::new();
+            bytes.hash(&mut hasher);
+            hasher.finish() as i64
+        }
+        Value::None => 0xFCA86420,
+        Value::NotImplemented => 0xFCA86421,
+        Value::Tuple(items) => hash_tuple(items, hash_value)?,
+        Value::FrozenSet(set) => set.hash(),
+        // As CPython does, a range hashes like the tuple (len, start, step),
+        // with the parts that don't affect its items replaced by None.
+        Value::Range(range) => {
+            let len = range.len();
+            let start = if len == 0 { Value::None } else { Value::Int(range.start) };
+            let step = if len <= 1 { Value::None } else { Value::Int(range.step) };
+            hash_tuple(&[Value::Int(len as i64), start, step], hash_value)?
+        }
+        Value::Iterator(iter) => (Rc::as_ptr(iter) as usize).rotate_right(4) as i64,
+        // Functions, modules, classes and instances are hashed by identity.
+        Value::Function(function) => (Rc::as_ptr(function) as usize).rotate_right(4) as i64,
+        Value::Module(module) => (Rc::as_ptr(module) as usize).rotate_right(4) as i64,
+        Value::Class(class) => (Rc::as_ptr(class) as usize).rotate_right(4) as i64,
+        // A class that defines `__eq__` but not `__hash__` has `__hash__` set to None.
+        Value::Instance(instance) if matches!(instance.class.lookup("__hash__"), Some(Value::None)) => {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("unhashable type: '{}'", value.type_name()),
+            ))
+        }
+        Value::Instance(instance) => (Rc::as_ptr(instance) as usize).rotate_right(4) as i64,
+        Value::Super(proxy) => (Rc::as_ptr(proxy) as usize).rotate_right(4) as i64,
+        Value::Traceback(frames) => (Rc::as_ptr(frames) as usize).rotate_right(4) as i64,
+        Value::Generator(generator) => (Rc::as_ptr(generator) as usize).rotate_right(4) as i64,
+        // Bound methods are equal, so hash alike, when function and receiver are the same.
+        Value::Method(method) => {
+            hash_value(&method.receiver)? ^ (Rc::as_ptr(&method.function) as usize).rotate_right(4) as i64
+        }
+        Value::Builtin(builtin) => {
+            let mut hasher = DefaultHasher::new();
+            builtin.name.hash(&mut hasher);
+            hasher.finish() as i64
+        }
+        Value::List(_) | Value::Dict(_) | Value::DictView(..) | Value::Set(_) | Value::Slice(_) => {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("unhashable type: '{}'", value.type_name()),
+            ))
+        }
+    };
+    Ok(if hash == -1 { -2 } else { hash })
+}
+
+// CPython's float hash: the value as a fraction reduced modulo HASH_MODULUS,
+// which makes integral floats hash like the equal int.
+fn hash_float(value: f64) -> i64 {
+    if value.is_infinite() {
+        return if value > 0.0 { HASH_INF } else { -HASH_INF };
+    }
+    if value.is_nan() {
+        return 0;
+    }
+    let (mut mantissa, mut exponent) = frexp(value);
+    let sign = if mantissa < 0.0 {
+        mantissa = -mantissa;
+        -1
+    } else {
+        1
+    };
+    let mut x: u64 = 0;
+    while mantissa != 0.0 {
+        x = ((x << 28) & HASH_MODULUS) | x >> (HASH_BITS - 28);
+        mantissa *= 268435456.0; // 2**28
+        exponent -= 28;
+        let digits = mantissa as u64;
+        mantissa -= digits as f64;
+        x += digits;
+        if x >= HASH_MODULUS {
+            x -= HASH_MODULUS;
+        }
+    }
+    let bits = HASH_BITS as i32;
+    let exponent = if exponent >= 0 { exponent % bits } else { bits - 1 - ((-1 - exponent) % bits) } as u32;
+    x = ((x << exponent) & HASH_MODULUS) | x >> (HASH_BITS - exponent);
+    x as i64 * sign
+}
+
+// Splits a float into a mantissa in [0.5, 1) and a power of two, like C's frexp.
+fn frexp(value: f64) -> (f64, i32) {
+    if value == 0.0 || !value.is_finite() {
+        return (value, 0);
+    }
+    let bits = value.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    if exponent == 0 {
+        // Subnormal: scale into the normal range first.
+        let (mantissa, exponent) = frexp(value * 2f64.powi(54));
+        return (mantissa, exponent - 54);
+    }
+    (f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52)), exponent - 1022)
+}
+
+// CPython's tuple hash, a variant of xxHash over the item hashes.
+pub fn hash_tuple(
+    items: &[Value],
+    mut hash_item: impl FnMut(&Value) -> Result<i64, PyError>,
+) -> Result<i64, PyError> {
+    const PRIME_1: u64 = 11400714785074694791;
+    const PRIME_2: u64 = 14029467366897019727;
+    const PRIME_5: u64 = 2870177450012600261;
+    let mut acc = PRIME_5;
+    for item in items {
+        let lane = hash_item(item)? as u64;
+        acc = acc.wrapping_add(lane.wrapping_mul(PRIME_2)).rotate_left(31).wrapping_mul(PRIME_1);
+    }
+    acc = acc.wrapping_add(items.len() as u64 ^ (PRIME_5 ^ 3527539));
+    Ok(if acc == u64::MAX { 1546275796 } else { acc as i64 })
+}
+
+// The items of a value that can be unpacked into a list, e.g. the right-hand
+// side of a slice assignment or the argument to `list.extend`. Dicts give
+// their keys, in insertion order.
+pub fn sequence_items(value: &Value) -> Result<Vec<Value>, PyError> {
+    match value {
+        Value::List(items) => Ok(items.borrow().clone()),
+        Value::Tuple(items) => Ok(items.to_vec()),
+        Value::Dict(dict) | Value::DictView(dict, ViewKind::Keys) => Ok(dict.borrow().keys()),
+        Value::DictView(dict, ViewKind::Values) => Ok(dict.borrow().values()),
+        Value::DictView(dict, ViewKind::Items) => Ok(dict.borrow().items()),
+        Value::Set(set) => Ok(set.borrow().items()),
+        Value::FrozenSet(set) => Ok(set.items()),
+        Value::Str(s) => Ok(s.chars().map(|c| Value::Str(c.to_string())).collect()),
+        _ => {
+            let iterator = iterate(value)?;
+            let mut items = Vec::new();
+            while let Some(item) = iterator.borrow_mut().next()? {
+                items.push(item);
+            }
+            Ok(items)
+        }
+    }
+}
+
+// `value in range`: arithmetic for ints, otherwise a search by equality
+// (so `2.0 in range(3)` holds).
+fn range_contains(range: &Range, value: &Value) -> Result<bool, PyError> {
+    if let (Value::Int(_) | Value::Bool(_), Some(n)) = (value, value.as_int()) {
+        let (start, stop, step) = (range.start as i128, range.stop as i128, range.step as i128);
+        let n = n as i128;
+        let in_bounds = if step > 0 { start <= n && n < stop } else { stop < n && n <= start };
+        return Ok(in_bounds && (n - start) % step == 0);
+    }
+    if value.is_int() {
+        return Ok(false); // beyond i64, so beyond any range
+    }
+    Ok((0..range.len()).any(|position| values_equal(&Value::Int(range.get(position)), value)))
+}
+
+// Resolves an index into a sequence of the given length, counting negative
+// indices from the end; None means it is out of range.
+pub fn sequence_index(index: &Value, len: usize, type_name: &str) -> Result<Option<usize>, PyError> {
+    let Some(index) = index.as_int() else {
+        if index.is_int() {
+            return Ok(None);
+        }
+        return Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("{} indices must be integers or slices, not {}", type_name, index.type_name()),
+        ));
+    };
+    let position = if index < 0 { index + len as i64 } else { index };
+    Ok(usize::try_from(position).ok().filter(|position| *position < len))
+}
+
+pub fn slice_bound(bound: &Value) -> Result<Option<i64>, PyError> {
+    match bound {
+        Value::None => Ok(None),
+        // Bounds beyond i64 clamp the same way as any other out-of-range bound.
+        Value::BigInt(n) => Ok(Some(if n.is_negative() { i64::MIN / 2 } else { i64::MAX / 2 })),
+        _ => match bound.as_int() {
+            Some(n) => Ok(Some(n)),
+            None => Err(PyError::new(
+                ErrorKind::TypeError,
+                "slice indices must be integers or None or have an __index__ method",
+            )),
+        },
+    }
+}
+
+// Clamps a slice against a sequence of the given length, as Python's
+// slice.indices() does, giving (start, stop, step).
+pub fn slice_range(slice: &Slice, len: usize) -> Result<(i64, i64, i64), PyError> {
+    let len = len as i64;
+    let step = slice_bound(&slice.step)?.unwrap_or(1);
+    if step == 0 {
+        return Err(PyError::new(ErrorKind::ValueError, "slice step cannot be zero"));
+    }
+    let clamp = |bound: Option<i64>, default: i64| match bound {
+        None => default,
+        Some(n) if n < 0 => (n + len).max(if step < 0 { -1 } else { 0 }),
+        Some(n) => n.min(if step < 0 { len - 1 } else { len }),
+    };
+    let lower = slice_bound(&slice.lower)?;
+    let upper = slice_bound(&slice.upper)?;
+    if step > 0 {
+        Ok((clamp(lower, 0), clamp(upper, len), step))
+    } else {
+        Ok((clamp(lower, len - 1), clamp(upper, -1), step))
+    }
+}
+
+// The positions a slice selects, in order.
+pub fn slice_indices(slice: &Slice, len: usize) -> Result<Vec<usize>, PyError> {
+    let (start, stop, step) = slice_range(slice, len)?;
+    let mut indices = Vec::new();
+    let mut i = start;
+    while (step > 0 && i < stop) || (step < 0 && i > stop) {
+        indices.push(i as usize);
+        match i.checked_add(step) {
+            Some(next) => i = next,
+            None => break,
+        }
+    }
+    Ok(indices)
+}
+
+// `container[index]`, where the index may be a slice.
+pub fn get_item(container: &Value, index: &Value) -> Result<Value, PyError> {
+    match container {
+        Value::List(items) => {
+            let items = items.borrow();
+            if let Value::Slice(slice) = index {
+                let indices = slice_indices(slice, items.len())?;
+                return Ok(Value::new_list(indices.into_iter().map(|i| items[i].clone()).collect()));
+            }
+            match sequence_index(index, items.len(), "list")? {
+                Some(position) => Ok(items[position].clone()),
+                None => Err(PyError::new(ErrorKind::IndexError, "list index out of range")),
+            }
+        }
+        Value::Tuple(items) => {
+            if let Value::Slice(slice) = index {
+                let indices = slice_indices(slice, items.len())?;
+                return Ok(Value::new_tuple(indices.into_iter().map(|i| items[i].clone()).collect()));
+            }
+            match sequence_index(index, items.len(), "tuple")? {
+                Some(position) => Ok(items[position].clone()),
+                None => Err(PyError::new(ErrorKind::IndexError, "tuple index out of range")),
+            }
+        }
+        // Strings index by code point; ASCII ones can index their bytes directly.
+        Value::Str(s) => {
+            let chars: Vec<char> = if s.is_ascii() { Vec::new() } else { s.chars().collect() };
+            let len = if s.is_ascii() { s.len() } else { chars.len() };
+            let char_at = |i: usize| if s.is_ascii() { s.as_bytes()[i] as char } else { chars[i] };
+            if let Value::Slice(slice) = index {
+                return Ok(Value::Str(slice_indices(slice, len)?.into_iter().map(char_at).collect()));
+            }
+            if !index.is_int() {
+                return Err(PyError::new(
+                    ErrorKind::TypeError,
+                    format!("string indices must be integers, not '{}'", index.type_name()),
+                ));
+            }
+            match sequence_index(index, len, "string")? {
+                Some(position) => Ok(Value::Str(char_at(position).to_string())),
+                None => Err(PyError::new(ErrorKind::IndexError, "string index out of range")),
+            }
+        }
+        Value::Bytes(bytes) => {
+            if let Value::Slice(slice) = index {
+                let indices = slice_indices(slice, bytes.len())?;
+                return Ok(Value::Bytes(Rc::new(indices.into_iter().map(|i| bytes[i]).collect())));
+            }
+            match sequence_index(index, bytes.len(), "byte")? {
+                Some(position) => Ok(Value::Int(bytes[position] as i64)),
+                None => Err(PyError::new(ErrorKind::IndexError, "index out of range")),
+            }
+        }
+        Value::Range(range) => {
+            if let Value::Slice(slice) = index {
+                // A slice of a range is another range.
+                let (start, stop, step) = slice_range(slice, range.len())?;
+                let bound = |position: i64| range.start + position * range.step;
+                let sliced = Range { start: bound(start), stop: bound(stop), step: step * range.step };
+                return Ok(Value::Range(Rc::new(sliced)));
+            }
+            match sequence_index(index, range.len(), "range")? {
+                Some(position) => Ok(Value::Int(range.get(position))),
+                None => Err(PyError::new(ErrorKind::IndexError, "range object index out of range")),
+            }
+        }
+        Value::Dict(dict) => match dict.borrow().get(index)? {
+            Some(value) => Ok(value),
+            None => Err(key_error(index)),
+        },
+        _ => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("'{}' object is not subscriptable", container.type_name()),
+        )),
+    }
+}
+
+// `container[index] = value`. Assigning to a plain slice may change the
+// length of the list; an extended slice needs exactly as many items as it selects.
+pub fn set_item(container: &Value, index: &Value, value: Value) -> Result<(), PyError> {
+    if let Value::Dict(dict) = container {
+        return dict.borrow_mut().insert(index.clone(), value);
+    }
+    let Value::List(items) = container else {
+        return Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("'{}' object does not support item assignment", container.type_name()),
+        ));
+    };
+    if let Value::Slice(slice) = index {
+        // Copy the replacement first, since it may be the list being assigned to.
+        let replacement = sequence_items(&value)
+            .map_err(|_| PyError::new(ErrorKind::TypeError, "can only assign an iterable"))?;
+        let mut items = items.borrow_mut();
+        let (start, stop, step) = slice_range(slice, items.len())?;
+        if step == 1 {
+            let start = start as usize;
+            items.splice(start..(stop as usize).max(start), replacement);
+            return Ok(());
+        }
+        let indices = slice_indices(slice, items.len())?;
+        if indices.len() != replacement.len() {
+            return Err(PyError::new(
+                ErrorKind::ValueError,
+                format!(
+                    "attempt to assign sequence of size {} to extended slice of size {}",
+                    replacement.len(),
+                    indices.len()
+                ),
+            ));
+        }
+        for (position, item) in indices.into_iter().zip(replacement) {
+            items[position] = item;
+        }
+        return Ok(());
+    }
+    let mut items = items.borrow_mut();
+    match sequence_index(index, items.len(), "list")? {
+        Some(position) => {
+            items[position] = value;
+            Ok(())
+        }
+        None => Err(PyError::new(ErrorKind::IndexError, "list assignment index out of range")),
+    }
+}
+
+// KeyError's message is the repr of the missing key, and its argument the key.
+pub fn key_error(key: &Value) -> PyError {
+    let mut error = PyError::new(ErrorKind::KeyError, key.repr());
+    error.exception = Some(new_exception(ErrorKind::KeyError, vec![key.clone()]));
+    error
+}
+
+// `del container[index]`.
+pub fn delete_item(container: &Value, index: &Value) -> Result<(), PyError> {
+    match container {
+        Value::Dict(dict) => match dict.borrow_mut().remove(index)? {
+            Some(_) => Ok(()),
+            None => Err(key_error(index)),
+        },
+        Value::List(items) => {
+            let mut items = items.borrow_mut();
+            if let Value::Slice(slice) = index {
+                // Remove from the back so earlier positions stay valid.
+                let mut indices = slice_indices(slice, items.len())?;
+                indices.sort_unstable();
+                for position in indices.into_iter().rev() {
+                    items.remove(position);
+                }
+                return Ok(());
+            }
+            match sequence_index(index, items.len(), "list")? {
+                Some(position) => {
+                    items.remove(position);
+                    Ok(())
+                }
+                None => Err(PyError::new(ErrorKind::IndexError, "list assignment index out of range")),
+            }
+        }
+        _ => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("'{}' object doesn't support item deletion", container.type_name()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::binary_op;
+    use crate::ast::Value;
+
+    fn float(operator: &str, left: f64, right: f64) -> String {
+        binary_op(operator, &Value::Float(left), &Value::Float(right)).unwrap().repr()
+    }
+
+    #[test]
+    fn float_floor_division_matches_cpython() {
+        let inf = f64::INFINITY;
+        let cases = [
+            (4184.0, -0.14285714285714285, "-29289.0", "-0.1428571428569106"),
+            (-1.0, inf, "-1.0", "inf"),
+            (5.0, -inf, "-1.0", "-inf"),
+            (inf, 1.0, "nan", "nan"),
+            (7.5, 2.0, "3.0", "1.5"),
+            (-7.5, 2.0, "-4.0", "0.5"),
+            (7.5, -2.0, "-4.0", "-0.5"),
+            (0.0, -3.0, "-0.0", "-0.0"),
+            (-0.0, 3.0, "-0.0", "0.0"),
+            (1e308, 1e-308, "inf", "3.498445546245627e-309"),
+            (0.1, 0.01, "10.0", "3.469446951953614e-18"),
+            (-1e-300, 1e300, "-1.0", "1e+300"),
+            (3.0, 0.1, "29.0", "0.09999999999999984"),
+        ];
+        for (left, right, quotient, remainder) in cases {
+            assert_eq!(float("//", left, right), quotient, "{:?} // {:?}", left, right);
+            assert_eq!(float("%", left, right), remainder, "{:?} % {:?}", left, right);
+        }
+    }
+}
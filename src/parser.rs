@@ -1,77 +1,217 @@
-use crate::ast::Expr;
-use crate::token::Token;
+use crate::ast::{ExceptHandler, Expr};
+use crate::error::{ErrorKind, PyError, Span};
+use crate::lexer::fstring_error;
+use crate::lower;
+use crate::scope::{self, Kind};
+use crate::token::{FStringPart, SpannedToken, Token};
+use std::mem;
+use std::rc::Rc;
+
+// The positional and keyword (`name=value`) arguments of a call.
+type Arguments = (Vec<Expr>, Vec<(String, Expr)>);
 
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<SpannedToken>,
     current: usize,
+    function_depth: usize, // how many `def` bodies enclose the current token
+    loop_depth: usize,     // how many loop bodies enclose it within the current function
+    scopes: Vec<String>,   // qualified names of the enclosing classes and functions
+    yields: bool,          // whether the function body being parsed contains `yield`
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+    pub fn new(tokens: Vec<SpannedToken>) -> Self {
+        Self { tokens, current: 0, function_depth: 0, loop_depth: 0, scopes: Vec::new(), yields: false }
     }
 
-    pub fn parse(&mut self) -> Vec<Expr> {
+    pub fn parse(&mut self) -> Result<Vec<Expr>, PyError> {
         let mut expressions = Vec::new();
         while self.current < self.tokens.len() {
-            if let Some(Token::Newline) = self.tokens.get(self.current) {
-                self.current += 1;
-                continue;
-            }
-            if let Some(expr) = self.parse_statement() {
-                expressions.push(expr);
-            } else {
-                self.current += 1;
+            match self.peek() {
+                Some(Token::Newline) => self.current += 1,
+                Some(Token::Indent) => return Err(self.error(ErrorKind::IndentationError, "unexpected indent")),
+                _ => expressions.push(self.parse_statement()?),
             }
         }
-        expressions
+        scope::analyze(Kind::Module, &[], &expressions)?;
+        Ok(expressions)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.current + offset).map(|t| &t.token)
     }
 
-    fn parse_statement(&mut self) -> Option<Expr> {
-        match self.tokens.get(self.current) {
-            Some(Token::Identifier(_)) => self.parse_assignment(),
+    // The span of the current token, or of the last one at end of input.
+    fn span(&self) -> Span {
+        self.tokens
+            .get(self.current)
+            .or_else(|| self.tokens.last())
+            .map_or(Span::new(1, 0, 0), |t| t.span)
+    }
+
+    // The span of the most recently consumed token.
+    fn previous_span(&self) -> Span {
+        self.tokens
+            .get(self.current.saturating_sub(1))
+            .map_or(Span::new(1, 0, 0), |t| t.span)
+    }
+
+    fn error(&self, kind: ErrorKind, message: &str) -> PyError {
+        PyError::new(kind, message).at(self.span())
+    }
+
+    fn syntax_error(&self) -> PyError {
+        self.error(ErrorKind::SyntaxError, "invalid syntax")
+    }
+
+    // Consumes the expected token or reports what was missing.
+    fn expect(&mut self, token: Token, description: &str) -> Result<(), PyError> {
+        if self.peek() == Some(&token) {
+            self.current += 1;
+            Ok(())
+        } else {
+            Err(self.error(ErrorKind::SyntaxError, &format!("expected {}", description)))
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Expr, PyError> {
+        match self.peek() {
             Some(Token::Def) => self.parse_function_def(),
+            Some(Token::Class) => self.parse_class_def(),
+            Some(Token::Pass) => {
+                self.current += 1; // consume 'pass'
+                self.parse_statement_end()?;
+                Ok(Expr::Pass)
+            }
             Some(Token::If) => self.parse_if_condition(),
             Some(Token::Print) => self.parse_print(),
             Some(Token::Return) => self.parse_return(),
+            Some(Token::Break) => self.parse_loop_control(Expr::Break, "'break' outside loop"),
+            Some(Token::Continue) => self.parse_loop_control(Expr::Continue, "'continue' not properly in loop"),
             Some(Token::For) => self.parse_for_loop(),
             Some(Token::While) => self.parse_while_loop(),
-            _ => None,
+            Some(Token::Import) => self.parse_import(),
+            Some(Token::Del) => self.parse_delete(),
+            Some(Token::Try) => self.parse_try(),
+            Some(Token::Raise) => self.parse_raise(),
+            Some(Token::Global | Token::Nonlocal) => self.parse_declaration(),
+            _ => self.parse_expression_statement(),
         }
     }
 
-    fn parse_assignment(&mut self) -> Option<Expr> {
-        if let Some(Token::Identifier(name)) = self.tokens.get(self.current).cloned() {
-            self.current += 1; // consume identifier
-            if let Some(Token::Equals) = self.tokens.get(self.current) {
-                self.current += 1; // consume '='
-                let value = self.parse_expression()?;
-                if let Some(Token::Newline) = self.tokens.get(self.current) {
-                    self.current += 1;
-                }
-                return Some(Expr::Assignment(name, Box::new(value)));
-            }
+    // A bare expression used as a statement (the REPL echoes its value), or
+    // the target of an assignment when followed by '='.
+    fn parse_expression_statement(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let expr = match self.peek() {
+            Some(Token::Yield) => self.parse_yield()?,
+            _ => self.parse_expression_list()?,
+        };
+        let span = start.to(self.previous_span());
+        if let Some(Token::Equals) = self.peek() {
+            check_target(&expr, span)?;
+            self.current += 1; // consume '='
+            let value = self.parse_assigned_value()?;
+            let span = start.to(self.previous_span());
+            self.parse_statement_end()?;
+            return Ok(Expr::Assignment(Box::new(expr), Box::new(value), span));
         }
-        None
+        if let Some(Token::AugmentedAssign(operator)) = self.peek().cloned() {
+            check_augmented_target(&expr, span)?;
+            self.current += 1; // consume the operator
+            let value = self.parse_assigned_value()?;
+            let span = start.to(self.previous_span());
+            self.parse_statement_end()?;
+            return Ok(Expr::AugmentedAssignment {
+                target: Box::new(expr),
+                operator,
+                value: Box::new(value),
+                span,
+            });
+        }
+        check_not_starred(&expr)?;
+        self.parse_statement_end()?;
+        Ok(Expr::Bare(Box::new(expr), span))
     }
 
-    fn parse_function_def(&mut self) -> Option<Expr> {
-        self.current += 1; // consume 'def'
-        let name = if let Some(Token::Identifier(name)) = self.tokens.get(self.current).cloned() {
-            self.current += 1;
-            name
-        } else {
-            return None;
+    // An expression list used as a value, e.g. after '=' or 'return'.
+    fn parse_value_list(&mut self) -> Result<Expr, PyError> {
+        let value = self.parse_expression_list()?;
+        check_not_starred(&value)?;
+        Ok(value)
+    }
+
+    // The value of an assignment, which may also be a bare `yield`.
+    fn parse_assigned_value(&mut self) -> Result<Expr, PyError> {
+        match self.peek() {
+            Some(Token::Yield) => self.parse_yield(),
+            _ => self.parse_value_list(),
+        }
+    }
+
+    // `yield`, `yield value` or `yield from iterable`, any of which makes the
+    // enclosing function a generator.
+    fn parse_yield(&mut self) -> Result<Expr, PyError> {
+        if self.function_depth == 0 {
+            return Err(self.error(ErrorKind::SyntaxError, "'yield' outside function"));
+        }
+        let start = self.span();
+        self.current += 1; // consume 'yield'
+        self.yields = true;
+        if let Some(Token::From) = self.peek() {
+            self.current += 1; // consume 'from'
+            let iterable = self.parse_expression()?;
+            return Ok(Expr::YieldFrom(Box::new(iterable), start.to(self.previous_span())));
+        }
+        let value = match self.peek() {
+            Some(Token::Newline | Token::Dedent | Token::CloseParen) | None => None,
+            _ => Some(Box::new(self.parse_value_list()?)),
         };
+        Ok(Expr::Yield(value, start.to(self.previous_span())))
+    }
+
+    // A simple statement must be followed by the end of its line.
+    fn parse_statement_end(&mut self) -> Result<(), PyError> {
+        match self.peek() {
+            Some(Token::Newline) => {
+                self.current += 1;
+                Ok(())
+            }
+            Some(Token::Dedent) | None => Ok(()),
+            _ => Err(self.syntax_error()),
+        }
+    }
 
-        if let Some(Token::OpenParen) = self.tokens.get(self.current) {
+    fn parse_identifier(&mut self) -> Result<String, PyError> {
+        if let Some(Token::Identifier(name)) = self.peek().cloned() {
             self.current += 1;
+            Ok(name)
         } else {
-            return None;
+            Err(self.syntax_error())
         }
+    }
+
+    // The qualified name of a function or class defined in the current scope,
+    // e.g. `Outer.method` or `function.<locals>.helper`.
+    fn qualify(&self, name: &str) -> String {
+        match self.scopes.last() {
+            Some(scope) => format!("{}.{}", scope, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn parse_function_def(&mut self) -> Result<Expr, PyError> {
+        self.current += 1; // consume 'def'
+        let name = self.parse_identifier()?;
+        let qualname = self.qualify(&name);
+        self.expect(Token::OpenParen, "'('")?;
 
         let mut params = Vec::new();
-        while let Some(token) = self.tokens.get(self.current) {
+        while let Some(token) = self.peek() {
             match token {
                 Token::Identifier(param) => {
                     params.push(param.clone());
@@ -79,292 +219,1003 @@ impl Parser {
                 }
                 Token::Comma => { self.current += 1; },
                 Token::CloseParen => { self.current += 1; break; },
-                _ => { self.current += 1; }
+                _ => return Err(self.syntax_error()),
             }
         }
 
-        if let Some(Token::Colon) = self.tokens.get(self.current) {
-            self.current += 1;
-        } else {
-            return None;
+        self.expect(Token::Colon, "':'")?;
+        // A loop around the `def` doesn't make `break` valid in its body.
+        let loop_depth = mem::replace(&mut self.loop_depth, 0);
+        let yields = mem::replace(&mut self.yields, false);
+        self.function_depth += 1;
+        self.scopes.push(format!("{}.<locals>", qualname));
+        let body = self.parse_block();
+        self.scopes.pop();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        let is_generator = mem::replace(&mut self.yields, yields);
+        let mut body = body?;
+        if is_generator {
+            body = lower::generator_body(body)?;
         }
+        let scope = Rc::new(scope::analyze(Kind::Function, &params, &body)?);
+        Ok(Expr::FunctionDef { name, qualname, params, body: Rc::new(body), is_generator, scope })
+    }
 
+    fn parse_class_def(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        self.current += 1; // consume 'class'
+        let name = self.parse_identifier()?;
+        let qualname = self.qualify(&name);
+        let mut bases = Vec::new();
+        if let Some(Token::OpenParen) = self.peek() {
+            self.current += 1; // consume '('
+            let (args, keywords) = self.parse_arguments()?;
+            // Metaclasses and other class keywords aren't supported.
+            if !keywords.is_empty() {
+                return Err(self.syntax_error());
+            }
+            bases = args;
+        }
+        let span = start.to(self.previous_span());
+        self.expect(Token::Colon, "':'")?;
+        // The class body is not a function body, nor part of an enclosing loop.
+        let function_depth = mem::replace(&mut self.function_depth, 0);
+        let loop_depth = mem::replace(&mut self.loop_depth, 0);
+        self.scopes.push(qualname.clone());
         let body = self.parse_block();
-        Some(Expr::FunctionDef { name, params, body })
+        self.scopes.pop();
+        self.function_depth = function_depth;
+        self.loop_depth = loop_depth;
+        let body = body?;
+        let scope = Rc::new(scope::analyze(Kind::Class, &[], &body)?);
+        Ok(Expr::ClassDef { name, qualname, bases, body, scope, span })
     }
 
-    fn parse_if_condition(&mut self) -> Option<Expr> {
-        self.current += 1; // consume 'if'
+    // Parses `if` and `elif` alike; an `elif` chain becomes nested ifs in `orelse`.
+    fn parse_if_condition(&mut self) -> Result<Expr, PyError> {
+        self.current += 1; // consume 'if' or 'elif'
+        let start = self.span();
         let condition = self.parse_expression()?;
-        if let Some(Token::Colon) = self.tokens.get(self.current) {
-            self.current += 1;
-        } else {
-            return None;
-        }
-        let body = self.parse_block();
-        Some(Expr::IfCondition { condition: Box::new(condition), body })
+        let span = start.to(self.previous_span());
+        self.expect(Token::Colon, "':'")?;
+        let body = self.parse_block()?;
+        let orelse = match self.peek() {
+            Some(Token::Elif) => vec![self.parse_if_condition()?],
+            Some(Token::Else) => {
+                self.current += 1; // consume 'else'
+                self.expect(Token::Colon, "':'")?;
+                self.parse_block()?
+            }
+            _ => Vec::new(),
+        };
+        Ok(Expr::IfCondition { condition: Box::new(condition), body, orelse, span })
     }
 
-    fn parse_print(&mut self) -> Option<Expr> {
+    fn parse_print(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
         self.current += 1; // consume 'print'
         let mut args = Vec::new();
-    
-        if let Some(Token::OpenParen) = self.tokens.get(self.current) {
+
+        if let Some(Token::OpenParen) = self.peek() {
+            let open = self.span();
             self.current += 1; // consume '('
-    
+
             // Parse the first expression.
-            if let Some(expr) = self.parse_expression() {
-                args.push(expr);
+            let first_start = self.span();
+            let first = self.parse_expression()?;
+            if let Some(Token::For) = self.peek() {
+                let generator = self.parse_generator_expression(first, first_start.to(self.previous_span()), open)?;
+                if self.peek() != Some(&Token::CloseParen) {
+                    return Err(self.error(ErrorKind::SyntaxError, "Generator expression must be parenthesized"));
+                }
+                args.push(generator);
             } else {
-                return None;
-            }          
-            while let Some(tok) = self.tokens.get(self.current) {
-                if let Token::Comma = tok {
-                    self.current += 1; // consume comma
-                    if let Some(expr) = self.parse_expression() {
-                        args.push(expr);
-                    } else {
-                        return None;
-                    }
-                } else {
-                    break;
+                args.push(first);
+            }
+            while let Some(Token::Comma) = self.peek() {
+                self.current += 1; // consume comma
+                args.push(self.parse_expression()?);
+                if let Some(Token::For) = self.peek() {
+                    return Err(self.error(ErrorKind::SyntaxError, "Generator expression must be parenthesized"));
                 }
             }
-            if let Some(Token::CloseParen) = self.tokens.get(self.current) {
-                self.current += 1; // consume ')'
+            self.expect(Token::CloseParen, "')'")?;
+        } else {
+            args.push(self.parse_expression()?);
+        }
+
+        let span = start.to(self.previous_span());
+        self.parse_statement_end()?;
+        Ok(Expr::Print(args, span))
+    }
+
+    fn parse_return(&mut self) -> Result<Expr, PyError> {
+        if self.function_depth == 0 {
+            return Err(self.error(ErrorKind::SyntaxError, "'return' outside function"));
+        }
+        let start = self.span();
+        self.current += 1; // consume 'return'
+        let expr = match self.peek() {
+            Some(Token::Newline) | Some(Token::Dedent) | None => None,
+            _ => Some(Box::new(self.parse_value_list()?)),
+        };
+        let span = start.to(self.previous_span());
+        self.parse_statement_end()?;
+        Ok(Expr::Return(expr, span))
+    }
+
+    fn parse_loop_control(&mut self, statement: Expr, outside_loop: &str) -> Result<Expr, PyError> {
+        if self.loop_depth == 0 {
+            return Err(self.error(ErrorKind::SyntaxError, outside_loop));
+        }
+        self.current += 1; // consume 'break' or 'continue'
+        self.parse_statement_end()?;
+        Ok(statement)
+    }
+
+    // A loop body, then the optional `else` block, which is outside the loop.
+    fn parse_loop_blocks(&mut self) -> Result<(Vec<Expr>, Vec<Expr>), PyError> {
+        self.expect(Token::Colon, "':'")?;
+        self.loop_depth += 1;
+        let body = self.parse_block();
+        self.loop_depth -= 1;
+        let orelse = match self.peek() {
+            Some(Token::Else) => {
+                self.current += 1; // consume 'else'
+                self.expect(Token::Colon, "':'")?;
+                self.parse_block()?
+            }
+            _ => Vec::new(),
+        };
+        Ok((body?, orelse))
+    }
+
+    fn parse_for_loop(&mut self) -> Result<Expr, PyError> {
+        self.current += 1; // consume 'for'
+        let start = self.span();
+        let target = self.parse_target_list()?;
+        check_target(&target, start.to(self.previous_span()))?;
+        self.expect(Token::In, "'in'")?;
+        let iterable_start = self.span();
+        let iterable = self.parse_expression_list()?;
+        let span = iterable_start.to(self.previous_span());
+        let (body, orelse) = self.parse_loop_blocks()?;
+        Ok(Expr::ForLoop { target: Box::new(target), iterable: Box::new(iterable), body, orelse, span })
+    }
+
+    // `global names` or `nonlocal names`.
+    fn parse_declaration(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let global = self.peek() == Some(&Token::Global);
+        self.current += 1; // consume 'global' or 'nonlocal'
+        let mut names = vec![self.parse_identifier()?];
+        while let Some(Token::Comma) = self.peek() {
+            self.current += 1;
+            names.push(self.parse_identifier()?);
+        }
+        let span = start.to(self.previous_span());
+        self.parse_statement_end()?;
+        Ok(if global { Expr::Global(names, span) } else { Expr::Nonlocal(names, span) })
+    }
+
+    fn parse_import(&mut self) -> Result<Expr, PyError> {
+        self.current += 1; // consume 'import'
+        let span = self.span();
+        let name = self.parse_identifier()?;
+        self.parse_statement_end()?;
+        Ok(Expr::Import(name, span))
+    }
+
+    fn parse_delete(&mut self) -> Result<Expr, PyError> {
+        let statement_start = self.span();
+        self.current += 1; // consume 'del'
+        let mut targets = Vec::new();
+        loop {
+            let start = self.span();
+            let target = self.parse_expression()?;
+            if !is_delete_target(&target) {
+                let span = start.to(self.previous_span());
+                return Err(PyError::new(ErrorKind::SyntaxError, "cannot delete expression").at(span));
+            }
+            targets.push(target);
+            if let Some(Token::Comma) = self.peek() {
+                self.current += 1;
             } else {
-                return None;
+                break;
             }
-        } else {
-            if let Some(expr) = self.parse_expression() {
-                args.push(expr);
+        }
+        let span = statement_start.to(self.previous_span());
+        self.parse_statement_end()?;
+        Ok(Expr::Delete(targets, span))
+    }
+
+    fn parse_try(&mut self) -> Result<Expr, PyError> {
+        self.current += 1; // consume 'try'
+        self.expect(Token::Colon, "':'")?;
+        let body = self.parse_block()?;
+        let mut handlers: Vec<ExceptHandler> = Vec::new();
+        // Where a bare `except:` was seen, since it must be the last clause.
+        let mut catch_all = None;
+        while let Some(Token::Except) = self.peek() {
+            if let Some(span) = catch_all {
+                return Err(PyError::new(ErrorKind::SyntaxError, "default 'except:' must be last").at(span));
+            }
+            let start = self.span();
+            self.current += 1; // consume 'except'
+            let types = match self.peek() {
+                Some(Token::Colon) => {
+                    catch_all = Some(start.to(self.span()));
+                    None
+                }
+                _ => Some(self.parse_expression()?),
+            };
+            let name = match self.peek() {
+                Some(Token::As) => {
+                    self.current += 1; // consume 'as'
+                    Some(self.parse_identifier()?)
+                }
+                _ => None,
+            };
+            let span = start.to(self.previous_span());
+            self.expect(Token::Colon, "':'")?;
+            let body = self.parse_block()?;
+            handlers.push(ExceptHandler { types, name, body, span });
+        }
+        let mut orelse = Vec::new();
+        if let (Some(Token::Else), false) = (self.peek(), handlers.is_empty()) {
+            self.current += 1; // consume 'else'
+            self.expect(Token::Colon, "':'")?;
+            orelse = self.parse_block()?;
+        }
+        let mut finalbody = Vec::new();
+        if let Some(Token::Finally) = self.peek() {
+            self.current += 1; // consume 'finally'
+            self.expect(Token::Colon, "':'")?;
+            finalbody = self.parse_block()?;
+        } else if handlers.is_empty() {
+            return Err(self.error(ErrorKind::SyntaxError, "expected 'except' or 'finally' block"));
+        }
+        Ok(Expr::Try { body, handlers, orelse, finalbody })
+    }
+
+    fn parse_raise(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        self.current += 1; // consume 'raise'
+        let mut exception = None;
+        let mut cause = None;
+        if !matches!(self.peek(), Some(Token::Newline) | Some(Token::Dedent) | None) {
+            exception = Some(Box::new(self.parse_expression()?));
+            if let Some(Token::From) = self.peek() {
+                self.current += 1; // consume 'from'
+                cause = Some(Box::new(self.parse_expression()?));
+            }
+        }
+        let span = start.to(self.previous_span());
+        self.parse_statement_end()?;
+        Ok(Expr::Raise { exception, cause, span })
+    }
+
+    fn parse_while_loop(&mut self) -> Result<Expr, PyError> {
+        self.current += 1; // consume 'while'
+        let start = self.span();
+        let condition = self.parse_expression()?;
+        let span = start.to(self.previous_span());
+        let (body, orelse) = self.parse_loop_blocks()?;
+        Ok(Expr::WhileLoop { condition: Box::new(condition), body, orelse, span })
+    }
+
+    // One or more comma-separated expressions; a comma makes a tuple, as in
+    // `return a, b` or `a, b = b, a`. Items may be starred, for unpacking.
+    fn parse_expression_list(&mut self) -> Result<Expr, PyError> {
+        let first = self.parse_starred_expression()?;
+        self.continue_expression_list(first)
+    }
+
+    // The rest of an expression list after its first element.
+    fn continue_expression_list(&mut self, first: Expr) -> Result<Expr, PyError> {
+        if self.peek() != Some(&Token::Comma) {
+            return Ok(first);
+        }
+        let mut elements = vec![first];
+        while let Some(Token::Comma) = self.peek() {
+            self.current += 1;
+            // A trailing comma is allowed, as in `(1,)`.
+            if matches!(
+                self.peek(),
+                Some(Token::Newline | Token::Dedent | Token::Equals | Token::CloseParen) | None
+            ) {
+                break;
+            }
+            elements.push(self.parse_starred_expression()?);
+        }
+        Ok(Expr::Tuple(elements))
+    }
+
+    fn parse_starred_expression(&mut self) -> Result<Expr, PyError> {
+        if let Some(Token::Star) = self.peek() {
+            let start = self.span();
+            self.current += 1; // consume '*'
+            let value = self.parse_additive_expression()?;
+            return Ok(Expr::Starred(Box::new(value), start.to(self.previous_span())));
+        }
+        self.parse_expression()
+    }
+
+    // The target list of a `for` loop, which ends at `in` rather than being
+    // parsed as a comparison.
+    fn parse_target_list(&mut self) -> Result<Expr, PyError> {
+        let mut elements = Vec::new();
+        let mut trailing_comma = false;
+        while elements.is_empty() || (trailing_comma && self.peek() != Some(&Token::In)) {
+            if let Some(Token::Star) = self.peek() {
+                let start = self.span();
+                self.current += 1; // consume '*'
+                let target = self.parse_postfix()?;
+                elements.push(Expr::Starred(Box::new(target), start.to(self.previous_span())));
             } else {
-                return None;
+                elements.push(self.parse_postfix()?);
+            }
+            trailing_comma = self.peek() == Some(&Token::Comma);
+            if trailing_comma {
+                self.current += 1;
             }
         }
-    
-        if let Some(Token::Newline) = self.tokens.get(self.current) {
+        if elements.len() == 1 && !trailing_comma {
+            return Ok(elements.pop().unwrap());
+        }
+        Ok(Expr::Tuple(elements))
+    }
+
+    fn parse_expression(&mut self) -> Result<Expr, PyError> {
+        if let Some(Token::Lambda) = self.peek() {
+            return self.parse_lambda();
+        }
+        self.parse_or_expression()
+    }
+
+    // `lambda params: value`, parsed as a function whose body is `return value`.
+    fn parse_lambda(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        self.current += 1; // consume 'lambda'
+        let mut params = Vec::new();
+        while let Some(Token::Identifier(param)) = self.peek().cloned() {
+            params.push(param);
             self.current += 1;
+            if self.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.current += 1; // consume ','
         }
-    
-        Some(Expr::Print(args))
+        self.expect(Token::Colon, "':'")?;
+        let qualname = self.qualify("<lambda>");
+        let yields = mem::replace(&mut self.yields, false);
+        self.function_depth += 1;
+        self.scopes.push(format!("{}.<locals>", qualname));
+        let value = self.parse_expression();
+        self.scopes.pop();
+        self.function_depth -= 1;
+        let is_generator = mem::replace(&mut self.yields, yields);
+        let mut body = vec![Expr::Return(Some(Box::new(value?)), start.to(self.previous_span()))];
+        if is_generator {
+            body = lower::generator_body(body)?;
+        }
+        let scope = Rc::new(scope::analyze(Kind::Function, &params, &body)?);
+        Ok(Expr::Lambda { qualname, params, body: Rc::new(body), is_generator, scope })
     }
 
-    fn parse_return(&mut self) -> Option<Expr> {
-        self.current += 1; // consume 'return'
-        let expr = self.parse_expression()?;
-        if let Some(Token::Newline) = self.tokens.get(self.current) {
+    fn parse_or_expression(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut left = self.parse_and_expression()?;
+        while let Some(Token::Or) = self.peek() {
             self.current += 1;
+            let right = self.parse_and_expression()?;
+            let span = start.to(self.previous_span());
+            left = Expr::Logical { left: Box::new(left), operator: "or".to_string(), right: Box::new(right), span };
         }
-        Some(Expr::Return(Box::new(expr)))
+        Ok(left)
     }
 
-    fn parse_for_loop(&mut self) -> Option<Expr> {
-        self.current += 1; // consume 'for'
-        let iterator = if let Some(Token::Identifier(name)) = self.tokens.get(self.current).cloned() {
+    fn parse_and_expression(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut left = self.parse_not_expression()?;
+        while let Some(Token::And) = self.peek() {
             self.current += 1;
-            name
-        } else {
-            return None;
-        };
+            let right = self.parse_not_expression()?;
+            let span = start.to(self.previous_span());
+            left = Expr::Logical { left: Box::new(left), operator: "and".to_string(), right: Box::new(right), span };
+        }
+        Ok(left)
+    }
 
-        if let Some(Token::In) = self.tokens.get(self.current) {
+    fn parse_not_expression(&mut self) -> Result<Expr, PyError> {
+        if let Some(Token::Not) = self.peek() {
+            let start = self.span();
             self.current += 1;
-        } else {
-            return None;
+            let operand = self.parse_not_expression()?;
+            return Ok(Expr::Not(Box::new(operand), start.to(self.previous_span())));
         }
+        self.parse_comparison()
+    }
 
-        if let Some(Token::Range) = self.tokens.get(self.current) {
+    // Comparison operators all share one precedence level and chain, so
+    // `a < b < c` means `a < b and b < c` with `b` evaluated once.
+    fn parse_comparison(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let left = self.parse_bitwise_or()?;
+        let mut operators = Vec::new();
+        let mut comparators = Vec::new();
+        loop {
+            let operator = match (self.peek(), self.peek_at(1)) {
+                (Some(Token::EqualEqual), _) => "==",
+                (Some(Token::NotEqual), _) => "!=",
+                (Some(Token::Less), _) => "<",
+                (Some(Token::LessEqual), _) => "<=",
+                (Some(Token::Greater), _) => ">",
+                (Some(Token::GreaterEqual), _) => ">=",
+                (Some(Token::In), _) => "in",
+                (Some(Token::Not), Some(Token::In)) => "not in",
+                (Some(Token::Is), Some(Token::Not)) => "is not",
+                (Some(Token::Is), _) => "is",
+                _ => break,
+            };
+            // Two-word operators consume both tokens.
+            self.current += if operator.contains(' ') { 2 } else { 1 };
+            operators.push(operator.to_string());
+            comparators.push(self.parse_bitwise_or()?);
+        }
+        if operators.is_empty() {
+            return Ok(left);
+        }
+        Ok(Expr::Compare {
+            left: Box::new(left),
+            operators,
+            comparators,
+            span: start.to(self.previous_span()),
+        })
+    }
+
+    // Builds a binary node spanning from the start of the left operand to the
+    // end of the right one.
+    fn binary(&self, left: Expr, operator: &str, right: Expr, start: Span) -> Expr {
+        Expr::Arithmetic {
+            left: Box::new(left),
+            operator: operator.to_string(),
+            right: Box::new(right),
+            span: start.to(self.previous_span()),
+        }
+    }
+
+    // The bitwise operators bind tighter than comparisons but looser than
+    // arithmetic: `|` loosest, then `^`, then `&`.
+    fn parse_bitwise_or(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut left = self.parse_bitwise_xor()?;
+        while let Some(Token::Pipe) = self.peek() {
             self.current += 1;
-        } else {
-            return None;
+            let right = self.parse_bitwise_xor()?;
+            left = self.binary(left, "|", right, start);
         }
+        Ok(left)
+    }
 
-        if let Some(Token::OpenParen) = self.tokens.get(self.current) {
+    fn parse_bitwise_xor(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut left = self.parse_bitwise_and()?;
+        while let Some(Token::Caret) = self.peek() {
             self.current += 1;
-        } else {
-            return None;
+            let right = self.parse_bitwise_and()?;
+            left = self.binary(left, "^", right, start);
         }
+        Ok(left)
+    }
 
-        let start = self.parse_expression()?;
-        if let Some(Token::Comma) = self.tokens.get(self.current) {
+    fn parse_bitwise_and(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut left = self.parse_shift_expression()?;
+        while let Some(Token::Ampersand) = self.peek() {
             self.current += 1;
-        } else {
-            return None;
+            let right = self.parse_shift_expression()?;
+            left = self.binary(left, "&", right, start);
         }
-        let end = self.parse_expression()?;
-        if let Some(Token::CloseParen) = self.tokens.get(self.current) {
+        Ok(left)
+    }
+
+    fn parse_shift_expression(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut left = self.parse_additive_expression()?;
+        while let Some(token) = self.peek() {
+            let operator = match token {
+                Token::LeftShift => "<<",
+                Token::RightShift => ">>",
+                _ => break,
+            };
             self.current += 1;
-        } else {
-            return None;
+            let right = self.parse_additive_expression()?;
+            left = self.binary(left, operator, right, start);
         }
-        if let Some(Token::Colon) = self.tokens.get(self.current) {
+        Ok(left)
+    }
+
+    fn parse_additive_expression(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut left = self.parse_multiplicative_expression()?;
+        while let Some(token) = self.peek() {
+            let operator = match token {
+                Token::Plus => "+",
+                Token::Minus => "-",
+                _ => break,
+            };
             self.current += 1;
-        } else {
-            return None;
+            let right = self.parse_multiplicative_expression()?;
+            left = self.binary(left, operator, right, start);
         }
-        let body = self.parse_block();
-        Some(Expr::ForLoop {
-            iterator,
-            range_start: Box::new(start),
-            range_end: Box::new(end),
-            body,
-        })
+        Ok(left)
     }
 
-    fn parse_while_loop(&mut self) -> Option<Expr> {
-        self.current += 1; // consume 'while'
-        let condition = self.parse_expression()?;
-        if let Some(Token::Colon) = self.tokens.get(self.current) {
+    fn parse_multiplicative_expression(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut left = self.parse_unary()?;
+        while let Some(token) = self.peek() {
+            let operator = match token {
+                Token::Star => "*",
+                Token::Slash => "/",
+                Token::DoubleSlash => "//",
+                Token::Percent => "%",
+                _ => break,
+            };
             self.current += 1;
-        } else {
-            return None;
+            let right = self.parse_unary()?;
+            left = self.binary(left, operator, right, start);
         }
-        let body = self.parse_block();
-        Some(Expr::WhileLoop { condition: Box::new(condition), body })
+        Ok(left)
     }
 
-    fn parse_expression(&mut self) -> Option<Expr> {
-        self.parse_additive_expression()
+    // Unary operators bind looser than `**` on their right, so `-2 ** 2` is
+    // -4, but tighter on its left: `2 ** -1` is 0.5.
+    fn parse_unary(&mut self) -> Result<Expr, PyError> {
+        let operator = match self.peek() {
+            Some(Token::Minus) => "-",
+            Some(Token::Plus) => "+",
+            Some(Token::Tilde) => "~",
+            _ => return self.parse_power(),
+        };
+        let start = self.span();
+        self.current += 1;
+        let operand = self.parse_unary()?;
+        Ok(Expr::UnaryOp {
+            operator: operator.to_string(),
+            operand: Box::new(operand),
+            span: start.to(self.previous_span()),
+        })
     }
 
-    fn parse_additive_expression(&mut self) -> Option<Expr> {
-        let mut left = self.parse_multiplicative_expression()?;
-        while let Some(token) = self.tokens.get(self.current) {
-            match token {
-                Token::Plus => {
-                    self.current += 1;
-                    let right = self.parse_multiplicative_expression()?;
-                    left = Expr::Arithmetic {
-                        left: Box::new(left),
-                        operator: "+".to_string(),
-                        right: Box::new(right),
+    fn parse_power(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let left = self.parse_postfix()?;
+        if let Some(Token::DoubleStar) = self.peek() {
+            self.current += 1;
+            let right = self.parse_unary()?;
+            return Ok(self.binary(left, "**", right, start));
+        }
+        Ok(left)
+    }
+
+    // A primary expression followed by any number of calls, subscripts and
+    // attribute lookups, e.g. `items[0].method(1)`.
+    fn parse_postfix(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut expr = self.parse_primary()?;
+        loop {
+            match self.peek() {
+                Some(Token::OpenParen) => {
+                    self.current += 1; // consume '('
+                    let (args, keywords) = self.parse_arguments()?;
+                    expr = Expr::Call {
+                        function: Box::new(expr),
+                        args,
+                        keywords,
+                        span: start.to(self.previous_span()),
                     };
                 }
-                Token::Minus => {
-                    self.current += 1;
-                    let right = self.parse_multiplicative_expression()?;
-                    left = Expr::Arithmetic {
-                        left: Box::new(left),
-                        operator: "-".to_string(),
-                        right: Box::new(right),
+                Some(Token::OpenBracket) => {
+                    self.current += 1; // consume '['
+                    let index = self.parse_subscript_index()?;
+                    self.expect(Token::CloseBracket, "']'")?;
+                    expr = Expr::Subscript {
+                        value: Box::new(expr),
+                        index: Box::new(index),
+                        span: start.to(self.previous_span()),
                     };
                 }
-                _ => break,
+                Some(Token::Dot) => {
+                    self.current += 1; // consume '.'
+                    let name = self.parse_identifier()?;
+                    expr = Expr::Attribute { value: Box::new(expr), name, span: start.to(self.previous_span()) };
+                }
+                _ => return Ok(expr),
             }
         }
-        Some(left)
     }
 
-    fn parse_multiplicative_expression(&mut self) -> Option<Expr> {
-        let mut left = self.parse_power()?;
-        while let Some(token) = self.tokens.get(self.current) {
-            match token {
-                Token::Star => {
-                    self.current += 1;
-                    let right = self.parse_power()?;
-                    left = Expr::Arithmetic {
-                        left: Box::new(left),
-                        operator: "*".to_string(),
-                        right: Box::new(right),
-                    };
-                }
-                Token::Slash => {
-                    self.current += 1;
-                    let right = self.parse_power()?;
-                    left = Expr::Arithmetic {
-                        left: Box::new(left),
-                        operator: "/".to_string(),
-                        right: Box::new(right),
-                    };
-                }
-                Token::DoubleSlash => {
-                    self.current += 1;
-                    let right = self.parse_power()?;
-                    left = Expr::Arithmetic {
-                        left: Box::new(left),
-                        operator: "//".to_string(),
-                        right: Box::new(right),
-                    };
+    // Call arguments up to and including the closing ')'. Keyword arguments
+    // (`name=value`) must come after all positional ones.
+    fn parse_arguments(&mut self) -> Result<Arguments, PyError> {
+        let open = self.previous_span(); // the call's '('
+        let mut args = Vec::new();
+        let mut keywords = Vec::new();
+        while self.peek() != Some(&Token::CloseParen) {
+            if let (Some(Token::Identifier(name)), Some(Token::Equals)) = (self.peek(), self.peek_at(1)) {
+                let name = name.clone();
+                self.current += 2; // consume the name and '='
+                keywords.push((name, self.parse_expression()?));
+            } else if keywords.is_empty() {
+                let start = self.span();
+                let arg = self.parse_expression()?;
+                if let Some(Token::For) = self.peek() {
+                    // A generator expression needs no parentheses of its own as
+                    // the only argument.
+                    let generator = self.parse_generator_expression(arg, start.to(self.previous_span()), open)?;
+                    if !args.is_empty() || self.peek() != Some(&Token::CloseParen) {
+                        return Err(self.error(ErrorKind::SyntaxError, "Generator expression must be parenthesized"));
+                    }
+                    args.push(generator);
+                } else {
+                    args.push(arg);
                 }
-                _ => break,
+            } else {
+                return Err(self.error(ErrorKind::SyntaxError, "positional argument follows keyword argument"));
+            }
+            if let Some(Token::Comma) = self.peek() {
+                self.current += 1;
+            } else {
+                break;
             }
         }
-        Some(left)
+        self.expect(Token::CloseParen, "')'")?;
+        Ok((args, keywords))
     }
 
-    fn parse_power(&mut self) -> Option<Expr> {
-        let left = self.parse_unary()?;
-        if let Some(Token::DoubleStar) = self.tokens.get(self.current) {
-            self.current += 1;
-            let right = self.parse_power()?;
-            return Some(Expr::Arithmetic {
-                left: Box::new(left),
-                operator: "**".to_string(),
-                right: Box::new(right),
-            });
+    // The `for` and `if` clauses of a generator expression, after its element,
+    // up to the ')' closing the parentheses opened at `open`. They become the
+    // body of its generator function: nested loops and conditions around
+    // `yield element`, the outermost loop walking `.0`.
+    fn parse_generator_expression(&mut self, element: Expr, element_span: Span, open: Span) -> Result<Expr, PyError> {
+        let mut clauses = Vec::new();
+        while let Some(Token::For) = self.peek() {
+            self.current += 1; // consume 'for'
+            let target_start = self.span();
+            let target = self.parse_target_list()?;
+            check_target(&target, target_start.to(self.previous_span()))?;
+            self.expect(Token::In, "'in'")?;
+            let iterable_start = self.span();
+            let iterable = self.parse_expression()?;
+            let span = iterable_start.to(self.previous_span());
+            let mut conditions = Vec::new();
+            while let Some(Token::If) = self.peek() {
+                self.current += 1; // consume 'if'
+                let condition_start = self.span();
+                let condition = self.parse_expression()?;
+                conditions.push((condition, condition_start.to(self.previous_span())));
+            }
+            clauses.push((target, iterable, span, conditions));
+        }
+        // Only the outermost iterable is evaluated outside the generator.
+        let inner = clauses.iter().enumerate().flat_map(|(i, (_, iterable, _, conditions))| {
+            conditions.iter().map(|(condition, _)| condition).chain((i > 0).then_some(iterable))
+        });
+        if let Some(span) = std::iter::once(&element).chain(inner).find_map(lower::find_yield) {
+            return Err(PyError::new(ErrorKind::SyntaxError, "'yield' inside generator expression").at(span));
         }
-        Some(left)
+        let element = Expr::Yield(Some(Box::new(element)), element_span);
+        let mut body = vec![Expr::Bare(Box::new(element), element_span)];
+        let mut outermost = None;
+        for (i, (target, iterable, span, conditions)) in clauses.into_iter().enumerate().rev() {
+            for (condition, span) in conditions.into_iter().rev() {
+                body = vec![Expr::IfCondition { condition: Box::new(condition), body, orelse: Vec::new(), span }];
+            }
+            let iterable = match i {
+                0 => {
+                    outermost = Some(iterable);
+                    Expr::Variable(".0".to_string(), span)
+                }
+                _ => iterable,
+            };
+            let iterable = Box::new(iterable);
+            body = vec![Expr::ForLoop { target: Box::new(target), iterable, body, orelse: Vec::new(), span }];
+        }
+        let scope = Rc::new(scope::analyze(Kind::Function, &[".0".to_string()], &body)?);
+        Ok(Expr::GeneratorExp {
+            qualname: self.qualify("<genexpr>"),
+            span: open.to(self.span()),
+            body: Rc::new(body),
+            scope,
+            iterable: Box::new(outermost.expect("the outermost clause sets it")),
+        })
     }
 
-    fn parse_unary(&mut self) -> Option<Expr> {
-        if let Some(Token::Minus) = self.tokens.get(self.current) {
+    // The inside of `[...]` after a value: a plain index or a `lower:upper:step` slice.
+    fn parse_subscript_index(&mut self) -> Result<Expr, PyError> {
+        let bound = |parser: &mut Self| -> Result<Option<Box<Expr>>, PyError> {
+            match parser.peek() {
+                Some(Token::Colon) | Some(Token::CloseBracket) => Ok(None),
+                _ => Ok(Some(Box::new(parser.parse_expression()?))),
+            }
+        };
+        let lower = bound(self)?;
+        if self.peek() != Some(&Token::Colon) {
+            let index = *lower.ok_or_else(|| self.syntax_error())?;
+            // `d[a, b]` indexes with the tuple `(a, b)`.
+            if self.peek() != Some(&Token::Comma) {
+                return Ok(index);
+            }
+            let mut elements = vec![index];
+            while let Some(Token::Comma) = self.peek() {
+                self.current += 1;
+                if self.peek() == Some(&Token::CloseBracket) {
+                    break;
+                }
+                elements.push(self.parse_expression()?);
+            }
+            return Ok(Expr::Tuple(elements));
+        }
+        self.current += 1; // consume ':'
+        let upper = bound(self)?;
+        let mut step = None;
+        if let Some(Token::Colon) = self.peek() {
             self.current += 1;
-            let expr = self.parse_unary()?;
-            return Some(Expr::Arithmetic {
-                left: Box::new(Expr::Number(0)),
-                operator: "-".to_string(),
-                right: Box::new(expr),
-            });
+            step = bound(self)?;
         }
-        self.parse_primary()
+        Ok(Expr::Slice { lower, upper, step })
     }
 
-    fn parse_primary(&mut self) -> Option<Expr> {
-        if let Some(Token::OpenParen) = self.tokens.get(self.current) {
+    fn parse_primary(&mut self) -> Result<Expr, PyError> {
+        if let Some(Token::OpenParen) = self.peek() {
+            let open = self.span();
             self.current += 1;
-            let expr = self.parse_expression();
-            if let Some(Token::CloseParen) = self.tokens.get(self.current) {
+            if let Some(Token::CloseParen) = self.peek() {
                 self.current += 1;
-                return expr;
-            } else {
-                return None;
+                return Ok(Expr::Tuple(Vec::new()));
+            }
+            if let Some(Token::Yield) = self.peek() {
+                let value = self.parse_yield()?;
+                self.expect(Token::CloseParen, "')'")?;
+                return Ok(value);
+            }
+            let start = self.span();
+            let first = self.parse_starred_expression()?;
+            if let Some(Token::For) = self.peek() {
+                check_not_starred(&first)?;
+                let expr = self.parse_generator_expression(first, start.to(self.previous_span()), open)?;
+                self.expect(Token::CloseParen, "')'")?;
+                return Ok(expr);
+            }
+            let expr = self.continue_expression_list(first)?;
+            self.expect(Token::CloseParen, "')'")?;
+            return Ok(expr);
+        }
+        if let Some(Token::OpenBracket) = self.peek() {
+            self.current += 1;
+            let mut elements = Vec::new();
+            while self.peek() != Some(&Token::CloseBracket) {
+                elements.push(self.parse_starred_expression()?);
+                if let Some(Token::Comma) = self.peek() {
+                    self.current += 1;
+                } else {
+                    break;
+                }
+            }
+            self.expect(Token::CloseBracket, "']'")?;
+            return Ok(Expr::List(elements));
+        }
+        if let Some(Token::OpenBrace) = self.peek() {
+            let start = self.span();
+            self.current += 1;
+            // `{}` is an empty dict; a first element without ':' makes a set.
+            let mut is_set = false;
+            let mut entries = Vec::new();
+            let mut elements = Vec::new();
+            while self.peek() != Some(&Token::CloseBrace) {
+                let key = self.parse_starred_expression()?;
+                if entries.is_empty() && elements.is_empty() {
+                    is_set = self.peek() != Some(&Token::Colon);
+                }
+                if is_set {
+                    elements.push(key);
+                } else {
+                    check_not_starred(&key)?;
+                    self.expect(Token::Colon, "':'")?;
+                    entries.push((key, self.parse_expression()?));
+                }
+                if let Some(Token::Comma) = self.peek() {
+                    self.current += 1;
+                } else {
+                    break;
+                }
+            }
+            self.expect(Token::CloseBrace, "'}'")?;
+            let span = start.to(self.previous_span());
+            return Ok(if is_set { Expr::Set(elements, span) } else { Expr::Dict(entries, span) });
+        }
+        if let Some(Token::StringLiteral(_) | Token::Bytes(_) | Token::FString(_)) = self.peek() {
+            return self.parse_string_literals();
+        }
+        let span = self.span();
+        let expr = match self.peek() {
+            Some(Token::Number(value)) => Expr::Number(*value),
+            Some(Token::BigNumber(value)) => Expr::BigNumber(Rc::new(value.clone())),
+            Some(Token::Float(value)) => Expr::Float(*value),
+            Some(Token::True) => Expr::Bool(true),
+            Some(Token::False) => Expr::Bool(false),
+            Some(Token::None) => Expr::None,
+            Some(Token::Identifier(name)) => Expr::Variable(name.clone(), span),
+            _ => return Err(self.syntax_error()),
+        };
+        self.current += 1;
+        Ok(expr)
+    }
+
+    // Adjacent string literals, as in `'a' 'b'`, are joined into one; str and
+    // bytes literals can't be mixed. Any f-string among them makes the whole
+    // a joined string.
+    fn parse_string_literals(&mut self) -> Result<Expr, PyError> {
+        let start = self.span();
+        let mut parts = Vec::new();
+        let (mut text, mut bytes, mut formatted) = (false, None::<Vec<u8>>, false);
+        loop {
+            match self.peek() {
+                Some(Token::StringLiteral(s)) => {
+                    push_literal(&mut parts, s);
+                    text = true;
+                }
+                Some(Token::Bytes(b)) => bytes.get_or_insert_with(Vec::new).extend(b),
+                Some(Token::FString(fstring)) => {
+                    let fstring = fstring.clone();
+                    for part in self.parse_fstring_parts(&fstring, self.span())? {
+                        match part {
+                            Expr::String(s) => push_literal(&mut parts, &s),
+                            other => parts.push(other),
+                        }
+                    }
+                    text = true;
+                    formatted = true;
+                }
+                _ => break,
             }
+            self.current += 1;
+        }
+        match (text, bytes) {
+            (true, None) if formatted => Ok(Expr::JoinedString(parts)),
+            (true, None) => Ok(parts.pop().unwrap_or(Expr::String(String::new()))),
+            (false, Some(bytes)) => Ok(Expr::Bytes(Rc::new(bytes))),
+            _ => Err(PyError::new(ErrorKind::SyntaxError, "cannot mix bytes and nonbytes literals")
+                .at(start.to(self.previous_span()))),
         }
-        match self.tokens.get(self.current) {
-            Some(Token::Number(value)) => { self.current += 1; Some(Expr::Number(*value)) },
-            Some(Token::Float(value)) => { self.current += 1; Some(Expr::Float(*value)) },
-            Some(Token::StringLiteral(s)) => { self.current += 1; Some(Expr::String(s.clone())) },
-            Some(Token::Identifier(name)) => { self.current += 1; Some(Expr::Variable(name.clone())) },
-            _ => None,
+    }
+
+    // The literal text and formatted fields of an f-string (or of a format
+    // spec within one), parsing each field's expression on its own.
+    fn parse_fstring_parts(&mut self, fstring: &[FStringPart], span: Span) -> Result<Vec<Expr>, PyError> {
+        let mut parts = Vec::new();
+        for part in fstring {
+            match part {
+                FStringPart::Literal(s) => parts.push(Expr::String(s.clone())),
+                FStringPart::Field { tokens, conversion, spec } => {
+                    let mut parser = Parser::new(tokens.clone());
+                    parser.function_depth = self.function_depth;
+                    let value = parser.parse_expression().map_err(|error| fstring_error(error, span))?;
+                    if parser.peek() != Some(&Token::Newline) {
+                        return Err(fstring_error(parser.syntax_error(), span));
+                    }
+                    self.yields |= parser.yields;
+                    let spec = if spec.is_empty() {
+                        None
+                    } else {
+                        Some(Box::new(Expr::JoinedString(self.parse_fstring_parts(spec, span)?)))
+                    };
+                    parts.push(Expr::FormattedValue { value: Box::new(value), conversion: *conversion, spec, span });
+                }
+            }
         }
+        Ok(parts)
     }
 
-    fn parse_block(&mut self) -> Vec<Expr> {
+    fn parse_block(&mut self) -> Result<Vec<Expr>, PyError> {
         let mut statements = Vec::new();
-        while let Some(Token::Newline) = self.tokens.get(self.current) {
+        // A simple statement may follow the ':' on the same line, as in `class Empty: pass`.
+        if !matches!(self.peek(), Some(Token::Newline | Token::Indent) | None) {
+            statements.push(self.parse_statement()?);
+            return Ok(statements);
+        }
+        while let Some(Token::Newline) = self.peek() {
             self.current += 1;
         }
-        if let Some(Token::Indent) = self.tokens.get(self.current) {
+        if let Some(Token::Indent) = self.peek() {
             self.current += 1;
         } else {
-            return statements;
+            return Err(self.error(ErrorKind::IndentationError, "expected an indented block"));
         }
         while self.current < self.tokens.len() {
-            if let Some(Token::Dedent) = self.tokens.get(self.current) {
+            if let Some(Token::Dedent) = self.peek() {
                 self.current += 1;
                 break;
             }
-            if let Some(Token::Newline) = self.tokens.get(self.current) {
+            if let Some(Token::Newline) = self.peek() {
                 self.current += 1;
                 continue;
             }
-            if let Some(statement) = self.parse_statement() {
-                statements.push(statement);
-            } else {
-                self.current += 1;
+            statements.push(self.parse_statement()?);
+        }
+        Ok(statements)
+    }
+}
+
+// Checks that an expression can be assigned to: a name, subscript or
+// attribute, or a tuple or list of targets with at most one starred item.
+fn check_target(target: &Expr, span: Span) -> Result<(), PyError> {
+    let error = |message: &str| Err(PyError::new(ErrorKind::SyntaxError, message).at(span));
+    match target {
+        Expr::Variable(..) | Expr::Subscript { .. } | Expr::Attribute { .. } => Ok(()),
+        Expr::Tuple(elements) | Expr::List(elements) => {
+            if elements.iter().filter(|element| matches!(element, Expr::Starred(..))).count() > 1 {
+                return error("multiple starred expressions in assignment");
             }
+            for element in elements {
+                match element {
+                    Expr::Starred(inner, _) => check_target(inner, span)?,
+                    other => check_target(other, span)?,
+                }
+            }
+            Ok(())
         }
-        statements
+        Expr::Starred(..) => error("starred assignment target must be in a list or tuple"),
+        _ => error("cannot assign to expression"),
+    }
+}
+
+// An augmented assignment updates a single name, subscript or attribute.
+fn check_augmented_target(target: &Expr, span: Span) -> Result<(), PyError> {
+    let description = match target {
+        Expr::Variable(..) | Expr::Subscript { .. } | Expr::Attribute { .. } => return Ok(()),
+        Expr::Tuple(_) => "tuple",
+        Expr::List(_) => "list",
+        Expr::Dict(..) => "dict literal",
+        Expr::Set(..) => "set display",
+        Expr::Starred(..) => "starred",
+        Expr::Call { .. } => "function call",
+        Expr::Compare { .. } => "comparison",
+        Expr::Number(_) | Expr::BigNumber(_) | Expr::Float(_) | Expr::String(_) | Expr::Bytes(_) => "literal",
+        Expr::JoinedString(_) => "f-string expression",
+        Expr::Bool(true) => "True",
+        Expr::Bool(false) => "False",
+        Expr::None => "None",
+        _ => "expression",
+    };
+    let message = format!("'{}' is an illegal expression for augmented assignment", description);
+    Err(PyError::new(ErrorKind::SyntaxError, message).at(span))
+}
+
+fn is_delete_target(target: &Expr) -> bool {
+    match target {
+        Expr::Variable(..) | Expr::Subscript { .. } | Expr::Attribute { .. } => true,
+        Expr::Tuple(elements) | Expr::List(elements) => elements.iter().all(is_delete_target),
+        _ => false,
+    }
+}
+
+// A starred expression on its own (not inside a tuple or list) isn't a value.
+fn check_not_starred(expr: &Expr) -> Result<(), PyError> {
+    if let Expr::Starred(_, starred_span) = expr {
+        return Err(PyError::new(ErrorKind::SyntaxError, "can't use starred expression here").at(*starred_span));
+    }
+    Ok(())
+}
+
+// Appends literal text to the parts of a string, joining it onto any literal
+// text just before it.
+fn push_literal(parts: &mut Vec<Expr>, text: &str) {
+    match parts.last_mut() {
+        Some(Expr::String(last)) => last.push_str(text),
+        _ => parts.push(Expr::String(text.to_string())),
     }
 }
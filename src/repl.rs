@@ -0,0 +1,85 @@
+use crate::ast::{Expr, Value};
+use crate::error::{ErrorKind, PyError, Span};
+use crate::evaluator::{evaluate, execute, Environment};
+use crate::exception;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::special;
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = ">>> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+// Reads one line from stdin after showing a prompt; None means end of input.
+fn read_line(input: &mut impl BufRead, prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+    }
+}
+
+pub fn run_repl() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut env = Environment::new();
+
+    println!("pyrust interactive mode; press Ctrl-D to exit.");
+    loop {
+        let Some(first) = read_line(&mut input, PROMPT) else {
+            println!();
+            break;
+        };
+
+        // Keep reading continuation lines until a blank line closes the block;
+        // blank lines inside a string literal are part of the string.
+        let mut lexer = Lexer::new();
+        let mut result = lexer.lex_line(&first);
+        let mut source = first;
+        while result.is_ok() && lexer.needs_more() {
+            match read_line(&mut input, CONTINUATION_PROMPT) {
+                Some(line) if !line.trim().is_empty() || lexer.in_string() => {
+                    result = lexer.lex_line(&line);
+                    source.push('\n');
+                    source.push_str(&line);
+                }
+                _ => break,
+            }
+        }
+
+        let outcome = match result {
+            Ok(_) => run_input(lexer, &source, &mut env),
+            Err(error) => Err(error.render("<stdin>", &source)),
+        };
+        if let Err(report) = outcome {
+            eprintln!("{}", report);
+        }
+    }
+}
+
+// Runs one input, returning the error report to print if it fails.
+fn run_input(lexer: Lexer, source: &str, env: &mut Environment) -> Result<(), String> {
+    let syntax_error = |error: PyError| error.render("<stdin>", source);
+    let ast = Parser::new(lexer.finish().map_err(syntax_error)?).parse().map_err(syntax_error)?;
+    let stdout = io::stdout();
+    let mut writer = stdout.lock();
+    run_statements(&ast, env, &mut writer).map_err(|error| exception::report(error, env, &mut writer, "<stdin>", source))
+}
+
+fn run_statements<W: Write>(ast: &[Expr], env: &mut Environment, writer: &mut W) -> Result<(), PyError> {
+    for stmt in ast {
+        // Bare expressions echo their repr, like CPython's interactive prompt.
+        if let Expr::Bare(expr, span) = stmt {
+            let value = evaluate(expr, env, writer).map_err(|e| e.at(Span::whole_line(span.line)))?;
+            if !matches!(value, Value::None) {
+                let repr = special::repr(&value, *span, env, writer)?;
+                writeln!(writer, "{}", repr).map_err(|e| PyError::new(ErrorKind::OSError, e.to_string()))?;
+            }
+        } else {
+            execute(stmt, env, writer)?;
+        }
+    }
+    writer.flush().map_err(|e| PyError::new(ErrorKind::OSError, e.to_string()))
+}
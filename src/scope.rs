@@ -0,0 +1,268 @@
+use crate::ast::Expr;
+use crate::error::{ErrorKind, PyError, Span};
+use std::collections::HashSet;
+
+// How the names in a function, lambda, generator expression or class body
+// resolve, worked out from its code as it is parsed.
+#[derive(Debug, Default)]
+pub struct Scope {
+    pub locals: HashSet<String>,  // bound in it, including the parameters
+    pub globals: HashSet<String>, // declared `global`
+    pub cells: HashSet<String>,   // locals that nested functions use, so they are shared
+    pub free: HashSet<String>,    // names it or nested scopes use that enclosing functions may bind
+    // `nonlocal` declarations in it or nested scopes that no function around
+    // them binds yet.
+    nonlocals: Vec<(String, Span)>,
+}
+
+pub enum Kind {
+    Module,
+    Class,
+    Function,
+}
+
+// What a block of code does with names. Nested scopes contribute only what
+// they need from it.
+#[derive(Default)]
+struct Names<'a> {
+    params: &'a [String],
+    bound: HashSet<String>,
+    used: HashSet<String>,
+    globals: Vec<(String, Span)>,
+    nonlocals: Vec<(String, Span)>,
+    nested_free: HashSet<String>,
+    pending: Vec<(String, Span)>,
+}
+
+fn syntax_error(message: String, span: Span) -> PyError {
+    PyError::new(ErrorKind::SyntaxError, message).at(span)
+}
+
+// Analyzes the body of a scope once it's parsed, along with its parameters.
+pub fn analyze(kind: Kind, params: &[String], body: &[Expr]) -> Result<Scope, PyError> {
+    let mut names = Names { params, ..Names::default() };
+    for statement in body {
+        names.visit(statement)?;
+    }
+    // A name declared both ways is reported at whichever declaration came first.
+    for (name, nonlocal_span) in &names.nonlocals {
+        if let Some((_, global_span)) = names.globals.iter().find(|(global, _)| global == name) {
+            let first = match (global_span.line, global_span.start) < (nonlocal_span.line, nonlocal_span.start) {
+                true => global_span,
+                false => nonlocal_span,
+            };
+            return Err(syntax_error(format!("name '{}' is nonlocal and global", name), *first));
+        }
+    }
+    let globals: HashSet<String> = names.globals.into_iter().map(|(name, _)| name).collect();
+    let declared: HashSet<&String> = names.nonlocals.iter().map(|(name, _)| name).collect();
+    let mut scope = Scope::default();
+    match kind {
+        Kind::Module => {
+            if let Some((_, span)) = names.nonlocals.first() {
+                return Err(syntax_error("nonlocal declaration not allowed at module level".to_string(), *span));
+            }
+            if let Some((name, span)) = names.pending.first() {
+                return Err(syntax_error(format!("no binding for nonlocal '{}' found", name), *span));
+            }
+        }
+        // Names bound in a class body are its attributes, which functions
+        // nested in it don't see.
+        Kind::Class => {
+            scope.locals =
+                names.bound.into_iter().filter(|name| !globals.contains(name) && !declared.contains(name)).collect();
+            scope.free =
+                names.used.into_iter().filter(|name| !scope.locals.contains(name) && !globals.contains(name)).collect();
+            scope.free.extend(names.nested_free);
+            scope.free.extend(declared.into_iter().cloned());
+            scope.nonlocals = names.nonlocals;
+            scope.nonlocals.extend(names.pending);
+        }
+        Kind::Function => {
+            scope.locals = params
+                .iter()
+                .chain(&names.bound)
+                .filter(|name| !globals.contains(*name) && !declared.contains(name))
+                .cloned()
+                .collect();
+            scope.free = names
+                .used
+                .into_iter()
+                .chain(names.nested_free.iter().cloned())
+                .filter(|name| !scope.locals.contains(name) && !globals.contains(name))
+                .collect();
+            scope.free.extend(declared.into_iter().cloned());
+            scope.cells = scope.locals.intersection(&names.nested_free).cloned().collect();
+            scope.nonlocals = names.nonlocals;
+            scope.nonlocals.extend(names.pending.into_iter().filter(|(name, _)| !scope.locals.contains(name)));
+        }
+    }
+    scope.globals = globals;
+    Ok(scope)
+}
+
+impl Names<'_> {
+    fn visit_all(&mut self, exprs: &[Expr]) -> Result<(), PyError> {
+        exprs.iter().try_for_each(|expr| self.visit(expr))
+    }
+
+    fn visit_optional(&mut self, expr: &Option<Box<Expr>>) -> Result<(), PyError> {
+        expr.as_deref().map_or(Ok(()), |expr| self.visit(expr))
+    }
+
+    // What a nested function, lambda, generator expression or class needs
+    // from the scopes around it.
+    fn nested(&mut self, scope: &Scope) {
+        self.nested_free.extend(scope.free.iter().cloned());
+        self.pending.extend(scope.nonlocals.iter().cloned());
+    }
+
+    // An assignment, `for` or `del` target: names in it are bound, while the
+    // parts of subscripts and attributes are evaluated.
+    fn target(&mut self, target: &Expr) -> Result<(), PyError> {
+        match target {
+            Expr::Variable(name, _) => {
+                self.bound.insert(name.clone());
+                Ok(())
+            }
+            Expr::Tuple(elements) | Expr::List(elements) => {
+                elements.iter().try_for_each(|element| self.target(element))
+            }
+            Expr::Starred(target, _) => self.target(target),
+            other => self.visit(other),
+        }
+    }
+
+    // `global` or `nonlocal` must come before any other use of the name in
+    // the scope, and can't name a parameter.
+    fn declare(&mut self, names: &[String], global: bool, span: Span) -> Result<(), PyError> {
+        let declaration = if global { "global" } else { "nonlocal" };
+        for name in names {
+            let problem = if self.params.contains(name) {
+                format!("name '{}' is parameter and {}", name, declaration)
+            } else if self.used.contains(name) {
+                format!("name '{}' is used prior to {} declaration", name, declaration)
+            } else if self.bound.contains(name) {
+                format!("name '{}' is assigned to before {} declaration", name, declaration)
+            } else {
+                let declared = if global { &mut self.globals } else { &mut self.nonlocals };
+                declared.push((name.clone(), span));
+                continue;
+            };
+            return Err(syntax_error(problem, span));
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, expr: &Expr) -> Result<(), PyError> {
+        match expr {
+            Expr::Number(_) | Expr::BigNumber(_) | Expr::Float(_) | Expr::String(_) | Expr::Bytes(_) => {}
+            Expr::Bool(_) | Expr::None | Expr::Pass | Expr::Break | Expr::Continue => {}
+            Expr::Variable(name, _) => {
+                self.used.insert(name.clone());
+            }
+            Expr::JoinedString(elements) | Expr::List(elements) | Expr::Tuple(elements) | Expr::Set(elements, _) => {
+                self.visit_all(elements)?
+            }
+            Expr::Print(elements, _) => self.visit_all(elements)?,
+            Expr::FormattedValue { value, spec, .. } => {
+                self.visit(value)?;
+                self.visit_optional(spec)?;
+            }
+            Expr::Starred(value, _) | Expr::Not(value, _) | Expr::Bare(value, _) => self.visit(value)?,
+            Expr::Dict(pairs, _) => {
+                for (key, value) in pairs {
+                    self.visit(key)?;
+                    self.visit(value)?;
+                }
+            }
+            Expr::Assignment(target, value, _) => {
+                self.target(target)?;
+                self.visit(value)?;
+            }
+            // An augmented assignment to a name reads it too, but as a local.
+            Expr::AugmentedAssignment { target, value, .. } => {
+                self.target(target)?;
+                self.visit(value)?;
+            }
+            Expr::Subscript { value, index, .. } => {
+                self.visit(value)?;
+                self.visit(index)?;
+            }
+            Expr::Slice { lower, upper, step } => {
+                self.visit_optional(lower)?;
+                self.visit_optional(upper)?;
+                self.visit_optional(step)?;
+            }
+            Expr::Attribute { value, .. } | Expr::UnaryOp { operand: value, .. } => self.visit(value)?,
+            Expr::Arithmetic { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.visit(left)?;
+                self.visit(right)?;
+            }
+            Expr::Compare { left, comparators, .. } => {
+                self.visit(left)?;
+                self.visit_all(comparators)?;
+            }
+            Expr::FunctionDef { name, scope, .. } => {
+                self.bound.insert(name.clone());
+                self.nested(scope);
+            }
+            Expr::ClassDef { name, bases, scope, .. } => {
+                self.visit_all(bases)?;
+                self.bound.insert(name.clone());
+                self.nested(scope);
+            }
+            Expr::Lambda { scope, .. } => self.nested(scope),
+            // The outermost iterable is evaluated where the expression is.
+            Expr::GeneratorExp { iterable, scope, .. } => {
+                self.visit(iterable)?;
+                self.nested(scope);
+            }
+            Expr::Call { function, args, keywords, .. } => {
+                self.visit(function)?;
+                self.visit_all(args)?;
+                for (_, arg) in keywords {
+                    self.visit(arg)?;
+                }
+            }
+            Expr::IfCondition { condition, body, orelse, .. } | Expr::WhileLoop { condition, body, orelse, .. } => {
+                self.visit(condition)?;
+                self.visit_all(body)?;
+                self.visit_all(orelse)?;
+            }
+            Expr::ForLoop { target, iterable, body, orelse, .. } => {
+                self.target(target)?;
+                self.visit(iterable)?;
+                self.visit_all(body)?;
+                self.visit_all(orelse)?;
+            }
+            Expr::Return(value, _) | Expr::Yield(value, _) => self.visit_optional(value)?,
+            Expr::YieldFrom(iterable, _) => self.visit(iterable)?,
+            Expr::Import(name, _) => {
+                self.bound.insert(name.clone());
+            }
+            Expr::Delete(targets, _) => targets.iter().try_for_each(|target| self.target(target))?,
+            Expr::Try { body, handlers, orelse, finalbody } => {
+                self.visit_all(body)?;
+                for handler in handlers {
+                    if let Some(types) = &handler.types {
+                        self.visit(types)?;
+                    }
+                    if let Some(name) = &handler.name {
+                        self.bound.insert(name.clone());
+                    }
+                    self.visit_all(&handler.body)?;
+                }
+                self.visit_all(orelse)?;
+                self.visit_all(finalbody)?;
+            }
+            Expr::Raise { exception, cause, .. } => {
+                self.visit_optional(exception)?;
+                self.visit_optional(cause)?;
+            }
+            Expr::Global(names, span) => self.declare(names, true, *span)?,
+            Expr::Nonlocal(names, span) => self.declare(names, false, *span)?,
+        }
+        Ok(())
+    }
+}
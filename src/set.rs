@@ -0,0 +1,340 @@
+use crate::ast::Value;
+use crate::error::PyError;
+use crate::operators::{hash_value, sequence_items, values_equal};
+use std::mem;
+
+const MIN_SIZE: usize = 8;
+const LINEAR_PROBES: usize = 9;
+const PERTURB_SHIFT: u32 = 5;
+
+#[derive(Debug, Clone)]
+enum Slot {
+    Empty,
+    Dummy, // a removed entry; probing continues past it
+    Active(i64, Value),
+}
+
+// A hash set laid out the way CPython lays out sets: open addressing in a
+// power-of-two table, probing a few neighbouring slots before jumping by a
+// perturbed step. Iteration walks the table, so sets print in the same order
+// as in CPython (small ints come out sorted, for instance).
+#[derive(Debug, Clone)]
+pub struct Set {
+    table: Vec<Slot>,
+    fill: usize, // active plus dummy slots
+    used: usize, // active slots
+    finger: usize, // where pop() resumes its search
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Set {
+    pub fn new() -> Self {
+        Self { table: vec![Slot::Empty; MIN_SIZE], fill: 0, used: 0, finger: 0 }
+    }
+
+    // A new set holding the items of any iterable.
+    pub fn from_value(value: &Value) -> Result<Self, PyError> {
+        let mut set = Set::new();
+        set.update(value)?;
+        Ok(set)
+    }
+
+    pub fn len(&self) -> usize {
+        self.used
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used == 0
+    }
+
+    fn mask(&self) -> usize {
+        self.table.len() - 1
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value> {
+        self.entries().map(|(_, key)| key)
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (i64, &Value)> {
+        self.table.iter().filter_map(|slot| match slot {
+            Slot::Active(hash, key) => Some((*hash, key)),
+            _ => None,
+        })
+    }
+
+    pub fn items(&self) -> Vec<Value> {
+        self.iter().cloned().collect()
+    }
+
+    // The probe sequence for a hash: runs of LINEAR_PROBES + 1 adjacent slots
+    // (when they fit in the table), each run starting at a perturbed jump.
+    fn probe(&self, hash: i64, mut visit: impl FnMut(usize) -> bool) {
+        let mask = self.mask();
+        let mut perturb = hash as u64;
+        let mut i = hash as usize & mask;
+        loop {
+            let probes = if i + LINEAR_PROBES <= mask { LINEAR_PROBES } else { 0 };
+            for j in i..=i + probes {
+                if visit(j) {
+                    return;
+                }
+            }
+            perturb >>= PERTURB_SHIFT;
+            i = i.wrapping_mul(5).wrapping_add(1).wrapping_add(perturb as usize) & mask;
+        }
+    }
+
+    fn find(&self, key: &Value, hash: i64) -> Option<usize> {
+        let mut found = None;
+        self.probe(hash, |j| match &self.table[j] {
+            Slot::Empty => true,
+            Slot::Active(existing_hash, existing) if *existing_hash == hash && values_equal(existing, key) => {
+                found = Some(j);
+                true
+            }
+            _ => false,
+        });
+        found
+    }
+
+    pub fn contains(&self, key: &Value) -> Result<bool, PyError> {
+        Ok(self.find(key, hash_value(key)?).is_some())
+    }
+
+    pub fn add(&mut self, key: Value) -> Result<(), PyError> {
+        let hash = hash_value(&key)?;
+        self.add_entry(key, hash);
+        Ok(())
+    }
+
+    fn add_entry(&mut self, key: Value, hash: i64) {
+        if self.find(&key, hash).is_none() {
+            self.place(key, hash);
+        }
+    }
+
+    // The keys stored under a hash, with their slots, for callers that
+    // compare keys through `__eq__` (which may change the set meanwhile).
+    pub fn candidates(&self, hash: i64) -> Vec<(usize, Value)> {
+        let mut candidates = Vec::new();
+        self.probe(hash, |j| match &self.table[j] {
+            Slot::Empty => true,
+            Slot::Active(existing_hash, existing) if *existing_hash == hash => {
+                candidates.push((j, existing.clone()));
+                false
+            }
+            _ => false,
+        });
+        candidates
+    }
+
+    // Adds a key known to be absent: into the first empty slot of its probe
+    // sequence, or a dummy seen on the way there.
+    pub fn place(&mut self, key: Value, hash: i64) {
+        let mut empty = 0;
+        let mut free_slot = None;
+        self.probe(hash, |j| match &self.table[j] {
+            Slot::Empty => {
+                empty = j;
+                true
+            }
+            Slot::Active(..) => false,
+            Slot::Dummy => {
+                free_slot = Some(j);
+                false
+            }
+        });
+        if let Some(free_slot) = free_slot {
+            self.table[free_slot] = Slot::Active(hash, key);
+            self.used += 1;
+            return;
+        }
+        self.table[empty] = Slot::Active(hash, key);
+        self.fill += 1;
+        self.used += 1;
+        if self.fill * 5 >= self.mask() * 3 {
+            self.resize(if self.used > 50000 { self.used * 2 } else { self.used * 4 });
+        }
+    }
+
+    pub fn remove_at(&mut self, position: usize) {
+        self.table[position] = Slot::Dummy;
+        self.used -= 1;
+    }
+
+    // Removes an arbitrary item, scanning on from where the last pop stopped.
+    pub fn pop(&mut self) -> Option<Value> {
+        if self.used == 0 {
+            return None;
+        }
+        let mut i = self.finger & self.mask();
+        while !matches!(self.table[i], Slot::Active(..)) {
+            i = (i + 1) & self.mask();
+        }
+        let Slot::Active(_, key) = mem::replace(&mut self.table[i], Slot::Dummy) else { unreachable!() };
+        self.used -= 1;
+        self.finger = i + 1;
+        Some(key)
+    }
+
+    // Rebuilds the table with room for `min_used` items, dropping dummies.
+    fn resize(&mut self, min_used: usize) {
+        let mut size = MIN_SIZE;
+        while size <= min_used {
+            size <<= 1;
+        }
+        let old = mem::replace(&mut self.table, vec![Slot::Empty; size]);
+        self.fill = self.used;
+        for slot in old {
+            if let Slot::Active(hash, key) = slot {
+                self.insert_clean(key, hash);
+            }
+        }
+    }
+
+    // Inserts a key known to be absent into a table without dummies.
+    fn insert_clean(&mut self, key: Value, hash: i64) {
+        let mut target = 0;
+        self.probe(hash, |j| {
+            target = j;
+            matches!(self.table[j], Slot::Empty)
+        });
+        self.table[target] = Slot::Active(hash, key);
+    }
+
+    // Adds every item of another set, copying its layout when this one is empty.
+    pub fn merge(&mut self, other: &Set) {
+        if other.used == 0 {
+            return;
+        }
+        if (self.fill + other.used) * 5 >= self.mask() * 3 {
+            self.resize((self.used + other.used) * 2);
+        }
+        if self.fill == 0 && self.mask() == other.mask() && other.fill == other.used {
+            self.table = other.table.clone();
+            self.fill = other.fill;
+            self.used = other.used;
+            return;
+        }
+        if self.fill == 0 {
+            self.fill = other.used;
+            self.used = other.used;
+            for (hash, key) in other.entries() {
+                self.insert_clean(key.clone(), hash);
+            }
+            return;
+        }
+        for (hash, key) in other.entries() {
+            self.add_entry(key.clone(), hash);
+        }
+    }
+
+    // Adds the items of any iterable.
+    pub fn update(&mut self, value: &Value) -> Result<(), PyError> {
+        match value {
+            Value::Set(other) => {
+                let other = other.borrow().clone();
+                self.merge(&other);
+            }
+            Value::FrozenSet(other) => self.merge(other),
+            Value::Dict(dict) => {
+                let keys = dict.borrow().keys();
+                if (self.fill + keys.len()) * 5 >= self.mask() * 3 {
+                    self.resize((self.used + keys.len()) * 2);
+                }
+                for key in keys {
+                    self.add(key)?;
+                }
+            }
+            _ => {
+                for item in sequence_items(value)? {
+                    self.add(item)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn union(&self, other: &Set) -> Set {
+        let mut result = Set::new();
+        result.merge(self);
+        result.merge(other);
+        result
+    }
+
+    // Walks the smaller set, keeping the items the larger one also has.
+    pub fn intersection(&self, other: &Set) -> Set {
+        let (larger, smaller) = if other.len() > self.len() { (other, self) } else { (self, other) };
+        let mut result = Set::new();
+        for (hash, key) in smaller.entries() {
+            if larger.find(key, hash).is_some() {
+                result.add_entry(key.clone(), hash);
+            }
+        }
+        result
+    }
+
+    pub fn difference(&self, other: &Set) -> Set {
+        // When this set is much larger, copying it and removing the other's
+        // items is cheaper than rebuilding it.
+        if self.len() >> 2 > other.len() {
+            let mut result = Set::new();
+            result.merge(self);
+            result.difference_update(other);
+            return result;
+        }
+        let mut result = Set::new();
+        for (hash, key) in self.entries() {
+            if other.find(key, hash).is_none() {
+                result.add_entry(key.clone(), hash);
+            }
+        }
+        result
+    }
+
+    // Removes the other set's items from this one, in place.
+    pub fn difference_update(&mut self, other: &Set) {
+        for (hash, key) in other.entries() {
+            if let Some(position) = self.find(key, hash) {
+                self.remove_at(position);
+            }
+        }
+    }
+
+    // Starts from a copy of the other set and toggles each of this set's items.
+    pub fn symmetric_difference(&self, other: &Set) -> Set {
+        let mut result = Set::new();
+        result.merge(other);
+        result.symmetric_difference_update(self);
+        result
+    }
+
+    // Toggles each of the other set's items in this one, in place.
+    pub fn symmetric_difference_update(&mut self, other: &Set) {
+        for (hash, key) in other.entries() {
+            match self.find(key, hash) {
+                Some(position) => self.remove_at(position),
+                None => self.add_entry(key.clone(), hash),
+            }
+        }
+    }
+
+    pub fn is_subset(&self, other: &Set) -> bool {
+        self.len() <= other.len() && self.entries().all(|(hash, key)| other.find(key, hash).is_some())
+    }
+
+    // CPython's frozenset hash: order-independent, mixing each item's hash.
+    pub fn hash(&self) -> i64 {
+        let shuffle = |h: u64| ((h ^ 89869747) ^ (h << 16)).wrapping_mul(3644798167);
+        let mut hash = self.entries().fold(0u64, |acc, (h, _)| acc ^ shuffle(h as u64));
+        hash ^= (self.used as u64 + 1).wrapping_mul(1927868237);
+        hash ^= (hash >> 11) ^ (hash >> 25);
+        hash = hash.wrapping_mul(69069).wrapping_add(907133923);
+        if hash == u64::MAX { 590923713 } else { hash as i64 }
+    }
+}
@@ -0,0 +1,954 @@
+use crate::ast::{Function, Value, ViewKind};
+use crate::builtins;
+use crate::class::Class;
+use crate::dict::Dict;
+use crate::error::{ErrorKind, PyError, Span};
+use crate::exception;
+use crate::evaluator::{call_function, Environment};
+use crate::format::{escape_ascii, format_value};
+use crate::generator::{self, Resume};
+use crate::iter::{iterate as iterate_builtin, Iter};
+use crate::operators::{self, hash_value, key_error, sequence_items};
+use crate::set::Set;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+// The special methods behind a binary operator: the method itself, the
+// reflected form tried on the right operand, and the in-place form.
+fn operator_methods(operator: &str) -> Option<(&'static str, &'static str, &'static str)> {
+    let methods = match operator {
+        "+" => ("__add__", "__radd__", "__iadd__"),
+        "-" => ("__sub__", "__rsub__", "__isub__"),
+        "*" => ("__mul__", "__rmul__", "__imul__"),
+        "/" => ("__truediv__", "__rtruediv__", "__itruediv__"),
+        "//" => ("__floordiv__", "__rfloordiv__", "__ifloordiv__"),
+        "%" => ("__mod__", "__rmod__", "__imod__"),
+        "**" => ("__pow__", "__rpow__", "__ipow__"),
+        "<<" => ("__lshift__", "__rlshift__", "__ilshift__"),
+        ">>" => ("__rshift__", "__rrshift__", "__irshift__"),
+        "&" => ("__and__", "__rand__", "__iand__"),
+        "|" => ("__or__", "__ror__", "__ior__"),
+        "^" => ("__xor__", "__rxor__", "__ixor__"),
+        _ => return None,
+    };
+    Some(methods)
+}
+
+// A rich comparison's method and the one tried on the right operand instead.
+fn comparison_methods(operator: &str) -> (&'static str, &'static str) {
+    match operator {
+        "==" => ("__eq__", "__eq__"),
+        "!=" => ("__ne__", "__ne__"),
+        "<" => ("__lt__", "__gt__"),
+        "<=" => ("__le__", "__ge__"),
+        ">" => ("__gt__", "__lt__"),
+        _ => ("__ge__", "__le__"),
+    }
+}
+
+fn is_instance(value: &Value) -> bool {
+    matches!(value, Value::Instance(_))
+}
+
+// Calls a special method, which Python looks up on the class rather than the
+// instance; None when the value doesn't define it.
+fn call_special<W: Write>(
+    value: &Value,
+    name: &str,
+    args: Vec<Value>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<Value>, PyError> {
+    let Value::Instance(instance) = value else { return Ok(None) };
+    let Some(method) = instance.class.lookup(name) else { return Ok(None) };
+    let result = match method {
+        Value::Function(function) => call_method(&function, value, args, Vec::new(), span, env, writer)?,
+        other => call_function(&other, args, Vec::new(), span, env, writer)?,
+    };
+    Ok(Some(result))
+}
+
+fn call_method<W: Write>(
+    function: &Rc<Function>,
+    receiver: &Value,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let mut method_args = Vec::with_capacity(args.len() + 1);
+    method_args.push(receiver.clone());
+    method_args.extend(args);
+    call_function(&Value::Function(Rc::clone(function)), method_args, kwargs, span, env, writer)
+}
+
+// The result of a special method, unless it returned NotImplemented.
+fn implemented(result: Option<Value>) -> Option<Value> {
+    result.filter(|value| !matches!(value, Value::NotImplemented))
+}
+
+fn same_class(left: &Value, right: &Value) -> bool {
+    matches!((left, right), (Value::Instance(l), Value::Instance(r)) if Rc::ptr_eq(&l.class, &r.class))
+}
+
+// Whether the right operand is an instance of a proper subclass of the left
+// operand's class, whose methods then get the first try.
+fn right_is_subclass(left: &Value, right: &Value) -> Option<(Rc<Class>, Rc<Class>)> {
+    match (left, right) {
+        (Value::Instance(l), Value::Instance(r)) if !Rc::ptr_eq(&l.class, &r.class) && r.class.is_subclass(&l.class) => {
+            Some((Rc::clone(&l.class), Rc::clone(&r.class)))
+        }
+        _ => None,
+    }
+}
+
+fn overrides(class: &Class, base: &Class, name: &str) -> bool {
+    match (class.lookup(name), base.lookup(name)) {
+        (Some(Value::Function(method)), Some(Value::Function(inherited))) => !Rc::ptr_eq(&method, &inherited),
+        (method, _) => method.is_some(),
+    }
+}
+
+// Tries `left.__op__(right)`, then `right.__rop__(left)` (first, when the
+// right operand's class derives from the left's and overrides it). None if
+// neither operand handles the operation.
+fn dispatch_binary<W: Write>(
+    operator: &str,
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<Value>, PyError> {
+    if !is_instance(left) && !is_instance(right) {
+        return Ok(None);
+    }
+    let Some((method, reflected, _)) = operator_methods(operator) else { return Ok(None) };
+    let right_first = right_is_subclass(left, right).is_some_and(|(base, class)| overrides(&class, &base, reflected));
+    if right_first {
+        if let Some(result) = implemented(call_special(right, reflected, vec![left.clone()], span, env, writer)?) {
+            return Ok(Some(result));
+        }
+    }
+    if let Some(result) = implemented(call_special(left, method, vec![right.clone()], span, env, writer)?) {
+        return Ok(Some(result));
+    }
+    if !right_first && !same_class(left, right) {
+        return Ok(implemented(call_special(right, reflected, vec![left.clone()], span, env, writer)?));
+    }
+    Ok(None)
+}
+
+pub fn binary_op<W: Write>(
+    operator: &str,
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if let Some(result) = dispatch_binary(operator, left, right, span, env, writer)? {
+        return Ok(result);
+    }
+    if is_set_operation(operator, left, right) {
+        let result = set_operation(operator, left, right, span, env, writer)?;
+        return Ok(match left {
+            Value::FrozenSet(_) => Value::FrozenSet(Rc::new(result)),
+            _ => Value::new_set(result),
+        });
+    }
+    operators::binary_op(operator, left, right).map_err(|e| e.at(span))
+}
+
+// `| & - ^` between sets, at least one holding instances.
+fn is_set_operation(operator: &str, left: &Value, right: &Value) -> bool {
+    matches!(operator, "|" | "&" | "-" | "^")
+        && matches!(left, Value::Set(_) | Value::FrozenSet(_))
+        && matches!(right, Value::Set(_) | Value::FrozenSet(_))
+        && (holds_instances(left) || holds_instances(right))
+}
+
+// `left op= right` tries `__iop__` before falling back to the binary operator.
+pub fn inplace_op<W: Write>(
+    operator: &str,
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let base = operator.strip_suffix('=').unwrap_or(operator);
+    if let Some((_, _, inplace)) = operator_methods(base) {
+        if let Some(result) = implemented(call_special(left, inplace, vec![right.clone()], span, env, writer)?) {
+            return Ok(result);
+        }
+    }
+    if let Some(result) = dispatch_binary(base, left, right, span, env, writer)? {
+        return Ok(result);
+    }
+    if let (Value::List(list), "+") = (left, base) {
+        // Collected before borrowing, since a list may be added to itself.
+        let extra = items(right, span, env, writer)?;
+        list.borrow_mut().extend(extra);
+        return Ok(left.clone());
+    }
+    if let (Value::Set(set), true) = (left, is_set_operation(base, left, right)) {
+        let result = set_operation(base, left, right, span, env, writer)?;
+        *set.borrow_mut() = result;
+        return Ok(left.clone());
+    }
+    operators::inplace_op(operator, left, right).map_err(|e| e.at(span))
+}
+
+pub fn unary_op<W: Write>(
+    operator: &str,
+    value: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let method = match operator {
+        "-" => "__neg__",
+        "+" => "__pos__",
+        _ => "__invert__",
+    };
+    match call_special(value, method, Vec::new(), span, env, writer)? {
+        Some(result) => Ok(result),
+        None => operators::unary_op(operator, value).map_err(|e| e.at(span)),
+    }
+}
+
+// One comparison of a chain. Rich comparisons give whatever the special
+// method returns, which need not be a bool.
+pub fn compare<W: Write>(
+    operator: &str,
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    match operator {
+        "in" => Ok(Value::Bool(contains(right, left, span, env, writer)?)),
+        "not in" => Ok(Value::Bool(!contains(right, left, span, env, writer)?)),
+        "is" | "is not" => Ok(Value::Bool(operators::compare_values(operator, left, right)?)),
+        _ => rich_compare(operator, left, right, span, env, writer),
+    }
+}
+
+fn rich_compare<W: Write>(
+    operator: &str,
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if is_instance(left) || is_instance(right) {
+        let (method, reflected) = comparison_methods(operator);
+        let right_first = right_is_subclass(left, right).is_some();
+        if right_first {
+            if let Some(result) = implemented(call_comparison(right, reflected, left, span, env, writer)?) {
+                return Ok(result);
+            }
+        }
+        if let Some(result) = implemented(call_comparison(left, method, right, span, env, writer)?) {
+            return Ok(result);
+        }
+        if !right_first {
+            if let Some(result) = implemented(call_comparison(right, reflected, left, span, env, writer)?) {
+                return Ok(result);
+            }
+        }
+    } else if let (Value::List(_), Value::List(_)) | (Value::Tuple(_), Value::Tuple(_)) = (left, right) {
+        if holds_instances(left) || holds_instances(right) {
+            let (left, right) = (sequence_items(left)?, sequence_items(right)?);
+            return compare_sequences(operator, &left, &right, span, env, writer);
+        }
+    } else if let (Value::Set(_) | Value::FrozenSet(_), Value::Set(_) | Value::FrozenSet(_)) = (left, right) {
+        if holds_instances(left) || holds_instances(right) {
+            return compare_sets(operator, left, right, span, env, writer);
+        }
+    } else if let (Value::Dict(l), Value::Dict(r)) = (left, right) {
+        if (operator == "==" || operator == "!=") && (holds_instances(left) || holds_instances(right)) {
+            let equal = dicts_equal(l, r, span, env, writer)?;
+            return Ok(Value::Bool(equal == (operator == "==")));
+        }
+    }
+    // Without special methods, == and != fall back to identity and ordering
+    // is unsupported, which the built-in comparison handles.
+    Ok(Value::Bool(operators::compare_values(operator, left, right).map_err(|e| e.at(span))?))
+}
+
+// A comparison method; a class without `__ne__` gets the inverse of its `__eq__`.
+fn call_comparison<W: Write>(
+    value: &Value,
+    method: &str,
+    other: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<Value>, PyError> {
+    if let Value::Instance(instance) = value {
+        if method == "__ne__" && instance.class.lookup("__ne__").is_none() {
+            return match implemented(call_special(value, "__eq__", vec![other.clone()], span, env, writer)?) {
+                Some(equal) => Ok(Some(Value::Bool(!truthy(&equal, span, env, writer)?))),
+                None => Ok(None),
+            };
+        }
+    }
+    call_special(value, method, vec![other.clone()], span, env, writer)
+}
+
+fn holds_instances(value: &Value) -> bool {
+    match value {
+        Value::Instance(_) => true,
+        Value::List(items) => items.borrow().iter().any(holds_instances),
+        Value::Tuple(items) => items.iter().any(holds_instances),
+        Value::Set(items) => items.borrow().iter().any(holds_instances),
+        Value::FrozenSet(items) => items.iter().any(holds_instances),
+        Value::Dict(dict) => dict.borrow().iter().any(|(key, value)| holds_instances(key) || holds_instances(value)),
+        _ => false,
+    }
+}
+
+// Sequences are ordered by their first differing items, or else by length.
+fn compare_sequences<W: Write>(
+    operator: &str,
+    left: &[Value],
+    right: &[Value],
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if left.len() != right.len() && (operator == "==" || operator == "!=") {
+        return Ok(Value::Bool(operator == "!="));
+    }
+    for (l, r) in left.iter().zip(right) {
+        if !equal(l, r, span, env, writer)? {
+            return match operator {
+                "==" => Ok(Value::Bool(false)),
+                "!=" => Ok(Value::Bool(true)),
+                _ => rich_compare(operator, l, r, span, env, writer),
+            };
+        }
+    }
+    let lengths = (Value::Int(left.len() as i64), Value::Int(right.len() as i64));
+    Ok(Value::Bool(operators::compare_values(operator, &lengths.0, &lengths.1)?))
+}
+
+// Sets are equal when they hold equal items, and ordered by inclusion.
+fn compare_sets<W: Write>(
+    operator: &str,
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let (l, r) = (builtins::len(left)?, builtins::len(right)?);
+    let result = match operator {
+        "==" => l == r && is_subset(left, right, span, env, writer)?,
+        "!=" => l != r || !is_subset(left, right, span, env, writer)?,
+        "<" => l < r && is_subset(left, right, span, env, writer)?,
+        "<=" => is_subset(left, right, span, env, writer)?,
+        ">" => r < l && is_subset(right, left, span, env, writer)?,
+        _ => is_subset(right, left, span, env, writer)?,
+    };
+    Ok(Value::Bool(result))
+}
+
+// Dicts are equal when they hold the same keys mapped to equal values.
+fn dicts_equal<W: Write>(
+    left: &Rc<RefCell<Dict>>,
+    right: &Rc<RefCell<Dict>>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<bool, PyError> {
+    if Rc::ptr_eq(left, right) {
+        return Ok(true);
+    }
+    if left.borrow().len() != right.borrow().len() {
+        return Ok(false);
+    }
+    let entries: Vec<_> = left.borrow().iter().map(|(key, value)| (key.clone(), value.clone())).collect();
+    for (key, value) in entries {
+        match dict_get(right, &key, span, env, writer)? {
+            Some(other) if equal(&value, &other, span, env, writer)? => {}
+            _ => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+// Equality as containers test it: identical objects are always equal.
+pub fn equal<W: Write>(
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<bool, PyError> {
+    if operators::compare_values("is", left, right)? {
+        return Ok(true);
+    }
+    let result = rich_compare("==", left, right, span, env, writer)?;
+    truthy(&result, span, env, writer)
+}
+
+// `item in container`: `__contains__`, or else a search of what iterating it produces.
+fn contains<W: Write>(
+    container: &Value,
+    item: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<bool, PyError> {
+    let searchable = match container {
+        Value::Instance(instance) => {
+            if let Some(result) = call_special(container, "__contains__", vec![item.clone()], span, env, writer)? {
+                return truthy(&result, span, env, writer);
+            }
+            instance.class.lookup("__iter__").is_some() || instance.class.lookup("__getitem__").is_some()
+        }
+        Value::List(_) | Value::Tuple(_) => holds_instances(item) || holds_instances(container),
+        Value::Dict(dict) | Value::DictView(dict, ViewKind::Keys) => {
+            return Ok(dict_get(dict, item, span, env, writer)?.is_some())
+        }
+        Value::Set(_) | Value::FrozenSet(_) => return set_contains(container, item, span, env, writer),
+        Value::Generator(_) => true,
+        _ => false,
+    };
+    if !searchable {
+        return operators::compare_values("in", item, container).map_err(|e| e.at(span));
+    }
+    let iterator = iterate(container, span, env, writer)?;
+    while let Some(candidate) = iterator.next(span, env, writer)? {
+        if equal(&candidate, item, span, env, writer)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Truthiness: `__bool__`, or else whether `__len__` is nonzero.
+pub fn truthy<W: Write>(value: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<bool, PyError> {
+    let Value::Instance(instance) = value else { return Ok(value.is_truthy()) };
+    if let Some(result) = call_special(value, "__bool__", Vec::new(), span, env, writer)? {
+        return match result {
+            Value::Bool(b) => Ok(b),
+            other => Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("__bool__ should return bool, returned {}", other.type_name()),
+            )
+            .at(span)),
+        };
+    }
+    if instance.class.lookup("__len__").is_some() {
+        return Ok(length(value, span, env, writer)? > 0);
+    }
+    Ok(true)
+}
+
+// len(value), which for an instance is what its `__len__` returns.
+pub fn length<W: Write>(value: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<usize, PyError> {
+    let Some(result) = call_special(value, "__len__", Vec::new(), span, env, writer)? else {
+        return builtins::len(value);
+    };
+    match result.as_int() {
+        Some(n) if n < 0 => Err(PyError::new(ErrorKind::ValueError, "__len__() should return >= 0")),
+        Some(n) => Ok(n as usize),
+        None if result.is_int() => Err(PyError::new(
+            ErrorKind::OverflowError,
+            "cannot fit 'int' into an index-sized integer",
+        )),
+        None => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("'{}' object cannot be interpreted as an integer", result.type_name()),
+        )),
+    }
+}
+
+pub fn get_item<W: Write>(
+    container: &Value,
+    index: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    if let Value::Dict(dict) = container {
+        return dict_get(dict, index, span, env, writer)?.ok_or_else(|| key_error(index).at(span));
+    }
+    match call_special(container, "__getitem__", vec![index.clone()], span, env, writer)? {
+        Some(item) => Ok(item),
+        None => operators::get_item(container, index).map_err(|e| e.at(span)),
+    }
+}
+
+pub fn set_item<W: Write>(
+    container: &Value,
+    index: &Value,
+    value: Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<(), PyError> {
+    if let Value::Dict(dict) = container {
+        return dict_insert(dict, index.clone(), value, span, env, writer);
+    }
+    if let (Value::List(_), Value::Slice(_)) = (container, index) {
+        // Collected here, since a generator or an iterator object runs Python code.
+        let replacement = items(&value, span, env, writer).map_err(|error| match error.kind {
+            ErrorKind::TypeError => PyError::new(ErrorKind::TypeError, "can only assign an iterable").at(span),
+            _ => error,
+        })?;
+        return operators::set_item(container, index, Value::new_list(replacement)).map_err(|e| e.at(span));
+    }
+    let args = vec![index.clone(), value.clone()];
+    match call_special(container, "__setitem__", args, span, env, writer)? {
+        Some(_) => Ok(()),
+        None => operators::set_item(container, index, value).map_err(|e| e.at(span)),
+    }
+}
+
+pub fn delete_item<W: Write>(
+    container: &Value,
+    index: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<(), PyError> {
+    if let Value::Dict(dict) = container {
+        return match dict_remove(dict, index, span, env, writer)? {
+            Some(_) => Ok(()),
+            None => Err(key_error(index).at(span)),
+        };
+    }
+    match call_special(container, "__delitem__", vec![index.clone()], span, env, writer)? {
+        Some(_) => Ok(()),
+        None => operators::delete_item(container, index).map_err(|e| e.at(span)),
+    }
+}
+
+// An iterator being walked: a built-in one, or an object with `__next__`.
+pub enum Iteration {
+    Builtin(Rc<RefCell<Iter>>),
+    Object(Value),
+}
+
+impl Iteration {
+    pub fn next<W: Write>(&self, span: Span, env: &mut Environment, writer: &mut W) -> Result<Option<Value>, PyError> {
+        match self {
+            Iteration::Builtin(iterator) => next_builtin(iterator, span, env, writer),
+            Iteration::Object(iterator) => match next(iterator, span, env, writer) {
+                Ok(item) => Ok(Some(item)),
+                Err(error) if error.kind == ErrorKind::StopIteration => Ok(None),
+                Err(error) => Err(error),
+            },
+        }
+    }
+}
+
+// Advances a built-in iterator, indexing the object it walks when that has
+// `__getitem__`; an IndexError ends the iteration.
+fn next_builtin<W: Write>(
+    iterator: &Rc<RefCell<Iter>>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<Value>, PyError> {
+    // Released before returning, since the caller may use the iterator itself.
+    let next_index = iterator.borrow_mut().next_index();
+    let Some((object, index)) = next_index else { return iterator.borrow_mut().next() };
+    match get_item(&object, &Value::Int(index as i64), span, env, writer) {
+        Ok(item) => Ok(Some(item)),
+        Err(error) if matches!(error.kind, ErrorKind::IndexError | ErrorKind::StopIteration) => {
+            iterator.borrow_mut().finish();
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+// iter(value): the iterator `__iter__` returns, or a built-in one, which for
+// an object with only `__getitem__` indexes it.
+pub fn iter<W: Write>(value: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<Value, PyError> {
+    // A generator is its own iterator.
+    if let Value::Generator(_) = value {
+        return Ok(value.clone());
+    }
+    let Some(iterator) = call_special(value, "__iter__", Vec::new(), span, env, writer)? else {
+        if let Value::Instance(instance) = value {
+            if instance.class.lookup("__getitem__").is_some() {
+                return Ok(Value::Iterator(Rc::new(RefCell::new(Iter::indexed(value.clone())))));
+            }
+        }
+        return Ok(Value::Iterator(iterate_builtin(value)?));
+    };
+    match &iterator {
+        Value::Iterator(_) => Ok(iterator),
+        Value::Instance(instance) if instance.class.lookup("__next__").is_some() => Ok(iterator),
+        other => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("iter() returned non-iterator of type '{}'", other.type_name()),
+        )),
+    }
+}
+
+pub fn iterate<W: Write>(value: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<Iteration, PyError> {
+    match iter(value, span, env, writer)? {
+        Value::Iterator(iterator) => Ok(Iteration::Builtin(iterator)),
+        iterator => Ok(Iteration::Object(iterator)),
+    }
+}
+
+// next(iterator), raising StopIteration once it is exhausted.
+pub fn next<W: Write>(iterator: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<Value, PyError> {
+    if let Value::Generator(generator) = iterator {
+        return generator::resume(generator, Resume::Send(Value::None), span, env, writer);
+    }
+    if let Some(item) = call_special(iterator, "__next__", Vec::new(), span, env, writer)? {
+        return Ok(item);
+    }
+    let Value::Iterator(iterator) = iterator else {
+        return Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("'{}' object is not an iterator", iterator.type_name()),
+        ));
+    };
+    let item = next_builtin(iterator, span, env, writer)?;
+    item.ok_or_else(|| PyError::new(ErrorKind::StopIteration, ""))
+}
+
+// Everything an iterable produces, as unpacking and list() collect it.
+pub fn items<W: Write>(value: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<Vec<Value>, PyError> {
+    if !is_instance(value) && !matches!(value, Value::Generator(_)) {
+        return sequence_items(value);
+    }
+    let iterator = iterate(value, span, env, writer)?;
+    let mut items = Vec::new();
+    while let Some(item) = iterator.next(span, env, writer)? {
+        items.push(item);
+    }
+    Ok(items)
+}
+
+// Calls an instance through its class's `__call__`.
+pub fn call<W: Write>(
+    value: &Value,
+    args: Vec<Value>,
+    kwargs: Vec<(String, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Value, PyError> {
+    let method = match value {
+        Value::Instance(instance) => instance.class.lookup("__call__"),
+        _ => None,
+    };
+    match method {
+        Some(Value::Function(function)) => call_method(&function, value, args, kwargs, span, env, writer),
+        Some(other) => call_function(&other, args, kwargs, span, env, writer),
+        None => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("'{}' object is not callable", value.type_name()),
+        )
+        .at(span)),
+    }
+}
+
+// repr(value): `__repr__` for instances, also for those inside containers.
+pub fn repr<W: Write>(value: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<String, PyError> {
+    match call_special(value, "__repr__", Vec::new(), span, env, writer)? {
+        Some(Value::Str(text)) => Ok(text),
+        Some(other) => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("__repr__ returned non-string (type {})", other.type_name()),
+        )
+        .at(span)),
+        None if exception::is_exception(value) => exception::repr(value, span, env, writer),
+        None => value.repr_with(&mut |item| repr(item, span, env, writer)),
+    }
+}
+
+// str(value), as print writes it: `__str__`, falling back to the repr.
+pub fn to_str<W: Write>(value: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<String, PyError> {
+    // object's `__str__` is the repr, so its result is checked as `__str__`'s.
+    let result = match call_special(value, "__str__", Vec::new(), span, env, writer)? {
+        None if exception::is_exception(value) => return exception::to_str(value, span, env, writer),
+        None => call_special(value, "__repr__", Vec::new(), span, env, writer)?,
+        result => result,
+    };
+    match result {
+        Some(Value::Str(text)) => Ok(text),
+        Some(other) => Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("__str__ returned non-string (type {})", other.type_name()),
+        )
+        .at(span)),
+        None if is_instance(value) || is_container(value) => repr(value, span, env, writer),
+        None => Ok(value.to_string()),
+    }
+}
+
+// Containers, whose str is the repr of their items.
+fn is_container(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::List(_) | Value::Tuple(_) | Value::Dict(_) | Value::DictView(..) | Value::Set(_) | Value::FrozenSet(_)
+    )
+}
+
+// format(value, spec) and f-string fields, after any `!r`, `!s` or `!a`
+// conversion. Instances format through `__format__`, or else as their str
+// when the spec is empty.
+pub fn format<W: Write>(
+    value: &Value,
+    conversion: Option<char>,
+    spec: &str,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<String, PyError> {
+    let value = match conversion {
+        Some('r') => Value::Str(repr(value, span, env, writer)?),
+        Some('s') => Value::Str(to_str(value, span, env, writer)?),
+        Some(_) => Value::Str(escape_ascii(&repr(value, span, env, writer)?)),
+        None => value.clone(),
+    };
+    match call_special(&value, "__format__", vec![Value::Str(spec.to_string())], span, env, writer)? {
+        Some(Value::Str(text)) => return Ok(text),
+        Some(other) => {
+            return Err(PyError::new(
+                ErrorKind::TypeError,
+                format!("__format__ must return a str, not {}", other.type_name()),
+            )
+            .at(span))
+        }
+        None => {}
+    }
+    if is_instance(&value) && !spec.is_empty() {
+        return Err(PyError::new(
+            ErrorKind::TypeError,
+            format!("unsupported format string passed to {}.__format__", value.type_name()),
+        )
+        .at(span));
+    }
+    if spec.is_empty() && (is_instance(&value) || is_container(&value)) {
+        return to_str(&value, span, env, writer);
+    }
+    format_value(&value, spec).map_err(|e| e.at(span))
+}
+
+// hash(value): what `__hash__` returns, for an instance whose class defines it.
+pub fn hash<W: Write>(value: &Value, span: Span, env: &mut Environment, writer: &mut W) -> Result<i64, PyError> {
+    if let Value::Instance(instance) = value {
+        if let Some(Value::Function(method)) = instance.class.lookup("__hash__") {
+            // A result that fits in a machine word is the hash itself (save
+            // -1, which CPython reserves for errors); larger ones are hashed.
+            return match call_method(&method, value, Vec::new(), Vec::new(), span, env, writer)? {
+                Value::Int(-1) => Ok(-2),
+                Value::Int(n) => Ok(n),
+                Value::Bool(b) => Ok(b as i64),
+                result if result.is_int() => hash_value(&result),
+                _ => Err(PyError::new(ErrorKind::TypeError, "__hash__ method should return an integer").at(span)),
+            };
+        }
+    }
+    if let Value::Tuple(items) = value {
+        if holds_instances(value) {
+            return operators::hash_tuple(items, |item| hash(item, span, env, writer));
+        }
+    }
+    hash_value(value).map_err(|e| e.at(span))
+}
+
+// Finds the dict key or set item equal to `key` among the candidates stored
+// under its hash, comparing with `__eq__`. A comparison may change the
+// container, so if the entry found has moved meanwhile the search starts
+// over, as CPython's does.
+fn find_key<W: Write>(
+    key: &Value,
+    hash: i64,
+    candidates: &dyn Fn(i64) -> Vec<(usize, Value)>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<usize>, PyError> {
+    'search: loop {
+        for (position, candidate) in candidates(hash) {
+            if equal(&candidate, key, span, env, writer)? {
+                let unmoved = candidates(hash)
+                    .iter()
+                    .any(|(p, existing)| *p == position && operators::values_identical(existing, &candidate));
+                if !unmoved {
+                    continue 'search;
+                }
+                return Ok(Some(position));
+            }
+        }
+        return Ok(None);
+    }
+}
+
+// dict[key], or None when the key is missing.
+pub fn dict_get<W: Write>(
+    dict: &RefCell<Dict>,
+    key: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<Value>, PyError> {
+    let hash = hash(key, span, env, writer)?;
+    let found = find_key(key, hash, &|hash| dict.borrow().candidates(hash), span, env, writer)?;
+    Ok(found.map(|position| dict.borrow().value_at(position)))
+}
+
+// dict[key] = value. Replacing a value keeps the original key object.
+pub fn dict_insert<W: Write>(
+    dict: &RefCell<Dict>,
+    key: Value,
+    value: Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<(), PyError> {
+    let hash = hash(&key, span, env, writer)?;
+    match find_key(&key, hash, &|hash| dict.borrow().candidates(hash), span, env, writer)? {
+        Some(position) => dict.borrow_mut().replace_at(position, value),
+        None => dict.borrow_mut().push(hash, key, value),
+    }
+    Ok(())
+}
+
+// Removes a key, giving its value, or None when the key is missing.
+pub fn dict_remove<W: Write>(
+    dict: &RefCell<Dict>,
+    key: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Option<Value>, PyError> {
+    let hash = hash(key, span, env, writer)?;
+    let found = find_key(key, hash, &|hash| dict.borrow().candidates(hash), span, env, writer)?;
+    Ok(found.map(|position| dict.borrow_mut().remove_at(position)))
+}
+
+// Whether a set or frozenset holds an item equal to `key`.
+fn set_contains<W: Write>(
+    set: &Value,
+    key: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<bool, PyError> {
+    let hash = hash(key, span, env, writer)?;
+    let found = match set {
+        Value::Set(set) => find_key(key, hash, &|hash| set.borrow().candidates(hash), span, env, writer)?,
+        Value::FrozenSet(set) => find_key(key, hash, &|hash| set.candidates(hash), span, env, writer)?,
+        _ => unreachable!("not a set: {}", set.type_name()),
+    };
+    Ok(found.is_some())
+}
+
+pub fn set_add<W: Write>(
+    set: &RefCell<Set>,
+    key: Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<(), PyError> {
+    let hash = hash(&key, span, env, writer)?;
+    if find_key(&key, hash, &|hash| set.borrow().candidates(hash), span, env, writer)?.is_none() {
+        set.borrow_mut().place(key, hash);
+    }
+    Ok(())
+}
+
+// Removes an item, giving whether it was present.
+pub fn set_discard<W: Write>(
+    set: &RefCell<Set>,
+    key: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<bool, PyError> {
+    let hash = hash(key, span, env, writer)?;
+    let found = find_key(key, hash, &|hash| set.borrow().candidates(hash), span, env, writer)?;
+    if let Some(position) = found {
+        set.borrow_mut().remove_at(position);
+    }
+    Ok(found.is_some())
+}
+
+// A new set holding the given items, added in order.
+pub fn new_set<W: Write>(
+    items: Vec<Value>,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Set, PyError> {
+    let set = RefCell::new(Set::new());
+    for item in items {
+        set_add(&set, item, span, env, writer)?;
+    }
+    Ok(set.into_inner())
+}
+
+// `| & - ^` on sets holding instances, whose items compare with `__eq__`.
+fn set_operation<W: Write>(
+    operator: &str,
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<Set, PyError> {
+    let (l, r) = (sequence_items(left)?, sequence_items(right)?);
+    let result = RefCell::new(Set::new());
+    match operator {
+        "|" => {
+            for item in l.into_iter().chain(r) {
+                set_add(&result, item, span, env, writer)?;
+            }
+        }
+        "&" | "-" => {
+            for item in l {
+                if set_contains(right, &item, span, env, writer)? == (operator == "&") {
+                    set_add(&result, item, span, env, writer)?;
+                }
+            }
+        }
+        _ => {
+            for item in r {
+                set_add(&result, item, span, env, writer)?;
+            }
+            for item in l {
+                if !set_discard(&result, &item, span, env, writer)? {
+                    set_add(&result, item, span, env, writer)?;
+                }
+            }
+        }
+    }
+    Ok(result.into_inner())
+}
+
+// Whether every item of one set is in another, comparing with `__eq__`.
+fn is_subset<W: Write>(
+    left: &Value,
+    right: &Value,
+    span: Span,
+    env: &mut Environment,
+    writer: &mut W,
+) -> Result<bool, PyError> {
+    for item in sequence_items(left)? {
+        if !set_contains(right, &item, span, env, writer)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
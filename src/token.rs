@@ -1,33 +1,97 @@
+use crate::bigint::BigInt;
+use crate::error::Span;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Identifier(String),
     Number(i64),
+    BigNumber(BigInt),
     Float(f64),
     StringLiteral(String), // new token for strings
+    Bytes(Vec<u8>),
+    FString(Vec<FStringPart>),
+    True,
+    False,
+    None,
     Equals,
+    AugmentedAssign(String), // `+=`, `//=`, `<<=` and the like
     Minus,
     Plus,
     Star,
     Slash,
     DoubleSlash,
+    Percent,
     DoubleStar,
+    Pipe,
+    Ampersand,
+    Caret,
+    Tilde,
+    LeftShift,
+    RightShift,
     Greater,
     Less,
+    GreaterEqual,
+    LessEqual,
+    EqualEqual,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    Is,
     Comma,
     Print,
     Def,
+    Class,
+    Pass,
     If,
+    Elif,
     Else,
     Return,
+    Break,
+    Continue,
     For,
     While,
     In,
-    Range,
+    Import,
+    Del,
+    Try,
+    Except,
+    Finally,
+    Raise,
+    From,
+    Yield,
+    Lambda,
+    Global,
+    Nonlocal,
+    As,
     OpenParen,
     CloseParen,
+    OpenBracket,
+    CloseBracket,
+    OpenBrace,
+    CloseBrace,
+    Dot,
     Colon,
     Newline,
     Indent,
     Dedent,
-    Unknown(char),
+}
+
+// A piece of an f-string: literal text, or a replacement field holding the
+// already-lexed tokens of its expression.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FStringPart {
+    Literal(String),
+    Field {
+        tokens: Vec<SpannedToken>,
+        conversion: Option<char>, // 'r', 's' or 'a' after a '!'
+        spec: Vec<FStringPart>,   // the format spec, which may hold fields itself
+    },
+}
+
+// A token together with where it appeared in the source.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
 }